@@ -1,4 +1,5 @@
 use mini_socket::tcp_listen_config::TcpListenConfig;
+use mini_socket::tcp_socket_msg::MAX_VERSION;
 use mini_utils::wconfig::WConfig;
 
 #[derive(Debug, Clone)]
@@ -6,6 +7,22 @@ pub struct Config {
     pub wconfig: WConfig,
     pub wan_listen_config: TcpListenConfig,
     pub lan_listen_config: TcpListenConfig,
+    /// default:0
+    /// 支持的最小客户端协议版本 AuthRequest 的 MsgData.ext 第3~12位
+    pub client_version_min: u16,
+    /// default:MAX_VERSION
+    /// 支持的最大客户端协议版本
+    pub client_version_max: u16,
+    /// default:0 不启用
+    /// 单位:毫秒 认证通过的客户端断线后 在这个时长内可以用 SessionToken 重连
+    /// 宽限期内服务不会收到断线 发给用户的消息保存起来 重连后补发
+    pub resume_grace: u64,
+    /// default:None 不启用
+    /// 客户端协议版本不小于这个值才启用断线重连 旧客户端不会收到 SessionToken
+    pub resume_version_min: Option<u16>,
+    /// default:1024
+    /// 每个用户保存的没有确认的消息的最大条数 超过时丢弃最早的 之后不能重连
+    pub resume_buffer_size: usize,
 }
 
 impl Config {
@@ -14,14 +31,26 @@ impl Config {
             wconfig: WConfig::new(),
             wan_listen_config: TcpListenConfig::new(),
             lan_listen_config: TcpListenConfig::new(),
+            client_version_min: 0,
+            client_version_max: MAX_VERSION,
+            resume_grace: 0,
+            resume_version_min: None,
+            resume_buffer_size: 1024,
         }
     }
 
     pub fn read_config(&mut self, _path: &String) -> Result<(), String> {
         self.wan_listen_config
-            .set_bind_socket_addr(&"0.0.0.0:9999".into());
+            .set_bind_socket_addr("0.0.0.0:9999")
+            .set_msg_deque_mark(192, 64)
+            .set_high_mark_timeout(30000)
+            .set_pause_read_on_full(true)
+            .set_close_on_queue_full(true)
+            .set_compress_threshold(512);
         self.lan_listen_config
-            .set_bind_socket_addr(&"0.0.0.0:6666".into());
+            .set_bind_socket_addr("0.0.0.0:6666")
+            .set_pause_read_on_full(true)
+            .set_chunk_max_size(16 * 1024 * 1024);
         Ok(())
     }
 }
@@ -0,0 +1,112 @@
+/// 组(房间,频道)成员 由服务通过系统协议维护
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+pub struct GroupRoute {
+    /// 组名 组内用户Id
+    group_uid: HashMap<String, HashSet<u64>>,
+    /// 用户Id 加入的所有组名
+    uid_group: HashMap<u64, HashSet<String>>,
+}
+
+impl GroupRoute {
+    pub fn new() -> Self {
+        GroupRoute {
+            group_uid: HashMap::new(),
+            uid_group: HashMap::new(),
+        }
+    }
+
+    /// 用户加入组 组不存在时创建
+    /// 返回false: 用户已在组内
+    pub fn join(&mut self, name: &str, uid: u64) -> bool {
+        let is_new = self
+            .group_uid
+            .entry(name.to_string())
+            .or_default()
+            .insert(uid);
+        if is_new {
+            self.uid_group
+                .entry(uid)
+                .or_default()
+                .insert(name.to_string());
+        }
+        is_new
+    }
+
+    /// 用户离开组 组内没有用户时删除组
+    /// 返回false: 用户不在组内
+    pub fn leave(&mut self, name: &str, uid: u64) -> bool {
+        let is_del = match self.group_uid.get_mut(name) {
+            Some(set_uid) => {
+                let is_del = set_uid.remove(&uid);
+                if set_uid.is_empty() {
+                    self.group_uid.remove(name);
+                }
+                is_del
+            }
+            None => false,
+        };
+        if is_del {
+            if let Some(set_name) = self.uid_group.get_mut(&uid) {
+                set_name.remove(name);
+                if set_name.is_empty() {
+                    self.uid_group.remove(&uid);
+                }
+            }
+        }
+        is_del
+    }
+
+    /// 用户断线时 离开加入的所有组
+    pub fn leave_all(&mut self, uid: u64) {
+        if let Some(set_name) = self.uid_group.remove(&uid) {
+            for name in set_name.iter() {
+                if let Some(set_uid) = self.group_uid.get_mut(name) {
+                    set_uid.remove(&uid);
+                    if set_uid.is_empty() {
+                        self.group_uid.remove(name);
+                    }
+                }
+            }
+        }
+    }
+
+    /// 组内所有用户Id
+    #[inline]
+    pub fn get_group(&self, name: &str) -> Option<&HashSet<u64>> {
+        self.group_uid.get(name)
+    }
+
+    /// 组内用户数量
+    #[inline]
+    pub fn group_size(&self, name: &str) -> usize {
+        self.group_uid.get(name).map_or(0, |set_uid| set_uid.len())
+    }
+
+    /// 组的数量
+    #[inline]
+    pub fn group_count(&self) -> usize {
+        self.group_uid.len()
+    }
+}
+
+#[test]
+fn test() {
+    let mut group_route = GroupRoute::new();
+
+    assert!(group_route.join("room_1", 1));
+    assert!(!group_route.join("room_1", 1));
+    assert!(group_route.join("room_1", 2));
+    assert!(group_route.join("room_2", 1));
+    assert_eq!(group_route.group_count(), 2);
+    assert_eq!(group_route.group_size("room_1"), 2);
+
+    assert!(group_route.leave("room_1", 2));
+    assert!(!group_route.leave("room_1", 2));
+    assert_eq!(group_route.group_size("room_1"), 1);
+
+    group_route.leave_all(1);
+    assert_eq!(group_route.group_count(), 0);
+    assert!(group_route.get_group("room_1").is_none());
+}
@@ -1,4 +1,5 @@
 use crate::lan_tcp_rw::LanTcpRw;
+use mini_socket::error::Error;
 use mini_socket::tcp_socket_msg::{SrvMsg,MsgData,SProtoId};
 use mini_socket::tcp_listen_config::TcpListenConfig;
 use mini_socket::tcp_listen_service::TcpListenService;
@@ -70,19 +71,27 @@ fn worker_closure(
         move |receiver: Receiver<SrvMsg>, sender: SyncSender<SrvMsg>| {
             //-----------------------------------------------------------------------------
             let mut net_msg_cb_fn = |sid: u64, vec_msg: Vec<MsgData>| {
-                for msg in vec_msg {
+                let mut iter = vec_msg.into_iter();
+                while let Some(msg) = iter.next() {
                     match sender.try_send(SrvMsg::new(sid, msg)) {
                         Ok(_) => {}
-                        Err(TrySendError::Full(_)) => {
+                        Err(TrySendError::Full(srv_msg)) => {
                             error!("LanService try_send Full");
+                            let mut vec_rest = vec![srv_msg.msg];
+                            vec_rest.extend(iter);
+                            return vec_rest;
                         }
                         Err(TrySendError::Disconnected(_)) => {
                             error!("LanService try_send Disconnected");
                         }
                     };
                 }
+                vec![]
             };
-            let mut msg_kind_cb_fn = |sid: u64, spid: SProtoId| {
+            let mut msg_kind_cb_fn = |sid: u64, spid: SProtoId, err: Option<&Error>| {
+                if let Some(err) = err {
+                    error!("LanService sid:{} disconnect:{}", sid, err);
+                }
                 match sender.try_send(SrvMsg::new(sid, MsgData::new_pid(spid as u16))) {
                     Ok(_) => {}
                     Err(TrySendError::Full(_)) => {
@@ -107,9 +116,8 @@ fn worker_closure(
             }
 
             //-----------------------------------------------------------------------------
-            let wait_timeout = 1;
+            let wait_timeout = tcp_listen_config.epoll_wait_timeout;
             loop {
-                tcp_listen_service.tick();
                 loop {
                     match tcp_listen_service.epoll_event(wait_timeout) {
                         Ok(0) => {
@@ -9,7 +9,9 @@ use std::time::Duration;
 mod config;
 mod lan_service;
 mod lan_tcp_rw;
+mod group_route;
 mod mucid_route;
+mod session_route;
 mod wan_service;
 mod wan_tcp_rw;
 mod service;
@@ -6,6 +6,8 @@ pub struct MucIdRoute {
     cid_uid: HashMap<u64, u64>,
     /// 用户Id 转 连接id
     uid_cid: HashMap<u64, u64>,
+    /// 连接id 客户端协议版本
+    cid_version: HashMap<u64, u16>,
 
     /// 可以优化改成数组
     /// mid(协议id) sid(服务id)
@@ -17,14 +19,29 @@ impl MucIdRoute {
         MucIdRoute {
             cid_uid: HashMap::new(),
             uid_cid: HashMap::new(),
+            cid_version: HashMap::new(),
             mid_sid: HashMap::new(),
         }
     }
 
     /// 增加 连接id uid=0
+    /// version: AuthRequest 中的客户端协议版本
     #[inline]
-    pub fn add_cid(&mut self, cid: u64) {
+    pub fn add_cid(&mut self, cid: u64, version: u16) {
         self.cid_uid.insert(cid, 0);
+        self.cid_version.insert(cid, version);
+    }
+
+    /// 连接的客户端协议版本 没有记录时为0
+    #[inline]
+    pub fn get_version(&self, cid: u64) -> u16 {
+        self.cid_version.get(&cid).copied().unwrap_or(0)
+    }
+
+    /// 连接断开 删除客户端协议版本
+    #[inline]
+    pub fn del_version(&mut self, cid: u64) {
+        self.cid_version.remove(&cid);
     }
     
     /// 增加 连接id 与 用户Id
@@ -46,6 +63,12 @@ impl MucIdRoute {
         self.uid_cid.get(&uid)
     }
 
+    /// 所有已认证用户的连接id
+    #[inline]
+    pub fn vec_auth_cid(&self) -> Vec<u64> {
+        self.uid_cid.values().copied().collect()
+    }
+
     /// 删除用户Id,连接id
     #[inline]
     pub fn del_uid_cid(&mut self, uid: u64) -> bool {
@@ -59,11 +82,14 @@ impl MucIdRoute {
     }
 
     /// 删除连接id,用户Id
+    /// 用户已在新的连接登录时 保留用户Id到新连接的映射
     #[inline]
     pub fn del_cid_data(&mut self, cid: u64) -> bool {
         match self.cid_uid.remove(&cid) {
             Some(uid) => {
-                self.uid_cid.remove(&uid);
+                if self.uid_cid.get(&uid) == Some(&cid) {
+                    self.uid_cid.remove(&uid);
+                }
                 true
             }
             None => false,
@@ -127,6 +153,14 @@ fn test(){
 
     mucid_route.del_sid(3);
 
+    // 用户在新连接登录后 旧连接断开不影响新连接
+    mucid_route.add_cid_uid(100, 7);
+    mucid_route.add_cid_uid(101, 7);
+    assert!(mucid_route.del_cid_data(100));
+    assert_eq!(mucid_route.uid_to_cid(7), Some(&101));
+    assert!(mucid_route.del_cid_data(101));
+    assert!(mucid_route.uid_to_cid(7).is_none());
+
     /*
     for (key, vec_sid) in mucid_route.mid_sid.iter() {
         for sid in vec_sid.iter(){
@@ -1,25 +1,36 @@
 use crate::config::Config;
 use crate::lan_service::LanService;
+use crate::group_route::GroupRoute;
 use crate::mucid_route::MucIdRoute;
-use mini_socket::tcp_socket_msg::{SrvMsg, MsgData, SProtoId};
+use crate::session_route::{SessionRoute, TOKEN_SIZE};
+use mini_socket::tcp_socket_msg::{SrvMsg, MsgBuf, MsgData, MulticastData, PublishGroupData, SProtoId, DisReason};
 
 use crate::wan_service::WanService;
 use log::{error,warn,debug};
 use mini_utils::bytes;
+use mini_utils::time;
+use std::collections::HashSet;
 use std::thread;
 use std::time::Duration;
 
 /// 用于把 广域网的数据 转到 局域网服务中
 pub struct Service {
     mucid_route: MucIdRoute,
+    group_route: GroupRoute,
+    session_route: SessionRoute,
     wan_service: WanService,
     lan_service: LanService,
     single_max_task_num: u16,
     sleep_duration: Duration,
+    /// 支持的客户端协议版本 (最小, 最大)
+    client_version: (u16, u16),
+    /// 客户端协议版本不小于这个值才启用断线重连 None:不启用
+    resume_version_min: Option<u16>,
 }
 
 impl Drop for Service {
     fn drop(&mut self) {
+        self.shutdown();
         if thread::panicking() {
             error!("dropped mini_proxy Service while unwinding");
         } else {
@@ -42,6 +53,10 @@ impl Service {
             sleep_duration,
             single_max_task_num,
             mucid_route: MucIdRoute::new(),
+            group_route: GroupRoute::new(),
+            session_route: SessionRoute::new(config.resume_grace, config.resume_buffer_size),
+            client_version: (config.client_version_min, config.client_version_max),
+            resume_version_min: config.resume_version_min,
         })
     }
 
@@ -54,6 +69,7 @@ impl Service {
             if !self.lan_receiver() {
                 is_sleep = false;
             }
+            self.session_expire();
             if is_sleep {
                 thread::sleep(self.sleep_duration);
             }
@@ -69,9 +85,10 @@ impl Service {
                 Some(msg_data) => {
                     if SProtoId::exists(msg_data.pid){
                         self.wan_sproto_id(SProtoId::new(msg_data.pid), msg_data);
+                    }else if self.mucid_route.get_vec_sid(msg_data.pid).is_some(){
+                        self.sender_lan(msg_data);
                     }else{
-                        //self.sender_lan(msg_data);
-                        // todo test code
+                        // todo test code 没有服务处理的协议原样返回
                         self.wan_service.sender(msg_data);
                     }
                 }
@@ -94,10 +111,13 @@ impl Service {
                     if SProtoId::exists(srv_msg.msg.pid){
                         self.lan_sproto_id(SProtoId::new(srv_msg.msg.pid), srv_msg);
                     }else{
-                        if let Some(cid) = self.mucid_route.uid_to_cid(srv_msg.msg.uid){
+                        let uid = srv_msg.msg.uid;
+                        // 断线重连宽限期内的用户 消息保存在会话中
+                        let is_session = self.session_route.record(uid, &mut srv_msg.msg);
+                        if let Some(cid) = self.mucid_route.uid_to_cid(uid){
                             self.wan_service.sender({srv_msg.msg.uid = *cid; srv_msg.msg});
-                        }else{
-                            debug!("uid_to_cid unknown uid:{}", srv_msg.msg.uid)
+                        }else if !is_session{
+                            debug!("uid_to_cid unknown uid:{}", uid)
                         }
                     }
                    
@@ -110,7 +130,9 @@ impl Service {
         }
     }
 
+    /// 消息带上连接的客户端协议版本 服务可以同时支持多个版本
     fn sender_lan(&self, mut msg_data: MsgData) {
+        msg_data.set_version(self.mucid_route.get_version(msg_data.uid));
         match self.mucid_route.cid_to_uid(msg_data.uid){
             Some(&0)=>{
                 warn!("AuthRequest Unfinished cid:{}", msg_data.uid);
@@ -132,6 +154,61 @@ impl Service {
         }
     }
 
+    /// 通知处理 Disconnect 的服务 连接已断开
+    /// MsgData.buf 带上断开原因
+    /// 有会话的用户进入断线重连宽限期 过期后才通知
+    fn disconnect_to_lan(&mut self, cid: u64, reason: DisReason){
+        if let Some(&uid) = self.mucid_route.cid_to_uid(cid){
+            if uid > 0 && self.session_route.detach(uid, cid, reason, time::timestamp()){
+                debug!("cid:{} uid:{} wait resume reason:{:?}", cid, uid, reason);
+            }else{
+                let hash_id = if uid > 0 {
+                    uid  //已认证成功的连接
+                }else{
+                    cid //未认证成功,未认证完成，没有认证的连接
+                };
+                self.disconnect_uid_to_lan(uid, hash_id, self.mucid_route.get_version(cid), reason);
+            }
+            self.mucid_route.del_cid_data(cid);
+            self.mucid_route.del_version(cid);
+        }else{
+            debug!("Disconnect unknown cid:{} reason:{:?}", cid, reason)
+        }
+    }
+
+    fn disconnect_uid_to_lan(&mut self, uid: u64, hash_id: u64, version: u16, reason: DisReason){
+        if uid > 0 {
+            self.group_route.leave_all(uid);
+        }
+        if let Some(sid) = self.mucid_route.get_sid(SProtoId::Disconnect as u16, hash_id){
+            let mut msg = MsgData::new_disconnect(uid, reason);
+            msg.set_version(version);
+            self.lan_service.sender(SrvMsg::new(sid, msg));
+        }else{
+            error!("proto id:{:?} no server handle", SProtoId::Disconnect);
+        }
+    }
+
+    /// 断线重连宽限期已过 通知服务用户断开
+    fn session_expire(&mut self){
+        if !self.session_route.is_enable() {
+            return;
+        }
+        for (uid, version, reason) in self.session_route.expire(time::timestamp()){
+            self.disconnect_uid_to_lan(uid, uid, version, reason);
+        }
+    }
+
+    /// proxy 退出时 通知服务所有已认证的用户断开
+    fn shutdown(&mut self){
+        for cid in self.mucid_route.vec_auth_cid(){
+            self.disconnect_to_lan(cid, DisReason::ProxyShutdown);
+        }
+        for (uid, version) in self.session_route.drain_detached(){
+            self.disconnect_uid_to_lan(uid, uid, version, DisReason::ProxyShutdown);
+        }
+    }
+
     fn  lan_sproto_id(&mut self, spid: SProtoId, mut srv_msg: SrvMsg){
         match spid {
             SProtoId::ServerJoin=> {
@@ -143,12 +220,21 @@ impl Service {
                 self.mucid_route.del_sid(srv_msg.id);
             }
             SProtoId::ExcUserData=> {
-                if let Some(cid) = self.mucid_route.uid_to_cid(srv_msg.msg.uid){
+                self.group_route.leave_all(srv_msg.msg.uid);
+                // 被踢的用户不能重连
+                let is_detached = self.session_route.is_detached(srv_msg.msg.uid);
+                self.session_route.remove(srv_msg.msg.uid);
+                let cid = self.mucid_route.uid_to_cid(srv_msg.msg.uid).copied();
+                if let Some(cid) = cid{
                     //通知客户端数据异常
                     let mut wan_msg = srv_msg.msg.clone();
-                    self.wan_service.sender({wan_msg.uid = *cid; wan_msg});
-                    //通知网络线程断开网络链接
-                    self.wan_service.sender(MsgData::new_uid_pid(*cid, SProtoId::Disconnect as u16));
+                    self.wan_service.sender({wan_msg.uid = cid; wan_msg});
+                    //通知网络线程断开网络链接 关闭时不会再通知 Disconnect
+                    self.wan_service.sender(MsgData::new_disconnect(cid, DisReason::Kick));
+                    self.mucid_route.del_cid_data(cid);
+                    self.mucid_route.del_version(cid);
+                }
+                if cid.is_some() || is_detached {
                     //然后再通知其它服务 用户已断线
                     if let Some(vec_sid) = self.mucid_route.get_vec_sid(spid as u16){
                         for sid in vec_sid.iter(){
@@ -162,6 +248,12 @@ impl Service {
             SProtoId::ServerBusy=> {
                 error!("ServerBusy: uid:{}", srv_msg.msg.uid);
             },
+            SProtoId::QueueHighMark=> {
+                warn!("QueueHighMark: sid:{}", srv_msg.id);
+            },
+            SProtoId::QueueLowMark=> {
+                warn!("QueueLowMark: sid:{}", srv_msg.id);
+            },
             SProtoId::MsgQueueFull=>  {
                 error!("MsgQueueFull: uid:{}", srv_msg.msg.uid);
             },
@@ -183,6 +275,14 @@ impl Service {
                             }else{
                                 //登录验证成功后 把cid uid 保存到mucid_route中
                                 self.mucid_route.add_cid_uid(srv_msg.msg.uid, uid);
+                                //支持断线重连的客户端 发送断线重连的令牌
+                                let version = self.mucid_route.get_version(srv_msg.msg.uid);
+                                //生成令牌失败 这次登录不支持断线重连
+                                if self.is_resume_client(version) {
+                                    if let Some(token_msg) = self.session_route.create(uid, srv_msg.msg.uid, version) {
+                                        self.wan_service.sender(token_msg);
+                                    }
+                                }
                             }
                         }else {
                             error!("repeat recv AuthReqPass uid:{}", uid);
@@ -200,6 +300,97 @@ impl Service {
                 // 超过一定次数直接断开这个连接
                 self.wan_service.sender(srv_msg.msg);
             },
+
+            SProtoId::Multicast=> {
+                match MulticastData::decode(&srv_msg.msg.buf){
+                    Ok(data)=>{
+                        let (vec_cid, vec_fail) = self.route_multicast(&data.vec_id, srv_msg.msg.ext, data.pid, data.payload);
+                        self.multicast_wan(srv_msg.id, srv_msg.msg.ext, data.pid, &vec_cid, data.payload);
+                        self.multicast_fail(srv_msg.id, data.pid, &vec_fail);
+                    }
+                    Err(err)=>{
+                        error!("Multicast sid:{} error:{}", srv_msg.id, err);
+                    }
+                }
+            },
+
+            SProtoId::Broadcast=> {
+                if srv_msg.msg.buf.len() < 2{
+                    error!("Broadcast sid:{} buffer data error", srv_msg.id);
+                    return;
+                }
+                let pid = bytes::read_u16(&srv_msg.msg.buf);
+                let mut vec_cid = self.mucid_route.vec_auth_cid();
+                if self.session_route.is_enable() {
+                    // 有会话的用户 消息带各自的序号 单独发送
+                    let msg = MsgData{uid: 0, pid, ext: srv_msg.msg.ext, buf: MsgBuf::shared(srv_msg.msg.buf[2..].to_vec())};
+                    let vec_msg = self.session_route.record_all(&msg);
+                    let set_cid: HashSet<u64> = vec_msg.iter().map(|msg| msg.uid).collect();
+                    vec_cid.retain(|cid| !set_cid.contains(cid));
+                    for msg in vec_msg {
+                        self.wan_service.sender(msg);
+                    }
+                }
+                self.multicast_wan(srv_msg.id, srv_msg.msg.ext, pid, &vec_cid, &srv_msg.msg.buf[2..]);
+            },
+
+            SProtoId::JoinGroup=> {
+                match std::str::from_utf8(&srv_msg.msg.buf){
+                    Ok(name)=>{
+                        // 只有在线或断线重连宽限期内的用户能加入 否则断线时不会离开组
+                        let uid = srv_msg.msg.uid;
+                        if uid > 0 && (self.mucid_route.uid_to_cid(uid).is_some() || self.session_route.is_detached(uid)) {
+                            self.group_route.join(name, uid);
+                        }else{
+                            warn!("JoinGroup sid:{} group:{} offline uid:{}", srv_msg.id, name, uid);
+                        }
+                    }
+                    Err(err)=>{
+                        error!("JoinGroup sid:{} group name error:{}", srv_msg.id, err);
+                    }
+                }
+            },
+
+            SProtoId::LeaveGroup=> {
+                match std::str::from_utf8(&srv_msg.msg.buf){
+                    Ok(name)=>{
+                        self.group_route.leave(name, srv_msg.msg.uid);
+                    }
+                    Err(err)=>{
+                        error!("LeaveGroup sid:{} group name error:{}", srv_msg.id, err);
+                    }
+                }
+            },
+
+            SProtoId::PublishGroup=> {
+                match PublishGroupData::decode(&srv_msg.msg.buf){
+                    Ok(data)=>{
+                        let vec_uid: Vec<u64> = match self.group_route.get_group(data.name){
+                            Some(set_uid)=> set_uid.iter().copied().collect(),
+                            None=> vec![],
+                        };
+                        let (vec_cid, vec_fail) = self.route_multicast(&vec_uid, srv_msg.msg.ext, data.pid, data.payload);
+                        self.multicast_wan(srv_msg.id, srv_msg.msg.ext, data.pid, &vec_cid, data.payload);
+                        self.multicast_fail(srv_msg.id, data.pid, &vec_fail);
+                    }
+                    Err(err)=>{
+                        error!("PublishGroup sid:{} error:{}", srv_msg.id, err);
+                    }
+                }
+            },
+
+            SProtoId::GroupInfo=> {
+                let name = String::from_utf8_lossy(&srv_msg.msg.buf).into_owned();
+                let group_count = self.group_route.group_count() as u32;
+                let group_size = self.group_route.group_size(&name) as u32;
+                let mut buf = Vec::with_capacity(8 + name.len());
+                buf.extend_from_slice(&group_count.to_le_bytes());
+                buf.extend_from_slice(&group_size.to_le_bytes());
+                buf.extend_from_slice(name.as_bytes());
+                let mut msg = MsgData::new_pid(SProtoId::GroupInfo as u16);
+                msg.buf = buf.into();
+                self.lan_service.sender(SrvMsg::new(srv_msg.id, msg));
+            },
             
             _=> {error!("unknown SProtoId:{:?}", srv_msg.msg.pid)},
         }
@@ -208,29 +399,16 @@ impl Service {
     fn wan_sproto_id(&mut self, spid: SProtoId, msg: MsgData){
         match spid {
             SProtoId::Disconnect=> {
-                if let Some(uid) = self.mucid_route.cid_to_uid(msg.uid){
-                    let hash_id = if *uid > 0 {
-                        *uid  //已认证成功的连接
-                    }else{
-                        msg.uid //未认证成功,未认证完成，没有认证的连接
-                    };
-                    if let Some(sid) = self.mucid_route.get_sid(msg.pid, hash_id){
-
-                        let msg = MsgData::new_uid_pid(*uid, msg.pid);
-
-                        self.lan_service.sender(SrvMsg::new(sid, msg));
-
-                    }else{
-                        error!("proto id:{:?} no server handle", spid);
-                    }
-                }else{
-                    debug!("Disconnect unknown cid:{}", msg.uid)
-                }
+                self.disconnect_to_lan(msg.uid, msg.get_dis_reason());
             }
             SProtoId::AuthRequest=> {
+                let version = msg.get_version();
+                if !self.check_version(msg.uid, version) {
+                    return;
+                }
                 match self.mucid_route.get_sid(spid as u16, msg.uid){
                     Some(sid)=>{
-                        self.mucid_route.add_cid(msg.uid);
+                        self.mucid_route.add_cid(msg.uid, version);
                         self.lan_service.sender(SrvMsg::new(sid, msg));
                     }
                     None=>{
@@ -238,21 +416,287 @@ impl Service {
                     }
                 }
             }
+            SProtoId::SessionResume=> {
+                let (cid, version) = (msg.uid, msg.get_version());
+                if !self.check_version(cid, version) {
+                    return;
+                }
+                if msg.buf.len() < TOKEN_SIZE + 8 || self.mucid_route.cid_to_uid(cid).is_some() {
+                    warn!("cid:{} SessionResume data error", cid);
+                    self.wan_service.sender(MsgData::new_uid_pid(cid, SProtoId::AuthNotPass as u16));
+                    return;
+                }
+                let recv_seq = Self::read_seq(&msg.buf[TOKEN_SIZE..]);
+                match self.session_route.resume(&msg.buf[..TOKEN_SIZE], recv_seq, cid, version){
+                    Ok(data)=>{
+                        if let Some(old_cid) = data.old_cid {
+                            //旧连接还没有检测到断开
+                            self.mucid_route.del_cid_data(old_cid);
+                            self.mucid_route.del_version(old_cid);
+                            self.wan_service.sender(MsgData::new_disconnect(old_cid, DisReason::Kick));
+                        }
+                        self.mucid_route.add_cid(cid, version);
+                        self.mucid_route.add_cid_uid(cid, data.uid);
+                        self.wan_service.sender(data.token_msg);
+                        for msg in data.vec_msg {
+                            self.wan_service.sender(msg);
+                        }
+                    }
+                    Err(err)=>{
+                        warn!("cid:{} SessionResume error:{}", cid, err);
+                        self.wan_service.sender(MsgData::new_uid_pid(cid, SProtoId::AuthNotPass as u16));
+                    }
+                }
+            }
+            SProtoId::SessionAck=> {
+                match self.mucid_route.cid_to_uid(msg.uid){
+                    Some(&uid) if uid > 0 && msg.buf.len() >= 8=>{
+                        self.session_route.ack(uid, Self::read_seq(&msg.buf));
+                    }
+                    _=>{
+                        debug!("SessionAck unauthorized cid:{}", msg.uid)
+                    }
+                }
+            }
+            SProtoId::QueueHighMark | SProtoId::QueueLowMark | SProtoId::ExcUserData=> {
+                // 通知处理这条协议的服务 可以暂停或恢复给这个用户推送消息
+                // ExcUserData: 解密失败 之后会收到 Disconnect
+                match self.mucid_route.cid_to_uid(msg.uid){
+                    Some(&uid) if uid > 0=>{
+                        if let Some(sid) = self.mucid_route.get_sid(msg.pid, uid){
+                            self.lan_service.sender(SrvMsg::new(sid, MsgData::new_uid_pid(uid, msg.pid)));
+                        }
+                    }
+                    _=>{
+                        debug!("{:?} unauthorized cid:{}", spid, msg.uid)
+                    }
+                }
+            }
+            SProtoId::MulticastFail=> {
+                // msg.uid(服务id) 连接id 转成 用户Id
+                match MulticastData::decode(&msg.buf){
+                    Ok(data)=>{
+                        let vec_uid: Vec<u64> = data.vec_id.iter()
+                            .filter_map(|cid| self.mucid_route.cid_to_uid(*cid))
+                            .filter(|uid| **uid != 0)
+                            .copied()
+                            .collect();
+                        self.multicast_fail(msg.uid, data.pid, &vec_uid);
+                    }
+                    Err(err)=>{
+                        error!("MulticastFail sid:{} error:{}", msg.uid, err);
+                    }
+                }
+            }
             _=> {error!("unknown SProtoId:{:?}", spid)},
         }
     }
 
-    fn get_sid_proto(buf: &Vec<u8>)->Vec<u16>{
-        let mut pos;
-        let mut end_pos = 2;
-        let buf_size = buf.len();
-        let mut vec_u16 = Vec::with_capacity(buf_size / 2);
+    /// 客户端协议版本不支持时 通知客户端并断开
+    fn check_version(&self, cid: u64, version: u16) -> bool {
+        let (min, max) = self.client_version;
+        if version < min || version > max {
+            warn!("cid:{} client version:{} not support min:{} max:{}", cid, version, min, max);
+            self.wan_service.sender(MsgData::new_version_not_support(cid, min, max));
+            self.wan_service.sender(MsgData::new_disconnect(cid, DisReason::Protocol));
+            return false;
+        }
+        true
+    }
+
+    /// 启用断线重连 并且客户端协议版本支持
+    fn is_resume_client(&self, version: u16) -> bool {
+        self.session_route.is_enable() && matches!(self.resume_version_min, Some(min) if version >= min)
+    }
+
+    /// SessionResume SessionAck 中的消息序号
+    fn read_seq(buf: &[u8]) -> u64 {
+        let mut seq = [0u8; 8];
+        seq.copy_from_slice(&buf[..8]);
+        u64::from_le_bytes(seq)
+    }
+
+    /// 用户Id 转 连接id 返回 (连接id列表, 发送失败的用户Id列表)
+    /// 启用断线重连时 消息保存到用户的会话 带各自的序号单独发送 宽限期内的用户不算发送失败
+    fn route_multicast(&mut self, vec_uid: &[u64], ext: u32, pid: u16, payload: &[u8]) -> (Vec<u64>, Vec<u64>){
+        let msg = if self.session_route.is_enable() {
+            Some(MsgData{uid: 0, pid, ext, buf: MsgBuf::shared(payload.to_vec())})
+        }else{
+            None
+        };
+        let mut vec_cid = Vec::with_capacity(vec_uid.len());
+        let mut vec_fail = vec![];
+        for uid in vec_uid.iter(){
+            let cid = self.mucid_route.uid_to_cid(*uid).copied();
+            if let Some(msg) = msg.as_ref() {
+                let mut msg = msg.clone();
+                if self.session_route.record(*uid, &mut msg) {
+                    if let Some(cid) = cid {
+                        self.wan_service.sender({msg.uid = cid; msg});
+                    }
+                    continue;
+                }
+            }
+            match cid {
+                Some(cid)=> vec_cid.push(cid),
+                None=> vec_fail.push(*uid),
+            }
+        }
+        (vec_cid, vec_fail)
+    }
+
+    /// 一条消息带上所有连接id 发给网络线程 由网络线程分发
+    fn multicast_wan(&self, sid: u64, ext: u32, pid: u16, vec_cid: &[u64], payload: &[u8]){
+        if vec_cid.is_empty() {
+            return;
+        }
+        let mut msg = MsgData::new_uid_pid(sid, SProtoId::Multicast as u16);
+        msg.ext = ext;
+        msg.buf = MulticastData::encode(pid, vec_cid, payload).into();
+        if !self.wan_service.sender(msg) {
+            let vec_uid: Vec<u64> = vec_cid.iter()
+                .filter_map(|cid| self.mucid_route.cid_to_uid(*cid))
+                .copied()
+                .collect();
+            self.multicast_fail(sid, pid, &vec_uid);
+        }
+    }
+
+    /// 通知服务 这些用户没有发送成功
+    fn multicast_fail(&self, sid: u64, pid: u16, vec_uid: &[u64]){
+        if vec_uid.is_empty() {
+            return;
+        }
+        let mut msg = MsgData::new_pid(SProtoId::MulticastFail as u16);
+        msg.buf = MulticastData::encode(pid, vec_uid, &[]).into();
+        self.lan_service.sender(SrvMsg::new(sid, msg));
+    }
+
+    /// ServerJoin MsgData.buf(|pid:u16|pid:u16|...|)
+    fn get_sid_proto(buf: &[u8])->Vec<u16>{
+        buf.chunks_exact(2).map(|pid| u16::from_le_bytes([pid[0], pid[1]])).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mini_socket::frame_codec::{FrameCodec, LanHead, WanHead};
+    use mini_socket::rpc::{self, Rpc, RpcError};
+    use mini_socket::tcp_socket::TcpSocket;
+    use mini_socket::tcp_socket_rw::{ReadResult, TcpSocketRw, WriteResult};
+    use mini_utils::wtimer::WTimer;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::net::{TcpListener, TcpStream};
+    use std::rc::Rc;
+
+    struct Peer {
+        socket: TcpSocket<MsgData>,
+        buffer: Vec<u8>,
+        vec_msg: VecDeque<MsgData>,
+    }
+
+    impl Peer {
+        fn connect(addr: &str, tcp_socket_rw: Box<dyn TcpSocketRw<MsgData>>) -> Self {
+            let deadline = time::timestamp() + 3000;
+            loop {
+                match TcpStream::connect(addr) {
+                    Ok(socket) => {
+                        return Peer { socket: TcpSocket::new(socket, tcp_socket_rw), buffer: vec![0u8; 65536], vec_msg: VecDeque::new() };
+                    }
+                    Err(err) => {
+                        assert!(time::timestamp() < deadline, "connect {} error:{}", addr, err);
+                        thread::sleep(Duration::from_millis(10));
+                    }
+                }
+            }
+        }
+
+        fn send(&mut self, msg: MsgData) {
+            self.socket.push_vec_queue(msg).unwrap();
+            loop {
+                match self.socket.write() {
+                    WriteResult::Finish => return,
+                    WriteResult::BufferFull => thread::sleep(Duration::from_millis(1)),
+                    WriteResult::Error(err) => panic!("write error:{}", err),
+                }
+            }
+        }
+
+        fn recv(&mut self) -> MsgData {
+            let deadline = time::timestamp() + 3000;
+            loop {
+                if let Some(msg) = self.vec_msg.pop_front() {
+                    return msg;
+                }
+                match self.socket.read(&mut self.buffer) {
+                    ReadResult::Data(vec_msg) => self.vec_msg.extend(vec_msg),
+                    ReadResult::Error(_, err) => panic!("read error:{}", err),
+                }
+                assert!(time::timestamp() < deadline, "recv timeout");
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+    }
+
+    fn free_addr() -> String {
+        TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string()
+    }
+
+    /// 客户端 -> proxy -> 服务 的rpc请求 回复和错误回复经 proxy 返回客户端
+    #[test]
+    fn test_rpc_through_proxy() {
+        let (wan_addr, lan_addr) = (free_addr(), free_addr());
+        let mut config = Config::new();
+        config.wan_listen_config.set_bind_socket_addr(&wan_addr);
+        config.lan_listen_config.set_bind_socket_addr(&lan_addr);
+        thread::spawn(move || Service::new(config).unwrap().run());
+
+        // 服务加入 GroupInfo 返回时 ServerJoin 已处理
+        let mut lan = Peer::connect(&lan_addr, Box::new(FrameCodec::<LanHead>::default()));
+        let mut join = MsgData::new_pid(SProtoId::ServerJoin as u16);
+        join.buf = [SProtoId::AuthRequest as u16, SProtoId::Disconnect as u16, 1000]
+            .iter()
+            .flat_map(|pid| pid.to_le_bytes())
+            .collect::<Vec<u8>>()
+            .into();
+        lan.send(join);
+        lan.send(MsgData::new_pid(SProtoId::GroupInfo as u16));
+        assert_eq!(lan.recv().pid, SProtoId::GroupInfo as u16);
+
+        // 认证 服务发一条消息 客户端收到时 AuthReqPass 已处理
+        let mut wan = Peer::connect(&wan_addr, Box::new(FrameCodec::<WanHead>::default()));
+        wan.send(MsgData::new_pid(SProtoId::AuthRequest as u16));
+        let auth = lan.recv();
+        assert_eq!(auth.pid, SProtoId::AuthRequest as u16);
+        let mut pass = MsgData::new_uid_pid(auth.uid, SProtoId::AuthReqPass as u16);
+        let mut buf = vec![0u8; 8];
+        bytes::write_u64(&mut buf, 7);
+        pass.buf = buf.into();
+        lan.send(pass);
+        lan.send(MsgData::new_uid_pid(7, 1001));
+        assert_eq!(wan.recv().pid, 1001);
+
+        let rpc = Rpc::new();
+        let mut wtimer = WTimer::new(1);
+        let result: Rc<RefCell<Vec<Result<MsgData, RpcError>>>> = Rc::default();
+        for _ in 0..2 {
+            let result = result.clone();
+            let req = rpc.request(&mut wtimer, MsgData::new_pid(1000), 3000, move |r| result.borrow_mut().push(r));
+            wan.send(req);
+        }
+        let req = lan.recv();
+        assert_eq!((req.uid, req.pid), (7, 1000));
+        lan.send(rpc::reply(&req, vec![1, 2]));
+        let req = lan.recv();
+        lan.send(rpc::reply_error(&req, 404, "not found"));
 
-        while end_pos <= buf_size {
-            pos = end_pos; 
-            end_pos = end_pos + 2;
-            vec_u16.push(bytes::read_u16(&buf[pos..]));
+        while rpc.pending_num() > 0 {
+            assert!(rpc.on_msg(wan.recv()).is_none());
         }
-        vec_u16
+        let result = result.borrow();
+        assert_eq!(result[0].as_ref().map(|m| &m.buf[..]).ok(), Some(&[1u8, 2][..]));
+        assert_eq!(result[1].as_ref().err(), Some(&RpcError::Remote(404, "not found".into())));
     }
 }
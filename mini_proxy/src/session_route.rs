@@ -0,0 +1,298 @@
+/// 断线重连 认证通过的用户在宽限期内可以用令牌重新连接
+/// 服务不会收到断线和重新登录 断线期间发给用户的消息保存在会话中
+use log::error;
+use mini_socket::tcp_socket_msg::{DisReason, MsgData, SProtoId};
+use std::collections::{HashMap, VecDeque};
+
+/// 令牌字节数
+pub const TOKEN_SIZE: usize = 16;
+
+struct Session {
+    token: [u8; TOKEN_SIZE],
+    /// None:断线中
+    cid: Option<u64>,
+    /// 客户端协议版本
+    version: u16,
+    /// 已发给用户的消息数 最后一条消息的序号
+    seq: u64,
+    /// 没有确认的消息 (序号, 消息) 序号连续 最后一条是 seq
+    deque_unack: VecDeque<(u64, MsgData)>,
+    /// 断线时间
+    detach_ts: u64,
+    /// 断线原因 宽限期过后通知服务
+    reason: DisReason,
+}
+
+/// 重连成功 要发给新连接的消息
+pub struct ResumeData {
+    pub uid: u64,
+    /// 旧连接还没有断开 要断开旧连接
+    pub old_cid: Option<u64>,
+    /// 新的 SessionToken
+    pub token_msg: MsgData,
+    /// 客户端没有收到的消息
+    pub vec_msg: Vec<MsgData>,
+}
+
+pub struct SessionRoute {
+    /// 单位:毫秒 0:不启用
+    grace: u64,
+    /// 每个会话保存没有确认的消息的最大条数
+    buffer_size: usize,
+    uid_session: HashMap<u64, Session>,
+    token_uid: HashMap<[u8; TOKEN_SIZE], u64>,
+    /// 断线的用户 按断线时间排序 (过期时间, 用户Id)
+    deque_expire: VecDeque<(u64, u64)>,
+}
+
+impl SessionRoute {
+    pub fn new(grace: u64, buffer_size: usize) -> Self {
+        SessionRoute {
+            grace,
+            buffer_size,
+            uid_session: HashMap::new(),
+            token_uid: HashMap::new(),
+            deque_expire: VecDeque::new(),
+        }
+    }
+
+    #[inline]
+    pub fn is_enable(&self) -> bool {
+        self.grace > 0
+    }
+
+    /// 认证通过 创建会话 同一个用户已有的会话被替换
+    /// 返回发给客户端的 SessionToken 生成令牌失败时不创建会话
+    pub fn create(&mut self, uid: u64, cid: u64, version: u16) -> Option<MsgData> {
+        self.remove(uid);
+        let token = self.new_token(uid)?;
+        self.uid_session.insert(uid, Session {
+            token,
+            cid: Some(cid),
+            version,
+            seq: 0,
+            deque_unack: VecDeque::new(),
+            detach_ts: 0,
+            reason: DisReason::Unknown,
+        });
+        Some(Self::token_msg(cid, &token))
+    }
+
+    /// 删除会话
+    #[inline]
+    pub fn remove(&mut self, uid: u64) {
+        if let Some(session) = self.uid_session.remove(&uid) {
+            self.token_uid.remove(&session.token);
+        }
+    }
+
+    /// 用户断线中
+    #[inline]
+    pub fn is_detached(&self, uid: u64) -> bool {
+        matches!(self.uid_session.get(&uid), Some(session) if session.cid.is_none())
+    }
+
+    /// 发给用户的消息编号后保存到会话 包体转成共享数据
+    /// 序号放在消息的 ext 中 WAN 线程丢弃消息时 客户端能发现序号跳过
+    /// 只保存用户协议 return false:用户没有会话
+    pub fn record(&mut self, uid: u64, msg: &mut MsgData) -> bool {
+        let buffer_size = self.buffer_size;
+        match self.uid_session.get_mut(&uid) {
+            Some(session) => {
+                if !SProtoId::exists(msg.pid) {
+                    msg.buf = std::mem::take(&mut msg.buf).into_shared();
+                    session.seq += 1;
+                    msg.set_session_seq(session.seq);
+                    session.deque_unack.push_back((session.seq, msg.clone()));
+                    if session.deque_unack.len() > buffer_size {
+                        session.deque_unack.pop_front();
+                    }
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 广播的消息保存到所有会话
+    /// 每个会话的序号不同 返回在线会话要单独发送的消息 MsgData.uid 是连接id
+    pub fn record_all(&mut self, msg: &MsgData) -> Vec<MsgData> {
+        let vec_uid: Vec<u64> = self.uid_session.keys().copied().collect();
+        let mut vec_msg = vec![];
+        for uid in vec_uid {
+            let mut msg = msg.clone();
+            self.record(uid, &mut msg);
+            if let Some(cid) = self.uid_session[&uid].cid {
+                msg.uid = cid;
+                vec_msg.push(msg);
+            }
+        }
+        vec_msg
+    }
+
+    /// 客户端确认收到 recv_seq 之前的消息
+    pub fn ack(&mut self, uid: u64, recv_seq: u64) {
+        if let Some(session) = self.uid_session.get_mut(&uid) {
+            while matches!(session.deque_unack.front(), Some((seq, _)) if *seq <= recv_seq) {
+                session.deque_unack.pop_front();
+            }
+        }
+    }
+
+    /// 连接断开 可以重连的原因进入宽限期
+    /// return true:会话保留 不通知服务
+    pub fn detach(&mut self, uid: u64, cid: u64, reason: DisReason, now: u64) -> bool {
+        let can_resume = matches!(
+            reason,
+            DisReason::PeerClosed
+                | DisReason::IoError
+                | DisReason::QueueFull
+                | DisReason::HighMarkTimeout
+                | DisReason::Checksum
+        );
+        if !matches!(self.uid_session.get(&uid), Some(session) if session.cid == Some(cid)) {
+            return false;
+        }
+        if !can_resume {
+            self.remove(uid);
+            return false;
+        }
+        if let Some(session) = self.uid_session.get_mut(&uid) {
+            session.cid = None;
+            session.detach_ts = now;
+            session.reason = reason;
+        }
+        self.deque_expire.push_back((now + self.grace, uid));
+        true
+    }
+
+    /// 客户端用令牌重连
+    /// recv_seq 之后的消息要还在会话中 否则失败
+    pub fn resume(&mut self, token: &[u8], recv_seq: u64, cid: u64, version: u16) -> Result<ResumeData, String> {
+        let uid = match self.token_uid.get(token) {
+            Some(uid) => *uid,
+            None => return Err("unknown token".into()),
+        };
+        let session = match self.uid_session.get(&uid) {
+            Some(session) => session,
+            None => return Err(format!("uid:{} no session", uid)),
+        };
+        if recv_seq > session.seq || recv_seq + (session.deque_unack.len() as u64) < session.seq {
+            return Err(format!("uid:{} recv_seq:{} seq:{} unack:{}", uid, recv_seq, session.seq, session.deque_unack.len()));
+        }
+        let token = match self.new_token(uid) {
+            Some(token) => token,
+            None => return Err(format!("uid:{} new token failed", uid)),
+        };
+        self.ack(uid, recv_seq);
+
+        let old_token = self.uid_session[&uid].token;
+        self.token_uid.remove(&old_token);
+
+        let session = self.uid_session.get_mut(&uid).unwrap();
+        session.token = token;
+        session.version = version;
+        let old_cid = session.cid.replace(cid);
+        let vec_msg = session.deque_unack.iter()
+            .map(|(_, msg)| MsgData { uid: cid, ..msg.clone() })
+            .collect();
+        Ok(ResumeData { uid, old_cid, token_msg: Self::token_msg(cid, &token), vec_msg })
+    }
+
+    /// 宽限期已过的会话
+    /// 返回 (用户Id, 客户端协议版本, 断线原因) 要通知服务
+    pub fn expire(&mut self, now: u64) -> Vec<(u64, u16, DisReason)> {
+        let mut vec_expire = vec![];
+        while let Some(&(expire_ts, uid)) = self.deque_expire.front() {
+            if expire_ts > now {
+                break;
+            }
+            self.deque_expire.pop_front();
+            match self.uid_session.get(&uid) {
+                // 重连后又断线 以最后一次为准
+                Some(session) if session.cid.is_none() && session.detach_ts + self.grace == expire_ts => {
+                    vec_expire.push((uid, session.version, session.reason));
+                    self.remove(uid);
+                }
+                _ => {}
+            }
+        }
+        vec_expire
+    }
+
+    /// 所有断线中的会话 proxy 退出时通知服务
+    pub fn drain_detached(&mut self) -> Vec<(u64, u16)> {
+        let vec_uid: Vec<(u64, u16)> = self.uid_session.iter()
+            .filter(|(_, session)| session.cid.is_none())
+            .map(|(uid, session)| (*uid, session.version))
+            .collect();
+        for (uid, _) in vec_uid.iter() {
+            self.remove(*uid);
+        }
+        self.deque_expire.clear();
+        vec_uid
+    }
+
+    /// 随机数生成失败时返回 None
+    fn new_token(&mut self, uid: u64) -> Option<[u8; TOKEN_SIZE]> {
+        let mut token = [0u8; TOKEN_SIZE];
+        loop {
+            if let Err(err) = getrandom::getrandom(&mut token) {
+                error!("uid:{} getrandom error:{}", uid, err);
+                return None;
+            }
+            if let std::collections::hash_map::Entry::Vacant(entry) = self.token_uid.entry(token) {
+                entry.insert(uid);
+                return Some(token);
+            }
+        }
+    }
+
+    #[inline]
+    fn token_msg(cid: u64, token: &[u8; TOKEN_SIZE]) -> MsgData {
+        MsgData { uid: cid, pid: SProtoId::SessionToken as u16, ext: 0, buf: token.to_vec().into() }
+    }
+}
+
+#[test]
+fn test() {
+    let mut session_route = SessionRoute::new(1000, 3);
+    let token_msg = session_route.create(7, 100, 1).unwrap();
+    assert_eq!((token_msg.uid, token_msg.buf.len()), (100, TOKEN_SIZE));
+
+    for i in 0..5 {
+        let mut msg = MsgData::new_uid_pid(7, 1000 + i);
+        assert!(session_route.record(7, &mut msg));
+        assert!(msg.buf.is_shared());
+        assert_eq!(msg.get_session_seq(i as u64), i as u64 + 1);
+    }
+    assert!(!session_route.record(8, &mut MsgData::new_uid_pid(8, 1000)));
+    assert!(session_route.record(7, &mut MsgData::new_uid_pid(7, SProtoId::Disconnect as u16)));
+
+    // 其它连接的断线 不能重连的原因
+    assert!(!session_route.detach(7, 101, DisReason::PeerClosed, 0));
+    assert!(session_route.detach(7, 100, DisReason::IoError, 0));
+    assert!(session_route.is_detached(7));
+
+    // 只保存了最后3条 第2条已经没有了
+    assert!(session_route.resume(&token_msg.buf, 1, 200, 1).is_err());
+    assert!(session_route.resume(&token_msg.buf, 6, 200, 1).is_err());
+    let data = session_route.resume(&token_msg.buf, 3, 200, 2).unwrap();
+    assert_eq!((data.uid, data.old_cid, data.token_msg.uid), (7, None, 200));
+    assert_eq!(data.vec_msg.iter().map(|msg| (msg.uid, msg.pid, msg.get_session_seq(3))).collect::<Vec<_>>(), vec![(200, 1003, 4), (200, 1004, 5)]);
+    // 令牌只能用一次
+    assert!(session_route.resume(&token_msg.buf, 5, 300, 2).is_err());
+
+    // 重连后又断线 宽限期从最后一次断线开始
+    assert!(session_route.detach(7, 200, DisReason::PeerClosed, 500));
+    assert!(session_route.expire(1000).is_empty());
+    assert_eq!(session_route.expire(1500), vec![(7, 2, DisReason::PeerClosed)]);
+    assert!(!session_route.is_detached(7));
+    assert!(session_route.resume(&data.token_msg.buf, 5, 300, 2).is_err());
+
+    session_route.create(9, 100, 1);
+    let vec_msg = session_route.record_all(&MsgData::new_pid(1000));
+    assert_eq!(vec_msg.iter().map(|msg| (msg.uid, msg.get_session_seq(0))).collect::<Vec<_>>(), vec![(100, 1)]);
+    assert!(!session_route.detach(9, 100, DisReason::Kick, 0));
+    assert!(session_route.drain_detached().is_empty());
+}
@@ -1,16 +1,20 @@
-use mini_socket::tcp_socket_msg::{MsgData,SProtoId};
+use mini_socket::error::Error;
+use mini_socket::tcp_socket_msg::{MsgBuf, MsgData, MulticastData, SProtoId};
 
 use crate::wan_tcp_rw::WanTcpRw;
 use mini_socket::tcp_listen_config::TcpListenConfig;
 use mini_socket::tcp_listen_service::TcpListenService;
 use mini_utils::wconfig::WConfig;
 
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::sync::mpsc::Receiver;
 use std::sync::mpsc::SyncSender;
 use std::sync::mpsc::TryRecvError;
 use std::sync::mpsc::TrySendError;
 
-use log::{error};
+use log::{error, info, warn};
 use mini_utils::worker::RecvResEnum;
 use mini_utils::worker::SendResEnum;
 use mini_utils::worker::Worker;
@@ -72,31 +76,63 @@ fn worker_closure(
 ) -> Box<dyn FnOnce(Receiver<MsgData>, SyncSender<MsgData>) + Send> {
     Box::new(
         move |receiver: Receiver<MsgData>, sender: SyncSender<MsgData>| {
+            // 连接的客户端地址 用了 PROXY protocol 时是真实地址 断开时写进日志
+            let peer_addrs: RefCell<HashMap<u64, Option<SocketAddr>>> = RefCell::new(HashMap::new());
+            // 第一次收到消息 还没查地址的连接
+            let new_cids: RefCell<Vec<u64>> = RefCell::new(vec![]);
             //-----------------------------------------------------------------------------
             let mut net_msg_cb_fn = |cid: u64, vec_msg: Vec<MsgData>| {
-                for mut msg in vec_msg {
+                if !peer_addrs.borrow().contains_key(&cid) {
+                    peer_addrs.borrow_mut().insert(cid, None);
+                    new_cids.borrow_mut().push(cid);
+                }
+                let mut iter = vec_msg.into_iter();
+                while let Some(mut msg) = iter.next() {
+                    if !is_client_pid(msg.pid) {
+                        warn!("cid:{} send system SProtoId:{}", cid, msg.pid);
+                        continue;
+                    }
                     msg.uid = cid;
                     match sender.try_send(msg) {
                         Ok(_) => {}
-                        Err(TrySendError::Full(_)) => {
+                        Err(TrySendError::Full(msg)) => {
                             error!("WanService try_send Full");
+                            let mut vec_rest = vec![msg];
+                            vec_rest.extend(iter);
+                            return vec_rest;
                         }
                         Err(TrySendError::Disconnected(_)) => {
                             error!("WanService try_send Disconnected");
                         }
                     };
                 }
+                vec![]
             };
-            let mut msg_kind_cb_fn = |cid: u64, spid: SProtoId| {
-                match sender.try_send(MsgData::new_uid_pid(cid, spid as u16)) {
-                    Ok(_) => {}
-                    Err(TrySendError::Full(_)) => {
-                        error!("WanService try_send Full");
-                    }
-                    Err(TrySendError::Disconnected(_)) => {
-                        error!("WanService try_send Disconnected");
+            let mut msg_kind_cb_fn = |cid: u64, spid: SProtoId, err: Option<&Error>| {
+                let mut vec_msg = vec![];
+                match err {
+                    Some(err) => {
+                        let peer_addr = peer_addrs.borrow_mut().remove(&cid).flatten();
+                        log_disconnect(cid, peer_addr, err);
+                        if let Error::Crypto(_) = err {
+                            // 解密失败 先通知服务用户数据异常
+                            vec_msg.push(MsgData::new_uid_pid(cid, SProtoId::ExcUserData as u16));
+                        }
+                        vec_msg.push(MsgData::new_disconnect(cid, err.into()));
                     }
+                    None => vec_msg.push(MsgData::new_uid_pid(cid, spid as u16)),
                 };
+                for msg in vec_msg {
+                    match sender.try_send(msg) {
+                        Ok(_) => {}
+                        Err(TrySendError::Full(_)) => {
+                            error!("WanService try_send Full");
+                        }
+                        Err(TrySendError::Disconnected(_)) => {
+                            error!("WanService try_send Disconnected");
+                        }
+                    };
+                }
             };
             //-----------------------------------------------------------------------------
             let mut tcp_listen_service: TcpListenService<WanTcpRw, MsgData>;
@@ -113,7 +149,6 @@ fn worker_closure(
             let wait_timeout = tcp_listen_config.epoll_wait_timeout;
 
             loop {
-                tcp_listen_service.tick();
                 loop {
                     match tcp_listen_service.epoll_event(wait_timeout) {
                         Ok(0) => {
@@ -126,15 +161,25 @@ fn worker_closure(
                         }
                     }
                 }
+                for cid in new_cids.borrow_mut().drain(..) {
+                    if let Some(stats) = tcp_listen_service.get_stats(cid) {
+                        if let Some(addr) = peer_addrs.borrow_mut().get_mut(&cid) {
+                            *addr = stats.peer_addr;
+                        }
+                    }
+                }
                 //-----------------------------------------------------------------------------
                 //single_write_msg_count = 0;
                 loop {
                     match receiver.try_recv() {
                         Ok(msg_data) => {
                             if msg_data.pid == SProtoId::Disconnect as u16 {
-                                tcp_listen_service.del_tcp_socket(msg_data.uid);
+                                //先发完之前的消息 如 ExcUserData 再关闭 关闭时不会再通知 Disconnect
+                                peer_addrs.borrow_mut().remove(&msg_data.uid);
+                                tcp_listen_service.close_after_flush(msg_data.uid);
+                            }else if msg_data.pid == SProtoId::Multicast as u16 {
+                                multicast(&mut tcp_listen_service, &sender, msg_data);
                             }else{
-                                //这里要优化 判断是否广播消息
                                 tcp_listen_service.write_msg(msg_data.uid, msg_data);
                             }
                         }
@@ -150,3 +195,75 @@ fn worker_closure(
         },
     )
 }
+
+/// 按断开原因分级记录
+/// 协议错误 超大消息 可能是恶意客户端 发送队列堆积是客户端网络太慢
+/// 客户端地址 用了 PROXY protocol 时是真实地址 还没收到消息的连接没有
+fn log_disconnect(cid: u64, peer_addr: Option<SocketAddr>, err: &Error) {
+    let addr = match peer_addr {
+        Some(addr) => addr.to_string(),
+        None => "-".into(),
+    };
+    match err {
+        Error::PeerClosed => info!("cid:{} addr:{} client closed", cid, addr),
+        Error::Protocol(_) | Error::MsgTooLarge(_) | Error::Crypto(_) | Error::Checksum(..) => warn!("cid:{} addr:{} bad client:{}", cid, addr, err),
+        Error::QueueFull | Error::HighMarkTimeout(_) => warn!("cid:{} addr:{} slow client:{}", cid, addr, err),
+        _ => error!("cid:{} addr:{} disconnect:{}", cid, addr, err),
+    }
+}
+
+/// 客户端能发送的协议 用户协议和这几个系统协议
+/// 其它系统协议只能由 proxy 或服务产生 客户端发送的丢弃
+#[inline]
+fn is_client_pid(pid: u16) -> bool {
+    if !SProtoId::exists(pid) {
+        return true;
+    }
+    matches!(
+        SProtoId::new(pid),
+        SProtoId::AuthRequest | SProtoId::SessionResume | SProtoId::SessionAck | SProtoId::KeyExchange
+    )
+}
+
+/// msg_data.uid(服务id) msg_data.buf(MulticastData 连接id列表)
+/// 包体只复制一份 所有连接共用
+/// 发送失败的连接id 用 MulticastFail 返回给 proxy
+fn multicast(
+    tcp_listen_service: &mut TcpListenService<WanTcpRw, MsgData>,
+    sender: &SyncSender<MsgData>,
+    msg_data: MsgData,
+) {
+    let data = match MulticastData::decode(&msg_data.buf) {
+        Ok(data) => data,
+        Err(err) => {
+            error!("WanService multicast sid:{} error:{}", msg_data.uid, err);
+            return;
+        }
+    };
+    let msg = MsgData {
+        uid: 0,
+        pid: data.pid,
+        ext: msg_data.ext,
+        buf: MsgBuf::shared(data.payload.to_vec()),
+    };
+    let vec_fail = tcp_listen_service.broadcast(&data.vec_id, msg);
+    if vec_fail.is_empty() {
+        return;
+    }
+    let mut fail_msg = MsgData::new_uid_pid(msg_data.uid, SProtoId::MulticastFail as u16);
+    fail_msg.buf = MulticastData::encode(data.pid, &vec_fail, &[]).into();
+    if let Err(err) = sender.try_send(fail_msg) {
+        error!("WanService try_send MulticastFail error:{}", err);
+    }
+}
+
+#[test]
+fn test_is_client_pid() {
+    assert!(is_client_pid(1000));
+    assert!(is_client_pid(SProtoId::AuthRequest as u16));
+    assert!(is_client_pid(SProtoId::SessionAck as u16));
+    assert!(!is_client_pid(SProtoId::QueueHighMark as u16));
+    assert!(!is_client_pid(SProtoId::ExcUserData as u16));
+    assert!(!is_client_pid(SProtoId::Disconnect as u16));
+    assert!(!is_client_pid(SProtoId::Multicast as u16));
+}
@@ -0,0 +1,136 @@
+//! epoll 与 io_uring 收发消息的对比
+//! 每轮客户端给所有连接各写 batch 条消息 服务端用 OSPoll 读到后原样写回 客户端读完后开始下一轮
+//! epoll: 可读可写时 TcpSocket 用 read writev 收发
+//! io_uring: recv writev 提交到 io_uring 每次 wait 一起提交
+//! cargo bench -p mini_socket --bench poll --features io-uring
+
+use mini_socket::frame_codec::FrameCodec;
+use mini_socket::frame_codec::LanHead;
+use mini_socket::os_epoll::OSEpoll;
+use mini_socket::os_poll::OSPoll;
+#[cfg(feature = "io-uring")]
+use mini_socket::os_uring::OSUring;
+use mini_socket::tcp_socket::TcpSocket;
+use mini_socket::tcp_socket_msg::MsgData;
+use mini_socket::tcp_socket_rw::ReadResult;
+use mini_socket::tcp_socket_rw::TcpSocketRw;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::os::unix::io::AsRawFd;
+use std::thread;
+use std::time::Instant;
+
+const ROUND: usize = 200;
+const BODY_SIZE: usize = 64;
+
+/// 一个连接所有轮次的消息 编码后的数据
+/// 包id 每条递增 每个连接的数据相同
+fn encode_frames(msg_num: usize) -> (Vec<u8>, usize) {
+    let mut codec = FrameCodec::<LanHead>::default();
+    let mut data = vec![];
+    let mut head = vec![0u8; codec.head_size()];
+    for n in 0..msg_num {
+        let mut msg = MsgData::new_uid_pid(n as u64 + 1, 1000);
+        msg.buf = vec![7u8; BODY_SIZE].into();
+        let msg = codec.prepare_msg(msg);
+        let msg = codec.seal_frame(msg).unwrap();
+        codec.encode_head(&msg, &mut head).unwrap();
+        data.extend_from_slice(&head);
+        data.extend_from_slice(codec.body(&msg));
+    }
+    (data, head.len() + BODY_SIZE)
+}
+
+fn bench(os_poll: OSPoll, conn_num: usize, batch: usize) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (data, frame_size) = encode_frames(ROUND * batch);
+    let client = thread::spawn(move || {
+        let mut vec_socket: Vec<TcpStream> = (0..conn_num).map(|_| TcpStream::connect(addr).unwrap()).collect();
+        let round_size = frame_size * batch;
+        let mut buf = vec![0u8; round_size];
+        for round in 0..ROUND {
+            let round_data = &data[round * round_size..(round + 1) * round_size];
+            for socket in vec_socket.iter_mut() {
+                socket.write_all(round_data).unwrap();
+            }
+            for socket in vec_socket.iter_mut() {
+                socket.read_exact(&mut buf).unwrap();
+            }
+        }
+    });
+
+    let mut vec_tcp_socket: Vec<TcpSocket<MsgData>> = (0..conn_num)
+        .map(|_| {
+            let (socket, _) = listener.accept().unwrap();
+            socket.set_nonblocking(true).unwrap();
+            socket.set_nodelay(true).unwrap();
+            TcpSocket::new(socket, Box::new(FrameCodec::<LanHead>::default()))
+        })
+        .collect();
+    for (cid, tcp_socket) in vec_tcp_socket.iter().enumerate() {
+        os_poll.add_socket(cid as u64, tcp_socket, false).unwrap();
+    }
+    let mut events = vec![libc::epoll_event { events: 0, u64: 0 }; 1024];
+    let mut share_buffer = vec![0u8; 1048576];
+    let total = ROUND * batch * conn_num;
+    let mut echo_num = 0;
+
+    let ts = Instant::now();
+    while echo_num < total {
+        let num = os_poll.wait(1000, &mut events).unwrap();
+        for event in events[..num as usize].iter() {
+            let cid = event.u64;
+            let tcp_socket = &mut vec_tcp_socket[cid as usize];
+            if event.events & libc::EPOLLIN as u32 != 0 {
+                match os_poll.read(cid, tcp_socket, &mut share_buffer) {
+                    ReadResult::Data(vec_msg) => {
+                        echo_num += vec_msg.len();
+                        for msg in vec_msg {
+                            tcp_socket.push_vec_queue(msg).unwrap();
+                        }
+                    }
+                    ReadResult::Error(_, err) => panic!("cid:{} read error:{}", cid, err),
+                }
+            }
+            os_poll.write(cid, tcp_socket).unwrap();
+        }
+    }
+    // 等最后一轮写完
+    while vec_tcp_socket.iter().any(|tcp_socket| tcp_socket.vec_queue_len() > 0) {
+        let num = os_poll.wait(1000, &mut events).unwrap();
+        for event in events[..num as usize].iter() {
+            let cid = event.u64;
+            os_poll.write(cid, &mut vec_tcp_socket[cid as usize]).unwrap();
+        }
+    }
+    client.join().unwrap();
+    let elapsed = ts.elapsed();
+    println!(
+        "{:<8} conn:{:>4} batch:{:>3} round:{} time:{:>10?} per msg:{:>8?}",
+        os_poll.name(),
+        conn_num,
+        batch,
+        ROUND,
+        elapsed,
+        elapsed / total as u32
+    );
+    for (cid, tcp_socket) in vec_tcp_socket.iter().enumerate() {
+        os_poll.del_socket(cid as u64, tcp_socket.socket.as_raw_fd()).unwrap();
+    }
+}
+
+fn main() {
+    for batch in [1, 64] {
+        for conn_num in [16, 256] {
+            bench(OSPoll::Epoll(OSEpoll::new().unwrap()), conn_num, batch);
+            #[cfg(feature = "io-uring")]
+            match OSUring::new() {
+                Ok(os_uring) => bench(OSPoll::Uring(Box::new(os_uring)), conn_num, batch),
+                Err(err) => println!("OSUring::new error:{}", err),
+            }
+        }
+    }
+}
@@ -0,0 +1,105 @@
+//! 连接表 Slab 与 HashMap(原来的 TcpSocketMgmt) 的增删查对比
+//! cargo bench -p mini_socket --bench slab
+
+use mini_socket::slab::Slab;
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::time::Instant;
+
+/// 每个连接模拟的数据大小
+type Conn = [u64; 8];
+
+/// 查找轮数
+const LOOKUP_ROUND: usize = 20;
+
+/// 原来的分配方式: next_cid 线性探测空闲id
+struct HashMapTable {
+    next_cid: u64,
+    hash_map: HashMap<u64, Conn>,
+}
+
+impl HashMapTable {
+    fn new(capacity: usize) -> Self {
+        HashMapTable {
+            next_cid: 0,
+            hash_map: HashMap::with_capacity(capacity),
+        }
+    }
+
+    fn insert(&mut self, conn: Conn) -> u64 {
+        let mut cid = self.next_cid;
+        loop {
+            cid += 1;
+            if !self.hash_map.contains_key(&cid) {
+                break;
+            }
+        }
+        self.next_cid = cid;
+        self.hash_map.insert(cid, conn);
+        cid
+    }
+}
+
+fn bench_slab(conn_num: usize) {
+    let mut slab = Slab::with_capacity(conn_num);
+    let ts = Instant::now();
+    let mut vec_cid: Vec<u64> = (0..conn_num).map(|i| slab.insert([i as u64; 8])).collect();
+    let insert_time = ts.elapsed();
+
+    let ts = Instant::now();
+    let mut sum = 0;
+    for _ in 0..LOOKUP_ROUND {
+        for cid in vec_cid.iter() {
+            sum += slab.get_mut(*cid).map_or(0, |conn| conn[0]);
+        }
+    }
+    black_box(sum);
+    let lookup_time = ts.elapsed();
+
+    // 断开一半连接再重连
+    let ts = Instant::now();
+    for cid in vec_cid.iter_mut().step_by(2) {
+        slab.remove(*cid);
+        *cid = slab.insert([0; 8]);
+    }
+    let churn_time = ts.elapsed();
+    println!(
+        "slab     conn:{:>6} insert:{:>10?} lookup:{:>10?} churn:{:>10?}",
+        conn_num, insert_time, lookup_time, churn_time
+    );
+}
+
+fn bench_hash_map(conn_num: usize) {
+    let mut table = HashMapTable::new(conn_num);
+    let ts = Instant::now();
+    let mut vec_cid: Vec<u64> = (0..conn_num).map(|i| table.insert([i as u64; 8])).collect();
+    let insert_time = ts.elapsed();
+
+    let ts = Instant::now();
+    let mut sum = 0;
+    for _ in 0..LOOKUP_ROUND {
+        for cid in vec_cid.iter() {
+            sum += table.hash_map.get_mut(cid).map_or(0, |conn| conn[0]);
+        }
+    }
+    black_box(sum);
+    let lookup_time = ts.elapsed();
+
+    let ts = Instant::now();
+    for cid in vec_cid.iter_mut().step_by(2) {
+        table.hash_map.remove(cid);
+        *cid = table.insert([0; 8]);
+    }
+    let churn_time = ts.elapsed();
+    println!(
+        "hash_map conn:{:>6} insert:{:>10?} lookup:{:>10?} churn:{:>10?}",
+        conn_num, insert_time, lookup_time, churn_time
+    );
+}
+
+fn main() {
+    for conn_num in [10_000, 100_000].iter() {
+        bench_slab(*conn_num);
+        bench_hash_map(*conn_num);
+    }
+}
@@ -0,0 +1,177 @@
+//! 突发大量小消息时 逐条write 与 批量writev 的系统调用次数对比
+//! cargo bench -p mini_socket --bench writev
+
+use mini_socket::error::Error;
+use mini_socket::tcp_socket::TcpSocket;
+use mini_socket::tcp_socket_rw::ReadResult;
+use mini_socket::tcp_socket_rw::TcpSocketRw;
+use mini_socket::tcp_socket_rw::WriteResult;
+use std::fs;
+use std::io;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::os::unix::io::AsRawFd;
+use std::thread;
+use std::time::Instant;
+
+const HEAD_SIZE: usize = 4;
+const MSG_NUM: usize = 100000;
+const MSG_SIZE: usize = 32;
+
+/// 包头只有包体字节数
+struct BenchRw {
+    batch: bool,
+    head_pos: usize,
+    body_pos: usize,
+    head_data: [u8; HEAD_SIZE],
+}
+
+impl BenchRw {
+    fn new(batch: bool) -> Self {
+        BenchRw {
+            batch,
+            head_pos: 0,
+            body_pos: 0,
+            head_data: [0u8; HEAD_SIZE],
+        }
+    }
+
+    /// 用 libc::write 才能在 /proc/self/io 的 syscw 中统计到
+    /// (TcpStream::write 用的是 send)
+    fn write_data(buffer: &[u8], pos: &mut usize, socket: &mut TcpStream) -> WriteResult {
+        loop {
+            let data = &buffer[*pos..];
+            let ret = unsafe {
+                libc::write(
+                    socket.as_raw_fd(),
+                    data.as_ptr() as *const libc::c_void,
+                    data.len(),
+                )
+            };
+            if ret == -1 {
+                let err = io::Error::last_os_error();
+                match err.kind() {
+                    ErrorKind::WouldBlock => return WriteResult::BufferFull,
+                    ErrorKind::Interrupted => continue,
+                    _ => return WriteResult::Error(err.into()),
+                }
+            }
+            if ret == 0 {
+                return WriteResult::Error(Error::PeerClosed);
+            }
+            *pos += ret as usize;
+            if *pos == buffer.len() {
+                return WriteResult::Finish;
+            }
+            return WriteResult::BufferFull;
+        }
+    }
+}
+
+impl TcpSocketRw<Vec<u8>> for BenchRw {
+    /// 和 WanTcpRw 一样 包头 包体各写一次
+    fn write(&mut self, socket: &mut TcpStream, msg: &mut Vec<u8>) -> WriteResult {
+        if self.head_pos == 0 {
+            self.head_data = (msg.len() as u32).to_le_bytes();
+        }
+        if self.head_pos < HEAD_SIZE {
+            let head_data = self.head_data;
+            match Self::write_data(&head_data, &mut self.head_pos, socket) {
+                WriteResult::Finish => {}
+                result => return result,
+            }
+        }
+        let result = Self::write_data(msg, &mut self.body_pos, socket);
+        if result == WriteResult::Finish {
+            self.head_pos = 0;
+            self.body_pos = 0;
+        }
+        result
+    }
+
+    fn read(&mut self, _socket: &mut TcpStream, _buffer: &mut Vec<u8>) -> ReadResult<Vec<u8>> {
+        ReadResult::Data(vec![])
+    }
+
+    fn head_size(&self) -> usize {
+        if self.batch {
+            HEAD_SIZE
+        } else {
+            0
+        }
+    }
+
+    fn encode_head(&mut self, msg: &Vec<u8>, head: &mut [u8]) -> Result<(), Error> {
+        head.copy_from_slice(&(msg.len() as u32).to_le_bytes());
+        Ok(())
+    }
+
+    fn body<'b>(&self, msg: &'b Vec<u8>) -> &'b [u8] {
+        msg
+    }
+}
+
+/// 进程已执行的写系统调用次数
+fn syscw() -> u64 {
+    let io = fs::read_to_string("/proc/self/io").unwrap_or_default();
+    for line in io.lines() {
+        if let Some(num) = line.strip_prefix("syscw: ") {
+            return num.trim().parse().unwrap_or(0);
+        }
+    }
+    0
+}
+
+fn bench(batch: bool) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let total_size = MSG_NUM * (HEAD_SIZE + MSG_SIZE);
+    let reader = thread::spawn(move || {
+        let (mut socket, _) = listener.accept().unwrap();
+        let mut buffer = vec![0u8; 65536];
+        let mut read_size = 0;
+        while read_size < total_size {
+            match socket.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(size) => read_size += size,
+                Err(_) => break,
+            }
+        }
+        read_size
+    });
+
+    let socket = TcpStream::connect(addr).unwrap();
+    socket.set_nonblocking(true).unwrap();
+    let mut tcp_socket = TcpSocket::new(socket, Box::new(BenchRw::new(batch)));
+    for _ in 0..MSG_NUM {
+        tcp_socket.push_vec_queue(vec![0u8; MSG_SIZE]).unwrap();
+    }
+
+    let start_syscw = syscw();
+    let start = Instant::now();
+    loop {
+        match tcp_socket.write() {
+            WriteResult::Finish => break,
+            WriteResult::BufferFull => thread::yield_now(),
+            WriteResult::Error(err) => panic!("write error:{}", err),
+        }
+    }
+    let elapsed = start.elapsed();
+    let syscall_num = syscw() - start_syscw;
+    assert_eq!(reader.join().unwrap(), total_size);
+
+    println!(
+        "{:<6} msgs:{} write syscalls:{} elapsed:{:?}",
+        if batch { "writev" } else { "write" },
+        MSG_NUM,
+        syscall_num,
+        elapsed
+    );
+}
+
+fn main() {
+    bench(false);
+    bench(true);
+}
@@ -0,0 +1,91 @@
+use std::fmt;
+use std::io;
+
+/// mini_socket 的错误
+/// 连接断开时通过 exc_msg_cb_fn 交给上层 上层可以按原因分别处理
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// 对方关闭了连接 read/write 返回0
+    PeerClosed,
+    /// 系统调用出错
+    Io(io::ErrorKind, String),
+    /// 包头 包id 等数据不符合协议
+    Protocol(String),
+    /// 消息字节数超过上限
+    MsgTooLarge(usize),
+    /// 待发送消息队列已满
+    QueueFull,
+    /// 待发送消息数在高水位以上超时 (毫秒)
+    HighMarkTimeout(u64),
+    /// 连接不存在
+    NotConnected,
+    /// 配置错误
+    Config(String),
+    /// 密钥交换或解密失败
+    Crypto(String),
+    /// 包的 CRC32C 校验失败 (包头里的值, 计算出的值)
+    Checksum(u32, u32),
+}
+
+impl Error {
+    /// errno 对应的错误
+    #[inline]
+    pub fn last_os_error() -> Self {
+        io::Error::last_os_error().into()
+    }
+
+    /// 在错误信息前加上出错的操作 如 "bind 127.0.0.1:80"
+    pub fn context(self, ctx: &str) -> Self {
+        match self {
+            Error::Io(kind, msg) => Error::Io(kind, format!("{} {}", ctx, msg)),
+            Error::Protocol(msg) => Error::Protocol(format!("{} {}", ctx, msg)),
+            Error::Config(msg) => Error::Config(format!("{} {}", ctx, msg)),
+            Error::Crypto(msg) => Error::Crypto(format!("{} {}", ctx, msg)),
+            err => err,
+        }
+    }
+
+    /// 系统调用错误的类型
+    #[inline]
+    pub fn io_kind(&self) -> Option<io::ErrorKind> {
+        match self {
+            Error::Io(kind, _) => Some(*kind),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err.kind(), err.to_string())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::PeerClosed => write!(f, "peer closed"),
+            Error::Io(_, msg) => write!(f, "{}", msg),
+            Error::Protocol(msg) => write!(f, "protocol error:{}", msg),
+            Error::MsgTooLarge(size) => write!(f, "msg size:{} too large", size),
+            Error::QueueFull => write!(f, "msg queue is full"),
+            Error::HighMarkTimeout(timeout) => write!(f, "above high mark timeout:{}ms", timeout),
+            Error::NotConnected => write!(f, "not connected"),
+            Error::Config(msg) => write!(f, "config error:{}", msg),
+            Error::Crypto(msg) => write!(f, "crypto error:{}", msg),
+            Error::Checksum(expect, actual) => write!(f, "checksum error:{:08x} != {:08x}", expect, actual),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[test]
+fn test_error_context() {
+    let err: Error = io::Error::from_raw_os_error(libc::ECONNRESET).into();
+    assert_eq!(err.io_kind(), Some(io::ErrorKind::ConnectionReset));
+    let err = err.context("write");
+    assert!(err.to_string().starts_with("write "));
+    assert_eq!(Error::PeerClosed.context("read"), Error::PeerClosed);
+    assert_eq!(Error::MsgTooLarge(10).io_kind(), None);
+}
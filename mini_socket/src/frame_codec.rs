@@ -0,0 +1,848 @@
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Write;
+use std::marker::PhantomData;
+use std::net::TcpStream;
+
+use crate::error::Error;
+use crate::msg_chunk::{self, ChunkAssembler};
+use crate::msg_compress;
+use crate::msg_crypto::{CryptoSession, TAG_SIZE};
+use crate::tcp_socket_msg::{MsgData, SProtoId, EXT_COMPRESS, EXT_ENCRYPT};
+use crate::tcp_socket_rw::{CompressStats, ReadResult, RwConfig, TcpSocketRw, WriteResult};
+
+/// Msg Id最大值
+pub const MSG_MAX_ID: u16 = 4095;
+
+/// 数据包体最大字节数 包头里占20位
+/// 启用 chunk_max_size 时 更大的消息拆成 SProtoId::MsgChunk 分片
+pub const MSG_MAX_SIZE: usize = (1 << 20) - 1;
+
+/// 包头最大字节数
+const HEAD_MAX_SIZE: usize = 32;
+
+/// 启用 RwConfig.checksum 时 FrameHead 后的 CRC32C 字节数
+/// 校验 FrameHead 和包体
+pub const CHECKSUM_SIZE: usize = 4;
+
+/// 包头格式
+/// 前4个字节固定是 |msg size:13~32位|+|mid:1~12位|
+pub trait FrameHead {
+    /// 包头字节数 不超过 HEAD_MAX_SIZE
+    const SIZE: usize;
+    const PID_OFFSET: usize;
+    const EXT_OFFSET: usize;
+    /// None: 包头不带 uid 读到的消息 uid 为0
+    const UID_OFFSET: Option<usize>;
+}
+
+/// 客户端和 proxy 之间 包头10个字节
+/// |(msg size + msg id):32|pid:16|ext:32|
+pub struct WanHead;
+
+impl FrameHead for WanHead {
+    const SIZE: usize = 10;
+    const PID_OFFSET: usize = 4;
+    const EXT_OFFSET: usize = 6;
+    const UID_OFFSET: Option<usize> = None;
+}
+
+/// proxy 和服务之间 包头18个字节
+/// |(msg size + msg id):32|pid:16|ext:32|uid:64|
+pub struct LanHead;
+
+impl FrameHead for LanHead {
+    const SIZE: usize = 18;
+    const PID_OFFSET: usize = 4;
+    const EXT_OFFSET: usize = 6;
+    const UID_OFFSET: Option<usize> = Some(10);
+}
+
+#[inline]
+fn next_msg_id(id: u16) -> u16 {
+    if id == MSG_MAX_ID {
+        0
+    } else {
+        id + 1
+    }
+}
+
+#[inline]
+fn read_u32(buf: &[u8]) -> u32 {
+    u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]])
+}
+
+fn encode_head<H: FrameHead>(id: u16, msg: &MsgData, head: &mut [u8], checksum: bool) {
+    let sign = ((msg.buf.len() as u32) << 12) + id as u32;
+    head[0..4].copy_from_slice(&sign.to_le_bytes());
+    head[H::PID_OFFSET..H::PID_OFFSET + 2].copy_from_slice(&msg.pid.to_le_bytes());
+    head[H::EXT_OFFSET..H::EXT_OFFSET + 4].copy_from_slice(&msg.ext.to_le_bytes());
+    if let Some(offset) = H::UID_OFFSET {
+        head[offset..offset + 8].copy_from_slice(&msg.uid.to_le_bytes());
+    }
+    if checksum {
+        let crc = crc32c::crc32c_append(crc32c::crc32c(&head[..H::SIZE]), &msg.buf);
+        head[H::SIZE..H::SIZE + CHECKSUM_SIZE].copy_from_slice(&crc.to_le_bytes());
+    }
+}
+
+/// 包头字节数 包含 CRC32C
+#[inline]
+fn head_len<H: FrameHead>(checksum: bool) -> usize {
+    if checksum {
+        H::SIZE + CHECKSUM_SIZE
+    } else {
+        H::SIZE
+    }
+}
+
+fn decode_head<H: FrameHead>(head: &[u8]) -> MsgData {
+    let mut msg = MsgData::new_pid(u16::from_le_bytes([head[H::PID_OFFSET], head[H::PID_OFFSET + 1]]));
+    msg.ext = read_u32(&head[H::EXT_OFFSET..]);
+    if let Some(offset) = H::UID_OFFSET {
+        let mut uid = [0u8; 8];
+        uid.copy_from_slice(&head[offset..offset + 8]);
+        msg.uid = u64::from_le_bytes(uid);
+    }
+    msg
+}
+
+/// MsgData 的帧编解码 H 是包头格式
+/// 包id 分片 压缩 加密由 RwConfig 开启
+pub struct FrameCodec<H> {
+    buf_reader: BufReader,
+    buf_writer: BufWriter,
+    /// 0:不启用分片 大于 MSG_MAX_SIZE 的消息出错
+    chunk_max_size: usize,
+    /// 分片包体的最大字节数 启用加密时留出 TAG_SIZE
+    frame_max_size: usize,
+    /// 0:不压缩
+    compress_threshold: usize,
+    /// 已发送过压缩的消息
+    compress_sent: bool,
+    /// 发送的压缩统计
+    compress_stats: CompressStats,
+    /// 客户端连接后最先发送的 KeyExchange
+    hello: Option<MsgData>,
+    /// 下一个加密帧的消息id 加密后的帧按顺序写入 和写入时的包id一致
+    seal_id: u16,
+    head: PhantomData<H>,
+}
+
+struct BufReader {
+    //包id(0~4095)
+    id: u16,
+    /// 0:no data
+    head_pos: usize,
+    /// 0:no data
+    body_pos: usize,
+    body_data: Vec<u8>,
+    head_data: [u8; HEAD_MAX_SIZE],
+    /// 重组 SProtoId::MsgChunk 分片
+    chunk_assembler: ChunkAssembler,
+    /// 分片重组的消息解压后的最大字节数 单帧解压后不超过 MSG_MAX_SIZE
+    decompress_max_size: usize,
+    /// 对方支持压缩 收到压缩的消息后为 true
+    peer_compress: bool,
+    /// 接收的压缩统计
+    compress_stats: CompressStats,
+    /// 服务端的 X25519 私钥
+    encrypt_secret: Option<[u8; 32]>,
+    /// 加密会话 客户端创建时生成 服务端收到 KeyExchange 后生成
+    crypto: Option<CryptoSession>,
+    /// 包头后有 CRC32C
+    checksum: bool,
+}
+
+struct BufWriter {
+    //包id(0~4095)
+    id: u16,
+    body_pos: usize,
+    head_pos: usize,
+    /// 是否已填充head
+    head_is_fill: bool,
+    head_data: [u8; HEAD_MAX_SIZE],
+    /// 包头后加 CRC32C
+    checksum: bool,
+}
+
+impl<H: FrameHead> Default for FrameCodec<H> {
+    fn default() -> Self {
+        FrameCodec {
+            buf_reader: BufReader {
+                id: 0,
+                body_pos: 0,
+                head_pos: 0,
+                body_data: vec![],
+                head_data: [0u8; HEAD_MAX_SIZE],
+                chunk_assembler: ChunkAssembler::new(0),
+                decompress_max_size: MSG_MAX_SIZE,
+                peer_compress: false,
+                compress_stats: CompressStats::default(),
+                encrypt_secret: None,
+                crypto: None,
+                checksum: false,
+            },
+            buf_writer: BufWriter {
+                id: 0,
+                body_pos: 0,
+                head_pos: 0,
+                head_is_fill: false,
+                head_data: [0u8; HEAD_MAX_SIZE],
+                checksum: false,
+            },
+            chunk_max_size: 0,
+            frame_max_size: MSG_MAX_SIZE,
+            compress_threshold: 0,
+            compress_sent: false,
+            compress_stats: CompressStats::default(),
+            hello: None,
+            seal_id: 0,
+            head: PhantomData,
+        }
+    }
+}
+
+impl<H> FrameCodec<H> {
+    fn write_data(buffer: &[u8], wsize: &mut usize, socket: &mut TcpStream) -> WriteResult {
+        // 没有包体的消息 write 会返回 Ok(0)
+        if buffer.is_empty() {
+            return WriteResult::Finish;
+        }
+        loop {
+            match socket.write(buffer) {
+                Ok(0) => {
+                    return WriteResult::Error(Error::PeerClosed);
+                }
+                Ok(size) => {
+                    if size == buffer.len() {
+                        return WriteResult::Finish;
+                    } else {
+                        *wsize += size;
+                        return WriteResult::BufferFull;
+                    }
+                }
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => {
+                    return WriteResult::BufferFull;
+                }
+                Err(ref err) if err.kind() == ErrorKind::Interrupted => {
+                    continue; //系统中断 write
+                }
+                Err(err) => return WriteResult::Error(err.into()),
+            }
+        }
+    }
+}
+
+impl<H: FrameHead> TcpSocketRw<MsgData> for FrameCodec<H> {
+    fn set_config(&mut self, config: &RwConfig) {
+        self.chunk_max_size = config.chunk_max_size;
+        self.buf_reader.chunk_assembler.set_max_size(config.chunk_max_size);
+        self.buf_reader.decompress_max_size = std::cmp::max(MSG_MAX_SIZE, config.chunk_max_size);
+        self.buf_reader.peer_compress = config.compress_first;
+        self.compress_threshold = config.compress_threshold;
+        self.buf_reader.encrypt_secret = config.encrypt_secret;
+        self.buf_reader.checksum = config.checksum;
+        self.buf_writer.checksum = config.checksum;
+        if config.encrypt_secret.is_some() || config.encrypt_server_key.is_some() {
+            self.frame_max_size = MSG_MAX_SIZE - TAG_SIZE;
+        }
+        if let Some(server_key) = &config.encrypt_server_key {
+            let (crypto, hello) = CryptoSession::client(server_key);
+            self.buf_reader.crypto = Some(crypto);
+            self.hello = Some(hello);
+            // hello 不加密 用掉第一个消息id
+            self.seal_id = next_msg_id(self.buf_writer.id);
+        }
+    }
+
+    fn hello_msg(&mut self) -> Option<MsgData> {
+        self.hello.take()
+    }
+
+    /// 把数据写到tcp buffer中
+    fn write(&mut self, socket: &mut TcpStream, msg: &mut MsgData) -> WriteResult {
+        if MSG_MAX_SIZE < msg.buf.len() {
+            return WriteResult::Error(Error::MsgTooLarge(msg.buf.len()));
+        }
+        let bw = &mut self.buf_writer;
+
+        // 新的消息包
+        if bw.head_pos == 0 && !bw.head_is_fill {
+            bw.head_is_fill = true;
+            let bw_id = bw.id;
+            bw.id = next_msg_id(bw.id);
+            encode_head::<H>(bw_id, msg, &mut bw.head_data, bw.checksum);
+        }
+
+        // 写头部数据
+        let head_size = head_len::<H>(bw.checksum);
+        if bw.head_pos < head_size {
+            // 写成功的字节数
+            let mut wsize = 0;
+            let result = Self::write_data(&bw.head_data[bw.head_pos..head_size], &mut wsize, socket);
+            if result == WriteResult::Finish {
+                bw.head_pos = head_size;
+            } else {
+                if WriteResult::BufferFull == result {
+                    bw.head_pos += wsize;
+                }
+                return result;
+            }
+        }
+        // 写成功的字节数
+        let mut wsize = 0;
+        // 把包体数据写入
+        let result = Self::write_data(&msg.buf[bw.body_pos..], &mut wsize, socket);
+        if WriteResult::Finish == result {
+            bw.head_pos = 0;
+            bw.body_pos = 0;
+            bw.head_is_fill = false;
+        }
+        if WriteResult::BufferFull == result {
+            bw.body_pos += wsize;
+        }
+        result
+    }
+
+    /// 批量写的包头字节数
+    fn head_size(&self) -> usize {
+        head_len::<H>(self.buf_writer.checksum)
+    }
+
+    /// 批量写时填充包头
+    fn encode_head(&mut self, msg: &MsgData, head: &mut [u8]) -> Result<(), Error> {
+        if MSG_MAX_SIZE < msg.buf.len() {
+            return Err(Error::MsgTooLarge(msg.buf.len()));
+        }
+        let bw = &mut self.buf_writer;
+        let bw_id = bw.id;
+        bw.id = next_msg_id(bw.id);
+        encode_head::<H>(bw_id, msg, head, bw.checksum);
+        Ok(())
+    }
+
+    /// 批量写时的包体数据
+    fn body<'b>(&self, msg: &'b MsgData) -> &'b [u8] {
+        &msg.buf
+    }
+
+    /// 对方支持压缩时 压缩不小于 compress_threshold 的包体
+    /// 多个连接共用的包体 (MsgBuf::Shared) 不压缩 避免每个连接各压缩复制一份
+    fn prepare_msg(&mut self, mut msg: MsgData) -> MsgData {
+        if self.compress_threshold > 0 && self.buf_reader.peer_compress && msg.ext & EXT_COMPRESS == 0 && !msg.buf.is_shared() {
+            if !self.compress_sent {
+                // 第一条消息总是压缩 通知对方支持压缩
+                self.compress_sent = msg_compress::compress(&mut msg, true, &mut self.compress_stats);
+            } else if msg.buf.len() >= self.compress_threshold {
+                msg_compress::compress(&mut msg, false, &mut self.compress_stats);
+            }
+        }
+        msg
+    }
+
+    /// 有加密会话时 每一帧按发送顺序加密 nonce 和接收方解密的顺序一致
+    /// 服务端启用加密 收到 KeyExchange 之前不发送任何帧 不会发出明文
+    fn seal_frame(&mut self, mut msg: MsgData) -> Result<MsgData, Error> {
+        match &mut self.buf_reader.crypto {
+            Some(crypto) => {
+                crypto.encrypt(&mut msg, self.seal_id)?;
+                self.seal_id = next_msg_id(self.seal_id);
+            }
+            None if self.buf_reader.encrypt_secret.is_some() => {
+                return Err(Error::Crypto(format!("no key exchange before pid:{}", msg.pid)));
+            }
+            None => {}
+        }
+        Ok(msg)
+    }
+
+    fn get_compress_stats(&self) -> CompressStats {
+        let read_stats = &self.buf_reader.compress_stats;
+        CompressStats {
+            read_raw_bytes: read_stats.read_raw_bytes,
+            read_bytes: read_stats.read_bytes,
+            ..self.compress_stats
+        }
+    }
+
+    /// 超过 frame_max_size 且不超过 chunk_max_size 的消息拆成分片
+    fn need_chunk(&self, msg: &MsgData) -> bool {
+        msg.buf.len() > self.frame_max_size && msg.buf.len() <= self.chunk_max_size
+    }
+
+    fn chunk_msg(&mut self, msg: MsgData) -> Vec<MsgData> {
+        msg_chunk::split(msg, self.frame_max_size)
+    }
+
+    fn support_read_data(&self) -> bool {
+        true
+    }
+
+    fn read_data(&mut self, data: &[u8]) -> ReadResult<MsgData> {
+        let mut vec_msg: Vec<MsgData> = vec![];
+        match self.buf_reader.split_data::<H>(data, &mut vec_msg) {
+            Some(err) => ReadResult::Error(vec_msg, err),
+            None => ReadResult::Data(vec_msg),
+        }
+    }
+
+    /// 从tcp buffer中读取数据
+    /// share_buffer: 共享缓冲区 这方式用于读小包的方案
+    fn read(&mut self, socket: &mut TcpStream, share_buffer: &mut Vec<u8>) -> ReadResult<MsgData> {
+        let mut vec_msg: Vec<MsgData> = vec![];
+        let br = &mut self.buf_reader;
+
+        loop {
+            match socket.read(share_buffer) {
+                Ok(0) => {
+                    return ReadResult::Error(vec_msg, Error::PeerClosed);
+                }
+                Ok(size) => {
+                    // 分解数据包
+                    if let Some(err) = br.split_data::<H>(&share_buffer[..size], &mut vec_msg) {
+                        return ReadResult::Error(vec_msg, err);
+                    }
+                    // 读完了TCP缓存区数据
+                    if size < share_buffer.len() {
+                        return ReadResult::Data(vec_msg);
+                    }
+                }
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => {
+                    return ReadResult::Data(vec_msg);
+                }
+                Err(ref err) if err.kind() == ErrorKind::Interrupted => {
+                    continue; //系统中断 再read一次
+                }
+                Err(err) => return ReadResult::Error(vec_msg, err.into()),
+            }
+        }
+    }
+}
+
+impl BufReader {
+    /// 把读到的数据分解成消息 不完整的包头 包体留到下次
+    fn split_data<H: FrameHead>(&mut self, buffer: &[u8], vec_msg: &mut Vec<MsgData>) -> Option<Error> {
+        let head_size = head_len::<H>(self.checksum);
+        let mut out_pos = 0;
+        loop {
+            if self.head_pos < head_size {
+                let min_len = std::cmp::min(buffer.len() - out_pos, head_size - self.head_pos);
+                self.head_data[self.head_pos..self.head_pos + min_len]
+                    .copy_from_slice(&buffer[out_pos..out_pos + min_len]);
+                self.head_pos += min_len;
+                out_pos += min_len;
+
+                //不够包头长度
+                if self.head_pos < head_size {
+                    return None;
+                }
+
+                //获取包头数据
+                let sign = read_u32(&self.head_data);
+                let (mid, msize) = ((sign & MSG_MAX_ID as u32) as u16, (sign >> 12) as usize);
+                if let Some(err) = self.check_sign_data(mid, msize) {
+                    return Some(err);
+                }
+
+                //包体没有数据
+                if msize == 0 {
+                    self.head_pos = 0;
+                    if let Some(err) = self.check_crc::<H>(&[]) {
+                        return Some(err);
+                    }
+                    if let Some(err) = self.push_msg(decode_head::<H>(&self.head_data), vec_msg) {
+                        return Some(err);
+                    }
+                    continue;
+                }
+                //分配包体内存
+                self.body_pos = 0;
+                self.body_data = vec![0u8; msize];
+            }
+
+            let min_len = std::cmp::min(buffer.len() - out_pos, self.body_data.len() - self.body_pos);
+            self.body_data[self.body_pos..self.body_pos + min_len]
+                .copy_from_slice(&buffer[out_pos..out_pos + min_len]);
+            self.body_pos += min_len;
+            out_pos += min_len;
+
+            //不够包体所需数据
+            if self.body_pos < self.body_data.len() {
+                return None;
+            }
+
+            // 分割了一个完整的包
+            self.head_pos = 0;
+            if let Some(err) = self.check_crc::<H>(&self.body_data) {
+                return Some(err);
+            }
+            let mut msg = decode_head::<H>(&self.head_data);
+            msg.buf = std::mem::take(&mut self.body_data).into();
+            if let Some(err) = self.push_msg(msg, vec_msg) {
+                return Some(err);
+            }
+        }
+    }
+
+    /// 每一帧按到达顺序解密 分片解密后交给 chunk_assembler 重组完成后再放入 vec_msg
+    /// 压缩的消息解压后再放入
+    #[inline]
+    fn push_msg(&mut self, mut msg: MsgData, vec_msg: &mut Vec<MsgData>) -> Option<Error> {
+        if let Some(err) = self.decrypt_msg(&mut msg) {
+            return Some(err);
+        }
+        if msg.pid == SProtoId::KeyExchange as u16 {
+            return None;
+        }
+        let mut max_size = MSG_MAX_SIZE;
+        if msg.pid == SProtoId::MsgChunk as u16 {
+            match self.chunk_assembler.push(&msg) {
+                Ok(Some(full_msg)) => msg = full_msg,
+                Ok(None) => return None,
+                Err(err) => return Some(err),
+            }
+            max_size = self.decompress_max_size;
+        }
+        if msg.ext & EXT_COMPRESS != 0 {
+            self.peer_compress = true;
+            if let Err(err) = msg_compress::decompress(&mut msg, max_size, &mut self.compress_stats) {
+                return Some(err);
+            }
+        }
+        vec_msg.push(msg);
+        None
+    }
+
+    /// 启用加密时 第一条消息必须是 KeyExchange 之后的消息必须加密
+    #[inline]
+    fn decrypt_msg(&mut self, msg: &mut MsgData) -> Option<Error> {
+        if let Some(crypto) = &mut self.crypto {
+            if msg.ext & EXT_ENCRYPT == 0 {
+                return Some(Error::Crypto(format!("plaintext pid:{}", msg.pid)));
+            }
+            // check_sign_data 已经把 id 加一
+            let id = if self.id == 0 { MSG_MAX_ID } else { self.id - 1 };
+            return crypto.decrypt(msg, id).err();
+        }
+        if let Some(secret) = &self.encrypt_secret {
+            match CryptoSession::server(secret, msg) {
+                Ok(crypto) => self.crypto = Some(crypto),
+                Err(err) => return Some(err),
+            }
+        } else if msg.ext & EXT_ENCRYPT != 0 {
+            return Some(Error::Crypto("encryption not enabled".into()));
+        }
+        None
+    }
+
+    /// 启用校验时 比较包头里的 CRC32C
+    #[inline]
+    fn check_crc<H: FrameHead>(&self, body: &[u8]) -> Option<Error> {
+        if !self.checksum {
+            return None;
+        }
+        let expect = read_u32(&self.head_data[H::SIZE..]);
+        let actual = crc32c::crc32c_append(crc32c::crc32c(&self.head_data[..H::SIZE]), body);
+        if expect != actual {
+            return Some(Error::Checksum(expect, actual));
+        }
+        None
+    }
+
+    /// 检查包id 及 包字节
+    #[inline]
+    fn check_sign_data(&mut self, id: u16, msg_size: usize) -> Option<Error> {
+        if id != self.id {
+            return Some(Error::Protocol("Msg Id does not match".into()));
+        }
+        self.id = next_msg_id(id);
+
+        if msg_size > MSG_MAX_SIZE {
+            return Some(Error::MsgTooLarge(msg_size));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tcp_socket_msg::{DisReason, MsgBuf, EXT_TID_MASK, EXT_VERSION_MASK, EXT_VERSION_SHIFT};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// 用批量写的接口编码成字节流
+    fn encode<H: FrameHead>(codec: &mut FrameCodec<H>, vec_msg: &[MsgData]) -> Vec<u8> {
+        let mut data = vec![];
+        for msg in vec_msg {
+            let mut head = vec![0u8; codec.head_size()];
+            codec.encode_head(msg, &mut head).unwrap();
+            data.extend_from_slice(&head);
+            data.extend_from_slice(codec.body(msg));
+        }
+        data
+    }
+
+    fn new_msg(uid: u64, pid: u16, ext: u32, size: usize) -> MsgData {
+        let mut msg = MsgData::new_uid_pid(uid, pid);
+        msg.ext = ext;
+        msg.buf = (0..size).map(|i| i as u8).collect::<Vec<u8>>().into();
+        msg
+    }
+
+    fn assert_msg(msg: &MsgData, expect: &MsgData) {
+        assert_eq!((msg.uid, msg.pid, msg.ext), (expect.uid, expect.pid, expect.ext));
+        assert_eq!(msg.buf[..], expect.buf[..]);
+    }
+
+    #[test]
+    fn test_partial_read() {
+        let vec_msg = vec![new_msg(7, 1000, 1 << 12, 300), new_msg(8, 1001, 0, 0), new_msg(u64::MAX, 1002, EXT_TID_MASK | EXT_VERSION_MASK, 1)];
+        let data = encode(&mut FrameCodec::<LanHead>::default(), &vec_msg);
+        assert_eq!(data.len(), 3 * LanHead::SIZE + 301);
+
+        // 每次读到的字节数不同 包头 包体都会被拆开
+        for step in [1, 3, 17, 18, 19, 100, data.len()] {
+            let mut codec = FrameCodec::<LanHead>::default();
+            let mut vec_read = vec![];
+            for buf in data.chunks(step) {
+                assert!(codec.buf_reader.split_data::<LanHead>(buf, &mut vec_read).is_none());
+            }
+            assert_eq!(vec_read.len(), vec_msg.len(), "step:{}", step);
+            for (msg, expect) in vec_read.iter().zip(vec_msg.iter()) {
+                assert_msg(msg, expect);
+            }
+        }
+    }
+
+    #[test]
+    fn test_split_head() {
+        let msg = new_msg(9, 1000, 5 << EXT_VERSION_SHIFT, 4);
+        let data = encode(&mut FrameCodec::<WanHead>::default(), std::slice::from_ref(&msg));
+        let mut codec = FrameCodec::<WanHead>::default();
+        let mut vec_read = vec![];
+
+        // 包头分两次到达
+        assert!(codec.buf_reader.split_data::<WanHead>(&data[..4], &mut vec_read).is_none());
+        assert_eq!((vec_read.len(), codec.buf_reader.head_pos), (0, 4));
+        assert!(codec.buf_reader.split_data::<WanHead>(&data[4..WanHead::SIZE], &mut vec_read).is_none());
+        assert!(vec_read.is_empty());
+        assert!(codec.buf_reader.split_data::<WanHead>(&data[WanHead::SIZE..], &mut vec_read).is_none());
+
+        // WanHead 不带 uid
+        assert_msg(&vec_read[0], &MsgData { uid: 0, ..msg });
+    }
+
+    #[test]
+    fn test_msg_id() {
+        let mut writer = FrameCodec::<WanHead>::default();
+        let vec_msg: Vec<MsgData> = (0..MSG_MAX_ID as usize + 3).map(|i| new_msg(0, 1000, 0, i % 3)).collect();
+        let data = encode(&mut writer, &vec_msg);
+        // 包id 0~MSG_MAX_ID 循环
+        assert_eq!(writer.buf_writer.id, 2);
+
+        let mut codec = FrameCodec::<WanHead>::default();
+        let mut vec_read = vec![];
+        assert!(codec.buf_reader.split_data::<WanHead>(&data, &mut vec_read).is_none());
+        assert_eq!((vec_read.len(), codec.buf_reader.id), (vec_msg.len(), 2));
+
+        // 包id 不连续
+        let data = encode(&mut writer, &[new_msg(0, 1000, 0, 1)]);
+        let mut codec = FrameCodec::<WanHead>::default();
+        let err = codec.buf_reader.split_data::<WanHead>(&data, &mut vec_read);
+        assert!(matches!(err, Some(Error::Protocol(_))));
+
+        // 包体超过 MSG_MAX_SIZE 包头放不下
+        let msg = new_msg(0, 1000, 0, MSG_MAX_SIZE + 1);
+        assert!(matches!(writer.encode_head(&msg, &mut [0u8; WanHead::SIZE]), Err(Error::MsgTooLarge(_))));
+    }
+
+    #[test]
+    fn test_checksum() {
+        let config = RwConfig { checksum: true, ..Default::default() };
+        let mut writer = FrameCodec::<LanHead>::default();
+        writer.set_config(&config);
+        assert_eq!(writer.head_size(), LanHead::SIZE + CHECKSUM_SIZE);
+        let mut data = encode(&mut writer, &[new_msg(1, 1000, 0, 0), new_msg(2, 1001, 0, 100)]);
+
+        let mut codec = FrameCodec::<LanHead>::default();
+        codec.set_config(&config);
+        let mut vec_read = vec![];
+        for buf in data.chunks(7) {
+            assert!(codec.buf_reader.split_data::<LanHead>(buf, &mut vec_read).is_none());
+        }
+        assert_eq!(vec_read.len(), 2);
+
+        // 包体被改了一个字节
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        let mut codec = FrameCodec::<LanHead>::default();
+        codec.set_config(&config);
+        let err = codec.buf_reader.split_data::<LanHead>(&data, &mut vec_read);
+        assert!(matches!(err, Some(Error::Checksum(..))));
+        assert_eq!(DisReason::from(&err.unwrap()), DisReason::Checksum);
+    }
+
+    #[test]
+    fn test_socket_rw() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let reader = thread::spawn(move || {
+            let (mut peer, _) = listener.accept().unwrap();
+            let mut reader = FrameCodec::<LanHead>::default();
+            let mut share_buffer = vec![0u8; 4096];
+            let mut vec_read = vec![];
+            loop {
+                match reader.read(&mut peer, &mut share_buffer) {
+                    ReadResult::Data(vec) => vec_read.extend(vec),
+                    ReadResult::Error(vec, err) => {
+                        vec_read.extend(vec);
+                        assert!(matches!(err, Error::PeerClosed));
+                        return vec_read;
+                    }
+                }
+            }
+        });
+
+        let mut socket = TcpStream::connect(addr).unwrap();
+        let mut writer = FrameCodec::<LanHead>::default();
+        // 包体字节数正好是 MSG_MAX_SIZE 时包头不会溢出
+        let vec_msg = [new_msg(1, 1000, 0, MSG_MAX_SIZE), new_msg(2, 1001, 3 << EXT_VERSION_SHIFT, 0)];
+        for msg in vec_msg.iter() {
+            let mut msg = msg.clone();
+            // 阻塞的 socket 也可能只写入一部分
+            let mut result = writer.write(&mut socket, &mut msg);
+            while result == WriteResult::BufferFull {
+                result = writer.write(&mut socket, &mut msg);
+            }
+            assert!(result == WriteResult::Finish);
+        }
+        drop(socket);
+
+        let vec_read = reader.join().unwrap();
+        assert_eq!(vec_read.len(), vec_msg.len());
+        for (msg, expect) in vec_read.iter().zip(vec_msg.iter()) {
+            assert_msg(msg, expect);
+        }
+    }
+
+    #[test]
+    fn test_compress_limit() {
+        let config = RwConfig { chunk_max_size: 4 * MSG_MAX_SIZE, compress_threshold: 512, compress_first: true, ..Default::default() };
+        let mut writer = FrameCodec::<WanHead>::default();
+        writer.set_config(&config);
+        // 共用的包体不压缩
+        let msg = writer.prepare_msg(MsgData { buf: MsgBuf::shared(vec![0u8; 1024]), ..MsgData::new_pid(1000) });
+        assert_eq!(msg.ext & EXT_COMPRESS, 0);
+        let msg = writer.prepare_msg(new_msg(0, 1000, 0, 1024));
+        assert_ne!(msg.ext & EXT_COMPRESS, 0);
+
+        // 单帧解压后超过 MSG_MAX_SIZE 启用分片也不行
+        let mut msg = new_msg(0, 1000, 0, 0);
+        msg.buf = vec![0u8; MSG_MAX_SIZE + 1].into();
+        assert!(msg_compress::compress(&mut msg, false, &mut CompressStats::default()));
+        let data = encode(&mut FrameCodec::<WanHead>::default(), &[msg]);
+        let mut reader = FrameCodec::<WanHead>::default();
+        reader.set_config(&config);
+        let mut vec_read = vec![];
+        let err = reader.buf_reader.split_data::<WanHead>(&data, &mut vec_read);
+        assert_eq!(err, Some(Error::MsgTooLarge(MSG_MAX_SIZE + 1)));
+    }
+
+    #[test]
+    fn test_seal_frame() {
+        let (secret, public) = crate::msg_crypto::gen_keypair();
+        let mut server = FrameCodec::<WanHead>::default();
+        server.set_config(&RwConfig { encrypt_secret: Some(secret), ..Default::default() });
+        // 收到 KeyExchange 之前不发明文
+        assert!(matches!(server.seal_frame(new_msg(0, 1000, 0, 3)), Err(Error::Crypto(_))));
+
+        let mut client = FrameCodec::<WanHead>::default();
+        client.set_config(&RwConfig { encrypt_server_key: Some(public), ..Default::default() });
+        let mut vec_msg = vec![client.hello_msg().unwrap()];
+        vec_msg.push(client.seal_frame(new_msg(0, 1000, 0, 3)).unwrap());
+        let data = encode(&mut client, &vec_msg);
+        let mut vec_read = vec![];
+        assert!(server.buf_reader.split_data::<WanHead>(&data, &mut vec_read).is_none());
+        assert_msg(&vec_read[0], &new_msg(0, 1000, 0, 3));
+
+        // 服务端的帧从消息id 0 开始加密
+        let vec_msg: Vec<MsgData> = (0..3).map(|i| server.seal_frame(new_msg(0, 1001, 0, i)).unwrap()).collect();
+        let data = encode(&mut server, &vec_msg);
+        let mut vec_read = vec![];
+        assert!(client.buf_reader.split_data::<WanHead>(&data, &mut vec_read).is_none());
+        assert_eq!(vec_read.len(), 3);
+        for (i, msg) in vec_read.iter().enumerate() {
+            assert_msg(msg, &new_msg(0, 1001, 0, i));
+        }
+    }
+
+    #[test]
+    fn test_chunk_compress_encrypt() {
+        use crate::msg_crypto;
+        use crate::tcp_socket::TcpSocket;
+
+        let (secret, public) = msg_crypto::gen_keypair();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let reader = thread::spawn(move || {
+            let (mut peer, _) = listener.accept().unwrap();
+            let mut reader = FrameCodec::<WanHead>::default();
+            let config = RwConfig { chunk_max_size: 4 * MSG_MAX_SIZE, encrypt_secret: Some(secret), ..Default::default() };
+            reader.set_config(&config);
+            let mut share_buffer = vec![0u8; 4096];
+            let mut vec_read = vec![];
+            loop {
+                match reader.read(&mut peer, &mut share_buffer) {
+                    ReadResult::Data(vec) => vec_read.extend(vec),
+                    ReadResult::Error(vec, err) => {
+                        vec_read.extend(vec);
+                        assert!(matches!(err, Error::PeerClosed), "{}", err);
+                        return (vec_read, reader.get_compress_stats());
+                    }
+                }
+            }
+        });
+
+        let mut writer = FrameCodec::<WanHead>::default();
+        writer.set_config(&RwConfig {
+            chunk_max_size: 4 * MSG_MAX_SIZE,
+            compress_threshold: 512,
+            compress_first: true,
+            encrypt_server_key: Some(public),
+            ..Default::default()
+        });
+        let mut tcp_socket = TcpSocket::new(TcpStream::connect(addr).unwrap(), Box::new(writer));
+        // 压缩后还超过 MSG_MAX_SIZE 的大消息
+        let mut big = new_msg(0, 1000, 0, 0);
+        big.buf = (0..3 * MSG_MAX_SIZE)
+            .map(|i| if i % 16 < 14 { ((i as u32).wrapping_mul(2654435761) >> 24) as u8 } else { 0 })
+            .collect::<Vec<u8>>()
+            .into();
+        let vec_small = [new_msg(0, 1001, 0, 3), new_msg(0, 1002, 5 << EXT_VERSION_SHIFT, 0)];
+        tcp_socket.push_vec_queue(big.clone()).unwrap();
+        for msg in vec_small.iter() {
+            tcp_socket.push_vec_queue(msg.clone()).unwrap();
+        }
+        // KeyExchange 第一个分片 小消息 小消息插在分片之间
+        assert_eq!(tcp_socket.vec_queue_len(), 4);
+        loop {
+            match tcp_socket.write() {
+                WriteResult::Finish => break,
+                WriteResult::BufferFull => continue,
+                WriteResult::Error(err) => panic!("write error:{}", err),
+            }
+        }
+        drop(tcp_socket);
+
+        // 小消息先到 大消息的分片写完后才完整
+        let (vec_read, stats) = reader.join().unwrap();
+        assert_eq!(vec_read.len(), 3);
+        assert_msg(&vec_read[0], &vec_small[0]);
+        assert_msg(&vec_read[1], &vec_small[1]);
+        assert_msg(&vec_read[2], &big);
+        assert_eq!(stats.read_raw_bytes, big.buf.len() as u64);
+        assert!(stats.read_bytes > MSG_MAX_SIZE as u64);
+    }
+}
@@ -1,5 +1,16 @@
+pub mod error;
+pub mod frame_codec;
+pub mod msg_chunk;
+pub mod msg_compress;
+pub mod msg_crypto;
 pub mod os_epoll;
+pub mod os_poll;
 pub mod os_socket;
+#[cfg(feature = "io-uring")]
+pub mod os_uring;
+pub mod proxy_protocol;
+pub mod rpc;
+pub mod slab;
 
 pub mod tcp_connect;
 pub mod tcp_connect_config;
@@ -11,3 +22,4 @@ pub mod tcp_socket;
 pub mod tcp_socket_mgmt;
 pub mod tcp_socket_rw;
 pub mod tcp_socket_msg;
+pub mod tcp_socket_option;
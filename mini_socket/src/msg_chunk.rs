@@ -0,0 +1,121 @@
+use crate::error::Error;
+use crate::tcp_socket_msg::{MsgData, SProtoId};
+use crate::frame_codec::MSG_MAX_SIZE;
+
+/// 分片包体头
+/// |pid:u16|total_size:u32|offset:u32|
+/// 分片的 MsgData.uid MsgData.ext 和原消息相同
+pub const CHUNK_HEAD_SIZE: usize = 10;
+
+/// 把大消息拆成 SProtoId::MsgChunk 分片
+/// frame_max_size: 分片包体的最大字节数 包含 CHUNK_HEAD_SIZE
+pub fn split(msg: MsgData, frame_max_size: usize) -> Vec<MsgData> {
+    let data_size = frame_max_size - CHUNK_HEAD_SIZE;
+    let total_size = msg.buf.len();
+    let mut vec_chunk = Vec::with_capacity(total_size / data_size + 1);
+    let mut offset = 0;
+    while offset < total_size {
+        let end = std::cmp::min(offset + data_size, total_size);
+        let mut buf = Vec::with_capacity(CHUNK_HEAD_SIZE + end - offset);
+        buf.extend_from_slice(&msg.pid.to_le_bytes());
+        buf.extend_from_slice(&(total_size as u32).to_le_bytes());
+        buf.extend_from_slice(&(offset as u32).to_le_bytes());
+        buf.extend_from_slice(&msg.buf[offset..end]);
+        vec_chunk.push(MsgData {
+            uid: msg.uid,
+            pid: SProtoId::MsgChunk as u16,
+            ext: msg.ext,
+            buf: buf.into(),
+        });
+        offset = end;
+    }
+    vec_chunk
+}
+
+/// 接收方重组分片
+/// 同一连接的大消息按顺序发送 同时只有一条在重组
+pub struct ChunkAssembler {
+    /// 重组后消息的最大字节数 0:不接收分片
+    max_size: usize,
+    /// 正在重组的消息
+    msg: Option<MsgData>,
+    body: Vec<u8>,
+}
+
+impl ChunkAssembler {
+    pub fn new(max_size: usize) -> Self {
+        ChunkAssembler {
+            max_size,
+            msg: None,
+            body: vec![],
+        }
+    }
+
+    #[inline]
+    pub fn set_max_size(&mut self, max_size: usize) {
+        self.max_size = max_size;
+    }
+
+    /// 放入一个分片 重组完成时返回原消息
+    pub fn push(&mut self, chunk: &MsgData) -> Result<Option<MsgData>, Error> {
+        let buf = &chunk.buf;
+        if buf.len() <= CHUNK_HEAD_SIZE {
+            return Err(Error::Protocol(format!("chunk size:{} too small", buf.len())));
+        }
+        let pid = u16::from_le_bytes([buf[0], buf[1]]);
+        let total_size = u32::from_le_bytes([buf[2], buf[3], buf[4], buf[5]]) as usize;
+        let offset = u32::from_le_bytes([buf[6], buf[7], buf[8], buf[9]]) as usize;
+        if total_size > self.max_size {
+            return Err(Error::MsgTooLarge(total_size));
+        }
+        if offset == 0 {
+            if self.msg.is_some() {
+                return Err(Error::Protocol("chunk restart before finish".into()));
+            }
+            // total_size 是对方填的 按收到的字节增长 不一次分配
+            self.body = Vec::with_capacity(std::cmp::min(total_size, MSG_MAX_SIZE));
+            self.msg = Some(MsgData {
+                uid: chunk.uid,
+                pid,
+                ext: chunk.ext,
+                buf: Default::default(),
+            });
+        }
+        match &self.msg {
+            Some(msg) if msg.pid == pid && self.body.len() == offset => {}
+            _ => return Err(Error::Protocol(format!("chunk pid:{} offset:{} mismatch", pid, offset))),
+        }
+        let data = &buf[CHUNK_HEAD_SIZE..];
+        if offset + data.len() > total_size {
+            return Err(Error::Protocol(format!("chunk offset:{} exceeds total:{}", offset, total_size)));
+        }
+        self.body.extend_from_slice(data);
+        if self.body.len() < total_size {
+            return Ok(None);
+        }
+        let mut msg = self.msg.take().unwrap();
+        msg.buf = std::mem::take(&mut self.body).into();
+        Ok(Some(msg))
+    }
+}
+
+#[test]
+fn test_chunk_split_assemble() {
+    let payload: Vec<u8> = (0..1000u32).map(|i| i as u8).collect();
+    let msg = MsgData { buf: payload.clone().into(), ext: 5, ..MsgData::new_uid_pid(9, 1000) };
+    let vec_chunk = split(msg, CHUNK_HEAD_SIZE + 300);
+    assert_eq!(vec_chunk.len(), 4);
+    assert!(vec_chunk.iter().all(|chunk| chunk.pid == SProtoId::MsgChunk as u16));
+
+    let mut assembler = ChunkAssembler::new(1000);
+    for chunk in vec_chunk[..3].iter() {
+        assert!(assembler.push(chunk).unwrap().is_none());
+    }
+    let msg = assembler.push(&vec_chunk[3]).unwrap().unwrap();
+    assert_eq!((msg.uid, msg.pid, msg.ext), (9, 1000, 5));
+    assert_eq!(&msg.buf[..], &payload[..]);
+
+    // 超过上限 分片乱序
+    assert_eq!(ChunkAssembler::new(999).push(&vec_chunk[0]).err(), Some(Error::MsgTooLarge(1000)));
+    assert!(ChunkAssembler::new(1000).push(&vec_chunk[1]).is_err());
+}
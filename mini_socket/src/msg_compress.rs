@@ -0,0 +1,61 @@
+use crate::error::Error;
+use crate::tcp_socket_msg::{MsgData, EXT_COMPRESS};
+use crate::tcp_socket_rw::CompressStats;
+
+/// 用 lz4 压缩包体 设置 EXT_COMPRESS
+/// 压缩后没有变小时不压缩 force:true 时总是压缩
+/// 返回是否压缩了
+pub fn compress(msg: &mut MsgData, force: bool, stats: &mut CompressStats) -> bool {
+    let buf = lz4_flex::compress_prepend_size(&msg.buf);
+    if !force && buf.len() >= msg.buf.len() {
+        return false;
+    }
+    stats.write_raw_bytes += msg.buf.len() as u64;
+    stats.write_bytes += buf.len() as u64;
+    msg.buf = buf.into();
+    msg.ext |= EXT_COMPRESS;
+    true
+}
+
+/// 解压有 EXT_COMPRESS 的包体 清除 EXT_COMPRESS
+/// max_size: 解压后的最大字节数
+pub fn decompress(msg: &mut MsgData, max_size: usize, stats: &mut CompressStats) -> Result<(), Error> {
+    let buf = &msg.buf;
+    if buf.len() < 4 {
+        return Err(Error::Protocol(format!("compress data size:{} too small", buf.len())));
+    }
+    // 先检查前4个字节的原始字节数 不给恶意的数据分配内存
+    let raw_size = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+    if raw_size > max_size {
+        return Err(Error::MsgTooLarge(raw_size));
+    }
+    let raw_buf = match lz4_flex::decompress_size_prepended(buf) {
+        Ok(raw_buf) => raw_buf,
+        Err(err) => return Err(Error::Protocol(format!("decompress:{}", err))),
+    };
+    stats.read_bytes += buf.len() as u64;
+    stats.read_raw_bytes += raw_buf.len() as u64;
+    msg.buf = raw_buf.into();
+    msg.ext &= !EXT_COMPRESS;
+    Ok(())
+}
+
+#[test]
+fn test_compress() {
+    let mut stats = CompressStats::default();
+    let payload = b"abcd".repeat(256);
+    let mut msg = MsgData { buf: payload.clone().into(), ..MsgData::new_pid(1000) };
+    assert!(compress(&mut msg, false, &mut stats));
+    assert_eq!(msg.ext, EXT_COMPRESS);
+    assert!(msg.buf.len() < payload.len());
+    assert!(decompress(&mut msg.clone(), 1023, &mut stats).is_err());
+    decompress(&mut msg, 1024, &mut stats).unwrap();
+    assert_eq!((msg.ext, &msg.buf[..]), (0, &payload[..]));
+    assert!(stats.write_ratio() < 0.5);
+    assert_eq!(stats.write_ratio(), stats.read_ratio());
+
+    // 没有变小不压缩
+    let mut msg = MsgData { buf: vec![1u8, 2, 3].into(), ..MsgData::new_pid(1000) };
+    assert!(!compress(&mut msg, false, &mut stats));
+    assert!(compress(&mut msg, true, &mut stats));
+}
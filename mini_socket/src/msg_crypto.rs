@@ -0,0 +1,167 @@
+use crate::error::Error;
+use crate::tcp_socket_msg::{MsgData, SProtoId, EXT_ENCRYPT};
+use chacha20poly1305::aead::{AeadInPlace, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+
+/// X25519 密钥字节数
+pub const KEY_SIZE: usize = 32;
+
+/// 加密后包体增加的 Poly1305 认证码字节数
+pub const TAG_SIZE: usize = 16;
+
+/// 生成服务端的长期密钥 (私钥, 公钥)
+/// 私钥配置在服务端 公钥配置在客户端
+pub fn gen_keypair() -> ([u8; KEY_SIZE], [u8; KEY_SIZE]) {
+    let secret = StaticSecret::random();
+    let public = PublicKey::from(&secret);
+    (secret.to_bytes(), public.to_bytes())
+}
+
+/// 连接的加密会话
+/// 客户端用临时密钥和服务端公钥协商 连接后第一条消息 SProtoId::KeyExchange 发送临时公钥
+/// 两个方向各用 HKDF-SHA256 导出的密钥 ChaCha20-Poly1305 加密 MsgData.buf
+/// nonce 由帧的12位消息id和每个方向的帧计数组成 发送方按写入顺序加密 接收方按到达顺序解密 双方计数一致
+/// 消息id 4096 帧循环一次 加上计数 nonce 在会话内不重复
+pub struct CryptoSession {
+    write_cipher: ChaCha20Poly1305,
+    read_cipher: ChaCha20Poly1305,
+    write_count: u64,
+    read_count: u64,
+}
+
+impl CryptoSession {
+    /// 客户端 返回会话和要最先发送的 KeyExchange 消息
+    pub fn client(server_key: &[u8; KEY_SIZE]) -> (Self, MsgData) {
+        let secret = EphemeralSecret::random();
+        let client_key = PublicKey::from(&secret).to_bytes();
+        let shared = secret.diffie_hellman(&PublicKey::from(*server_key));
+        let (c2s, s2c) = derive_keys(shared.as_bytes(), &client_key, server_key);
+        let mut msg = MsgData::new_pid(SProtoId::KeyExchange as u16);
+        msg.buf = client_key.to_vec().into();
+        (Self::new(&c2s, &s2c), msg)
+    }
+
+    /// 服务端 收到客户端的 KeyExchange 消息
+    pub fn server(secret: &[u8; KEY_SIZE], msg: &MsgData) -> Result<Self, Error> {
+        if msg.pid != SProtoId::KeyExchange as u16 || msg.buf.len() != KEY_SIZE {
+            return Err(Error::Crypto(format!("expect key exchange pid:{}", msg.pid)));
+        }
+        let mut client_key = [0u8; KEY_SIZE];
+        client_key.copy_from_slice(&msg.buf);
+        let secret = StaticSecret::from(*secret);
+        let server_key = PublicKey::from(&secret).to_bytes();
+        let shared = secret.diffie_hellman(&PublicKey::from(client_key));
+        if !shared.was_contributory() {
+            return Err(Error::Crypto("low order client key".into()));
+        }
+        let (c2s, s2c) = derive_keys(shared.as_bytes(), &client_key, &server_key);
+        Ok(Self::new(&s2c, &c2s))
+    }
+
+    fn new(write_key: &[u8; KEY_SIZE], read_key: &[u8; KEY_SIZE]) -> Self {
+        CryptoSession {
+            write_cipher: ChaCha20Poly1305::new(Key::from_slice(write_key)),
+            read_cipher: ChaCha20Poly1305::new(Key::from_slice(read_key)),
+            write_count: 0,
+            read_count: 0,
+        }
+    }
+
+    /// 加密包体 设置 EXT_ENCRYPT
+    /// pid ext 作为附加数据一起认证 id: 这一帧包头里的消息id
+    pub fn encrypt(&mut self, msg: &mut MsgData, id: u16) -> Result<(), Error> {
+        msg.ext |= EXT_ENCRYPT;
+        let nonce = make_nonce(id, self.write_count);
+        self.write_count += 1;
+        let mut buf = std::mem::take(&mut msg.buf).into_vec();
+        if self.write_cipher.encrypt_in_place(&nonce, &make_aad(msg), &mut buf).is_err() {
+            return Err(Error::Crypto("encrypt error".into()));
+        }
+        msg.buf = buf.into();
+        Ok(())
+    }
+
+    /// 解密有 EXT_ENCRYPT 的包体 清除 EXT_ENCRYPT
+    pub fn decrypt(&mut self, msg: &mut MsgData, id: u16) -> Result<(), Error> {
+        let nonce = make_nonce(id, self.read_count);
+        self.read_count += 1;
+        let mut buf = std::mem::take(&mut msg.buf).into_vec();
+        if self.read_cipher.decrypt_in_place(&nonce, &make_aad(msg), &mut buf).is_err() {
+            return Err(Error::Crypto(format!("decrypt pid:{} error", msg.pid)));
+        }
+        msg.buf = buf.into();
+        msg.ext &= !EXT_ENCRYPT;
+        Ok(())
+    }
+}
+
+/// 导出 (客户端到服务端, 服务端到客户端) 的密钥
+fn derive_keys(
+    shared: &[u8; KEY_SIZE],
+    client_key: &[u8; KEY_SIZE],
+    server_key: &[u8; KEY_SIZE],
+) -> ([u8; KEY_SIZE], [u8; KEY_SIZE]) {
+    let mut salt = [0u8; KEY_SIZE * 2];
+    salt[..KEY_SIZE].copy_from_slice(client_key);
+    salt[KEY_SIZE..].copy_from_slice(server_key);
+    let hkdf = Hkdf::<Sha256>::new(Some(&salt), shared);
+    let mut c2s = [0u8; KEY_SIZE];
+    let mut s2c = [0u8; KEY_SIZE];
+    // 输出长度固定32字节 不会出错
+    hkdf.expand(b"mini_socket c2s", &mut c2s).unwrap();
+    hkdf.expand(b"mini_socket s2c", &mut s2c).unwrap();
+    (c2s, s2c)
+}
+
+#[inline]
+fn make_nonce(id: u16, count: u64) -> Nonce {
+    let mut nonce = [0u8; 12];
+    nonce[..2].copy_from_slice(&id.to_le_bytes());
+    nonce[2..10].copy_from_slice(&count.to_le_bytes());
+    *Nonce::from_slice(&nonce)
+}
+
+#[inline]
+fn make_aad(msg: &MsgData) -> [u8; 6] {
+    let mut aad = [0u8; 6];
+    aad[..2].copy_from_slice(&msg.pid.to_le_bytes());
+    aad[2..].copy_from_slice(&msg.ext.to_le_bytes());
+    aad
+}
+
+#[test]
+fn test_crypto_session() {
+    let (secret, public) = gen_keypair();
+    let (mut client, hello) = CryptoSession::client(&public);
+    let mut server = CryptoSession::server(&secret, &hello).unwrap();
+
+    for i in 0..3u8 {
+        let mut msg = MsgData { buf: vec![i; 100].into(), ..MsgData::new_pid(1000) };
+        client.encrypt(&mut msg, i as u16 + 1).unwrap();
+        assert_eq!(msg.ext, EXT_ENCRYPT);
+        assert_ne!(&msg.buf[..100], &[i; 100][..]);
+        server.decrypt(&mut msg, i as u16 + 1).unwrap();
+        assert_eq!((msg.ext, &msg.buf[..]), (0, &[i; 100][..]));
+    }
+
+    let mut msg = MsgData { buf: vec![1u8; 10].into(), ..MsgData::new_pid(1000) };
+    server.encrypt(&mut msg, 0).unwrap();
+    // 重放的消息 nonce 不一致 解密失败
+    client.decrypt(&mut msg.clone(), 0).unwrap();
+    assert!(client.decrypt(&mut msg.clone(), 0).is_err());
+    // 计数一致 消息id不一致 解密失败
+    let mut msg = MsgData { buf: vec![1u8; 10].into(), ..MsgData::new_pid(1000) };
+    server.encrypt(&mut msg, 2).unwrap();
+    assert!(client.decrypt(&mut msg, 3).is_err());
+
+    // 改了 pid 解密失败
+    let (mut client, hello) = CryptoSession::client(&public);
+    let mut server = CryptoSession::server(&secret, &hello).unwrap();
+    let mut msg = MsgData { buf: vec![1u8; 10].into(), ..MsgData::new_pid(1000) };
+    server.encrypt(&mut msg, 0).unwrap();
+    msg.pid = 1001;
+    assert!(client.decrypt(&mut msg, 0).is_err());
+}
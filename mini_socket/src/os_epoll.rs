@@ -1,12 +1,89 @@
 use libc;
-use std::io::Error;
+use crate::error::Error;
 use std::os::unix::io::RawFd;
 
 const EPOLL_EVENTS: i32 = (libc::EPOLLET | libc::EPOLLERR) as i32;
 
+/// timerfd 在 epoll 中的id
+pub const TIMER_ID: u64 = u64::MAX;
+
+/// 定时任务用的 timerfd
+/// 到期后可读 OSEpoll 把它当成 TIMER_ID 的读事件
+#[derive(Debug)]
+pub(crate) struct TimerFd {
+    fd: RawFd,
+}
+
+impl Drop for TimerFd {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+impl TimerFd {
+    pub(crate) fn new() -> Result<Self, Error> {
+        // 用系统时间 和 time::timestamp() 一致
+        let flags = libc::TFD_NONBLOCK | libc::TFD_CLOEXEC;
+        let fd = unsafe { libc::timerfd_create(libc::CLOCK_REALTIME, flags) };
+        if fd == -1 {
+            return Err(Error::last_os_error().context("timerfd_create"));
+        }
+        Ok(TimerFd { fd })
+    }
+
+    #[inline]
+    pub(crate) fn fd(&self) -> RawFd {
+        self.fd
+    }
+
+    /// 设置到期时间
+    /// timestamp: 毫秒时间戳 None:停止
+    pub(crate) fn set(&self, timestamp: Option<u64>) -> Result<(), Error> {
+        let it_value = match timestamp {
+            // 0 会停止 timerfd 过期的时间用1纳秒代替
+            Some(ts) => libc::timespec {
+                tv_sec: (ts / 1000) as libc::time_t,
+                tv_nsec: std::cmp::max((ts % 1000) as libc::c_long * 1_000_000, 1),
+            },
+            None => libc::timespec {
+                tv_sec: 0,
+                tv_nsec: 0,
+            },
+        };
+        let new_value = libc::itimerspec {
+            it_interval: libc::timespec {
+                tv_sec: 0,
+                tv_nsec: 0,
+            },
+            it_value,
+        };
+        let ret = unsafe {
+            libc::timerfd_settime(self.fd, libc::TFD_TIMER_ABSTIME, &new_value, std::ptr::null_mut())
+        };
+        if ret == -1 {
+            return Err(Error::last_os_error().context("timerfd_settime"));
+        }
+        Ok(())
+    }
+
+    /// 读取到期次数 清除可读状态
+    pub(crate) fn read(&self) {
+        let mut expirations = 0u64;
+        unsafe {
+            libc::read(
+                self.fd,
+                &mut expirations as *mut u64 as *mut libc::c_void,
+                std::mem::size_of::<u64>(),
+            );
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct OSEpoll {
     fd: libc::c_int,
+    /// 到期时 epoll_wait 返回 TIMER_ID 事件
+    timer_fd: TimerFd,
 }
 
 impl Drop for OSEpoll {
@@ -18,21 +95,32 @@ impl Drop for OSEpoll {
 }
 
 impl OSEpoll {
-    pub fn new() -> Result<Self, String> {
-        let mut os_epoll = OSEpoll { fd: -1 };
-        unsafe {
-            let fd = libc::epoll_create1(0);
-            if fd != -1 {
-                os_epoll.fd = fd;
-                return Ok(os_epoll);
-            } else {
-                return Err(Error::last_os_error().to_string());
-            }
+    pub fn new() -> Result<Self, Error> {
+        let timer_fd = TimerFd::new()?;
+        let fd = unsafe { libc::epoll_create1(0) };
+        if fd == -1 {
+            return Err(Error::last_os_error());
         }
+        let os_epoll = OSEpoll { fd, timer_fd };
+        os_epoll.ctl_add_fd(TIMER_ID, os_epoll.timer_fd.fd(), libc::EPOLLIN)?;
+        Ok(os_epoll)
+    }
+
+    /// 设置 timerfd 到期时间
+    /// timestamp: 毫秒时间戳 None:停止
+    #[inline]
+    pub fn set_timer(&self, timestamp: Option<u64>) -> Result<(), Error> {
+        self.timer_fd.set(timestamp)
+    }
+
+    /// 读取 timerfd 清除到期事件
+    #[inline]
+    pub fn read_timer(&self) {
+        self.timer_fd.read()
     }
 
     #[inline]
-    pub fn ctl_add_fd(&self, id: u64, fd: RawFd, ev: i32) -> Result<(), String> {
+    pub fn ctl_add_fd(&self, id: u64, fd: RawFd, ev: i32) -> Result<(), Error> {
         let mut event = libc::epoll_event {
             u64: id,//as libc::c_ulonglong),
             events: (EPOLL_EVENTS | ev) as u32,
@@ -42,11 +130,11 @@ impl OSEpoll {
             if ret != -1 {
                 return Ok(());
             }
-            return Err(Error::last_os_error().to_string());
+            return Err(Error::last_os_error());
         }
     }
     #[inline]
-    pub fn ctl_mod_fd(&self, id: u64, fd: RawFd, ev: i32) -> Result<(), String> {
+    pub fn ctl_mod_fd(&self, id: u64, fd: RawFd, ev: i32) -> Result<(), Error> {
         let mut event = libc::epoll_event {
             u64: id,//as libc::c_ulonglong),
             events: (EPOLL_EVENTS | ev) as u32,
@@ -56,11 +144,11 @@ impl OSEpoll {
             if ret != -1 {
                 return Ok(());
             }
-            return Err(Error::last_os_error().to_string());
+            return Err(Error::last_os_error());
         }
     }
     #[inline]
-    pub fn ctl_del_fd(&self, id: u64, fd: RawFd) -> Result<(), String> {
+    pub fn ctl_del_fd(&self, id: u64, fd: RawFd) -> Result<(), Error> {
         let mut event = libc::epoll_event {
             u64: id,//as libc::c_ulonglong),
             events: 0,
@@ -71,11 +159,11 @@ impl OSEpoll {
             if ret != -1 {
                 return Ok(());
             }
-            return Err(Error::last_os_error().to_string());
+            return Err(Error::last_os_error());
         }
     }
     #[inline]
-    pub fn wait(&self, timeout: i32, events: &mut Vec<libc::epoll_event>) -> Result<u32, String> {
+    pub fn wait(&self, timeout: i32, events: &mut [libc::epoll_event]) -> Result<u32, Error> {
         unsafe {
             let ret = libc::epoll_wait(self.fd, &mut events[0], events.len() as i32, timeout);
             //println!("ret:{} epoll_event:{}", ret, mini_utils::time::timestamp());
@@ -85,7 +173,7 @@ impl OSEpoll {
             if libc::EINTR == *libc::__errno_location() {
                 return Ok(0);
             }
-            return Err(Error::last_os_error().to_string());
+            return Err(Error::last_os_error());
         }
     }
 }
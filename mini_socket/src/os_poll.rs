@@ -0,0 +1,164 @@
+use crate::error::Error;
+use crate::os_epoll::OSEpoll;
+#[cfg(feature = "io-uring")]
+use crate::os_uring::OSUring;
+use crate::tcp_socket::TcpSocket;
+use crate::tcp_socket_rw::ReadResult;
+use crate::tcp_socket_rw::WriteResult;
+use libc;
+#[cfg(feature = "io-uring")]
+use log::warn;
+use std::io;
+use std::io::Read;
+use std::net::TcpStream;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::io::RawFd;
+
+const EPOLL_IN_OUT: i32 = libc::EPOLLOUT | libc::EPOLLIN;
+
+/// TcpListenService TcpConnectService 使用的网络事件接口
+/// 开启 io-uring feature 时优先用 io_uring 内核不支持时用 epoll
+/// 连接的读写都经过 read write 由实现决定直接读写还是提交到 io_uring
+pub enum OSPoll {
+    Epoll(OSEpoll),
+    #[cfg(feature = "io-uring")]
+    Uring(Box<OSUring>),
+}
+
+macro_rules! poll_call {
+    ($self:ident, $poll:ident => $call:expr) => {
+        match $self {
+            OSPoll::Epoll($poll) => $call,
+            #[cfg(feature = "io-uring")]
+            OSPoll::Uring($poll) => $call,
+        }
+    };
+}
+
+impl OSPoll {
+    pub fn new() -> Result<Self, Error> {
+        #[cfg(feature = "io-uring")]
+        match OSUring::new() {
+            Ok(os_uring) => return Ok(OSPoll::Uring(Box::new(os_uring))),
+            Err(err) => warn!("io_uring not available use epoll:{}", err),
+        }
+        Ok(OSPoll::Epoll(OSEpoll::new()?))
+    }
+
+    /// 实际使用的实现 "epoll" 或 "io_uring"
+    pub fn name(&self) -> &'static str {
+        match self {
+            OSPoll::Epoll(_) => "epoll",
+            #[cfg(feature = "io-uring")]
+            OSPoll::Uring(_) => "io_uring",
+        }
+    }
+
+    /// 设置 timerfd 到期时间
+    /// timestamp: 毫秒时间戳 None:停止
+    #[inline]
+    pub fn set_timer(&self, timestamp: Option<u64>) -> Result<(), Error> {
+        poll_call!(self, poll => poll.set_timer(timestamp))
+    }
+
+    /// 读取 timerfd 清除到期事件
+    #[inline]
+    pub fn read_timer(&self) {
+        poll_call!(self, poll => poll.read_timer())
+    }
+
+    /// 监听 正在连接的socket 等待事件
+    #[inline]
+    pub fn ctl_add_fd(&self, id: u64, fd: RawFd, ev: i32) -> Result<(), Error> {
+        poll_call!(self, poll => poll.ctl_add_fd(id, fd, ev))
+    }
+
+    #[inline]
+    pub fn ctl_del_fd(&self, id: u64, fd: RawFd) -> Result<(), Error> {
+        poll_call!(self, poll => poll.ctl_del_fd(id, fd))
+    }
+
+    /// 加入连接 等待可读
+    /// registered: 连接时已经用 ctl_add_fd 加入
+    pub fn add_socket<MSG>(&self, cid: u64, tcp_socket: &TcpSocket<MSG>, registered: bool) -> Result<(), Error> {
+        let fd = tcp_socket.socket.as_raw_fd();
+        match self {
+            OSPoll::Epoll(os_epoll) if registered => os_epoll.ctl_mod_fd(cid, fd, libc::EPOLLIN),
+            OSPoll::Epoll(os_epoll) => os_epoll.ctl_add_fd(cid, fd, libc::EPOLLIN),
+            #[cfg(feature = "io-uring")]
+            OSPoll::Uring(os_uring) => {
+                if registered {
+                    os_uring.ctl_del_fd(cid, fd)?;
+                }
+                os_uring.add_socket(cid, tcp_socket)
+            }
+        }
+    }
+
+    /// 删除连接 要在 TcpSocket drop 之前调用
+    pub fn del_socket(&self, cid: u64, fd: RawFd) -> Result<(), Error> {
+        match self {
+            OSPoll::Epoll(os_epoll) => os_epoll.ctl_del_fd(cid, fd),
+            #[cfg(feature = "io-uring")]
+            OSPoll::Uring(os_uring) => os_uring.del_socket(cid),
+        }
+    }
+
+    /// 可读事件时读取消息
+    #[inline]
+    #[cfg_attr(not(feature = "io-uring"), allow(unused_variables))]
+    pub fn read<MSG>(&self, cid: u64, tcp_socket: &mut TcpSocket<MSG>, share_buffer: &mut Vec<u8>) -> ReadResult<MSG> {
+        match self {
+            OSPoll::Epoll(_) => tcp_socket.read(share_buffer),
+            #[cfg(feature = "io-uring")]
+            OSPoll::Uring(os_uring) => os_uring.read(cid, tcp_socket, share_buffer),
+        }
+    }
+
+    /// 不解析消息 直接读取数据 没有数据时返回 WouldBlock
+    #[inline]
+    #[cfg_attr(not(feature = "io-uring"), allow(unused_variables))]
+    pub fn recv(&self, cid: u64, socket: &mut TcpStream, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            OSPoll::Epoll(_) => socket.read(buf),
+            #[cfg(feature = "io-uring")]
+            OSPoll::Uring(os_uring) => os_uring.recv(cid, socket, buf),
+        }
+    }
+
+    /// 发送队列中的消息
+    pub fn write<MSG>(&self, cid: u64, tcp_socket: &mut TcpSocket<MSG>) -> Result<(), Error> {
+        match self {
+            OSPoll::Epoll(os_epoll) => epoll_write(os_epoll, cid, tcp_socket),
+            #[cfg(feature = "io-uring")]
+            OSPoll::Uring(os_uring) => os_uring.write(cid, tcp_socket),
+        }
+    }
+
+    /// timeout: 毫秒 -1:一直等待
+    #[inline]
+    pub fn wait(&self, timeout: i32, events: &mut [libc::epoll_event]) -> Result<u32, Error> {
+        poll_call!(self, poll => poll.wait(timeout, events))
+    }
+}
+
+/// 写不完时等待可写 写完后取消
+fn epoll_write<MSG>(os_epoll: &OSEpoll, cid: u64, tcp_socket: &mut TcpSocket<MSG>) -> Result<(), Error> {
+    match tcp_socket.write() {
+        WriteResult::Finish => {
+            if tcp_socket.epevs == libc::EPOLLIN {
+                return Ok(());
+            }
+            tcp_socket.epevs = libc::EPOLLIN;
+            os_epoll.ctl_mod_fd(cid, tcp_socket.socket.as_raw_fd(), libc::EPOLLIN)
+        }
+        WriteResult::BufferFull => {
+            if tcp_socket.epevs == EPOLL_IN_OUT {
+                return Ok(());
+            }
+            tcp_socket.epevs = EPOLL_IN_OUT;
+            os_epoll.ctl_mod_fd(cid, tcp_socket.socket.as_raw_fd(), EPOLL_IN_OUT)
+        }
+        WriteResult::Error(err) => Err(err),
+    }
+}
@@ -1,7 +1,9 @@
 use libc;
 
-use std::io::Error;
+use crate::error::Error;
+use std::io;
 use std::mem;
+use std::net::SocketAddr;
 use std::os::unix::io::RawFd;
 
 /*
@@ -15,7 +17,7 @@ pub fn bit_match(total: u8, val: u8) -> bool {
 
 /// os_socket::setsockopt(raw_fd,libc::SOL_SOCKET,libc::SO_SNDBUF, 8192)
 #[inline]
-pub fn setsockopt<T>(fd: RawFd, opt: libc::c_int, key: libc::c_int, val: T) -> Result<(), String> {
+pub fn setsockopt<T>(fd: RawFd, opt: libc::c_int, key: libc::c_int, val: T) -> Result<(), Error> {
     unsafe {
         let ret = libc::setsockopt(
             fd,
@@ -27,14 +29,14 @@ pub fn setsockopt<T>(fd: RawFd, opt: libc::c_int, key: libc::c_int, val: T) -> R
         if ret == 0 {
             return Ok(());
         } else {
-            return Err(Error::last_os_error().to_string());
+            return Err(Error::last_os_error());
         }
     }
 }
 
 //os_socket::getsockopt::<i32>(raw_fd, libc::SOL_SOCKET, libc::SO_SNDBUF)
 #[inline]
-pub fn getsockopt<T: Copy>(fd: RawFd, opt: libc::c_int, key: libc::c_int) -> Result<T, String> {
+pub fn getsockopt<T: Copy>(fd: RawFd, opt: libc::c_int, key: libc::c_int) -> Result<T, Error> {
     unsafe {
         let mut val: T = mem::zeroed();
         let ret = libc::getsockopt(
@@ -47,7 +49,83 @@ pub fn getsockopt<T: Copy>(fd: RawFd, opt: libc::c_int, key: libc::c_int) -> Res
         if ret == 0 {
             return Ok(val);
         } else {
-            return Err(Error::last_os_error().to_string());
+            return Err(Error::last_os_error());
         }
     }
 }
+
+/// 取出 socket 上待处理的错误 (SO_ERROR)
+/// EPOLLERR 时用它得到出错原因
+pub fn socket_error(fd: RawFd) -> Error {
+    match getsockopt::<i32>(fd, libc::SOL_SOCKET, libc::SO_ERROR) {
+        Ok(0) => Error::Io(io::ErrorKind::Other, "epoll error event".into()),
+        Ok(errno) => io::Error::from_raw_os_error(errno).into(),
+        Err(err) => err,
+    }
+}
+
+/// SocketAddr 转成系统的 sockaddr
+pub fn socket_addr_to_raw(addr: &SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    unsafe {
+        let mut storage: libc::sockaddr_storage = mem::zeroed();
+        let len = match addr {
+            SocketAddr::V4(addr_v4) => {
+                let sin = &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in);
+                sin.sin_family = libc::AF_INET as libc::sa_family_t;
+                sin.sin_port = addr_v4.port().to_be();
+                sin.sin_addr = libc::in_addr {
+                    s_addr: u32::from_ne_bytes(addr_v4.ip().octets()),
+                };
+                mem::size_of::<libc::sockaddr_in>()
+            }
+            SocketAddr::V6(addr_v6) => {
+                let sin6 = &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in6);
+                sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+                sin6.sin6_port = addr_v6.port().to_be();
+                sin6.sin6_addr = libc::in6_addr {
+                    s6_addr: addr_v6.ip().octets(),
+                };
+                sin6.sin6_flowinfo = addr_v6.flowinfo();
+                sin6.sin6_scope_id = addr_v6.scope_id();
+                mem::size_of::<libc::sockaddr_in6>()
+            }
+        };
+        (storage, len as libc::socklen_t)
+    }
+}
+
+/// 新建非阻塞的 tcp socket
+/// 返回的 raw_fd 要由调用者关闭
+pub fn tcp_socket(addr: &SocketAddr) -> Result<RawFd, Error> {
+    let domain = match addr {
+        SocketAddr::V4(_) => libc::AF_INET,
+        SocketAddr::V6(_) => libc::AF_INET6,
+    };
+    unsafe {
+        let fd = libc::socket(
+            domain,
+            libc::SOCK_STREAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+            0,
+        );
+        if fd == -1 {
+            return Err(Error::last_os_error());
+        }
+        Ok(fd)
+    }
+}
+
+/// 非阻塞 connect
+/// Ok(true): 已连接  Ok(false): 正在连接 等待可写后检查 SO_ERROR
+pub fn connect(fd: RawFd, addr: &SocketAddr) -> Result<bool, Error> {
+    let (storage, len) = socket_addr_to_raw(addr);
+    let ret = unsafe { libc::connect(fd, &storage as *const _ as *const libc::sockaddr, len) };
+    if ret == 0 {
+        return Ok(true);
+    }
+    let err = io::Error::last_os_error();
+    match err.raw_os_error() {
+        // 被中断的 connect 会在后台继续完成
+        Some(libc::EINPROGRESS) | Some(libc::EINTR) => Ok(false),
+        _ => Err(err.into()),
+    }
+}
@@ -0,0 +1,724 @@
+use crate::error::Error;
+use crate::os_epoll::TimerFd;
+use crate::os_epoll::TIMER_ID;
+use crate::tcp_socket::TcpSocket;
+use crate::tcp_socket::WRITEV_MAX_MSG;
+use crate::tcp_socket_rw::ReadResult;
+use crate::tcp_socket_rw::WriteResult;
+use io_uring::cqueue;
+use io_uring::opcode;
+use io_uring::register::Probe;
+use io_uring::squeue;
+use io_uring::types;
+use io_uring::IoUring;
+use libc;
+use log::error;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::io;
+use std::io::Read;
+use std::mem;
+use std::net::TcpStream;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::io::RawFd;
+use std::time::Duration;
+
+/// 提交队列长度 满了会先提交一次
+const RING_ENTRIES: u32 = 1024;
+
+/// AsyncCancel 的 user_data 完成事件直接丢弃
+const CANCEL_USER_DATA: u64 = u64::MAX;
+
+/// 每个连接 recv 的缓冲区字节数
+const RECV_BUFFER_SIZE: usize = 16384;
+
+/// 提交到 io_uring 的操作
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OpKind {
+    /// ctl_add_fd 多次触发的 PollAdd 返回 poll 的事件
+    Reg,
+    /// 连接上多次触发的 PollAdd 返回 poll 的事件
+    /// TcpSocketRw 不支持 recv writev 或等待 PROXY protocol 头时使用
+    ReadPoll,
+    Recv,
+    /// recv 返回 EAGAIN 后等待可读 再提交 recv
+    RecvPoll,
+    Writev,
+    /// writev 返回 EAGAIN 后等待可写 再提交同一个 writev
+    WritevPoll,
+    /// TcpSocketRw 不支持 writev 时等待可写 返回 EPOLLOUT
+    WritePoll,
+}
+
+#[derive(Debug)]
+struct Op {
+    id: u64,
+    kind: OpKind,
+}
+
+/// ctl_add_fd 注册的fd
+#[derive(Debug)]
+struct Reg {
+    fd: RawFd,
+    events: u32,
+    /// 当前 PollAdd 的 user_data
+    user_data: u64,
+}
+
+/// add_socket 加入的连接
+/// 缓冲区和 iovec 在操作完成前要保持有效 放在 Box 里不随 HashMap 移动
+struct UringSocket {
+    fd: RawFd,
+    /// recv writev 直接提交到 io_uring
+    completion: bool,
+    /// 正在执行的 ReadPoll Recv RecvPoll
+    read_op: Option<u64>,
+    /// 完成的 recv 结果 等待 read 解析
+    read_res: Option<i32>,
+    recv_buf: Box<[u8]>,
+    /// 正在执行的 Writev WritevPoll WritePoll
+    write_op: Option<u64>,
+    /// 完成的 writev 结果 等待 write 处理
+    write_res: Option<i32>,
+    /// 指向 TcpSocket 的包头和消息包体 writev 完成前它们不能修改
+    iovecs: Box<[libc::iovec]>,
+    iov_num: usize,
+    /// 本次 writev 的字节数
+    write_size: usize,
+}
+
+struct Ring {
+    ring: IoUring,
+    next_user_data: u64,
+    /// 已提交 还没有最后一个完成事件的操作
+    ops: HashMap<u64, Op>,
+    regs: HashMap<u64, Reg>,
+    sockets: HashMap<u64, UringSocket>,
+    /// 还没有返回给 wait 的事件
+    events: VecDeque<libc::epoll_event>,
+    vec_cqe: Vec<(u64, i32, u32)>,
+}
+
+/// io_uring 返回值转成 Result
+fn enter_result(ret: io::Result<usize>) -> Result<(), Error> {
+    match ret {
+        Ok(_) => Ok(()),
+        Err(err) => match err.raw_os_error() {
+            // 超时 系统中断 完成队列满 都要先取完成事件
+            Some(libc::ETIME) | Some(libc::EINTR) | Some(libc::EBUSY) => Ok(()),
+            _ => Err(Error::from(err).context("io_uring_enter")),
+        },
+    }
+}
+
+/// recv writev 的错误 0 表示对方已关闭
+fn res_error(res: i32) -> Error {
+    if res == 0 {
+        return Error::PeerClosed;
+    }
+    io::Error::from_raw_os_error(-res).into()
+}
+
+impl Ring {
+    fn push(&mut self, entry: &squeue::Entry) -> Result<(), Error> {
+        unsafe {
+            if self.ring.submission().push(entry).is_ok() {
+                return Ok(());
+            }
+            // 提交队列满了 先提交再放入
+            enter_result(self.ring.submit())?;
+            match self.ring.submission().push(entry) {
+                Ok(_) => Ok(()),
+                Err(_) => Err(Error::Io(io::ErrorKind::Other, "io_uring submission queue full".into())),
+            }
+        }
+    }
+
+    /// 放入提交队列 在 wait 时一起提交 返回 user_data
+    fn submit_op(&mut self, id: u64, kind: OpKind, entry: squeue::Entry) -> Result<u64, Error> {
+        self.next_user_data = self.next_user_data.wrapping_add(1);
+        if self.next_user_data == CANCEL_USER_DATA {
+            self.next_user_data = 0;
+        }
+        let user_data = self.next_user_data;
+        self.push(&entry.user_data(user_data))?;
+        self.ops.insert(user_data, Op { id, kind });
+        Ok(user_data)
+    }
+
+    fn poll_add(&mut self, id: u64, kind: OpKind, fd: RawFd, events: u32, multi: bool) -> Result<u64, Error> {
+        let entry = opcode::PollAdd::new(types::Fd(fd), events).multi(multi).build();
+        self.submit_op(id, kind, entry)
+    }
+
+    fn cancel(&mut self, user_data: u64) -> Result<(), Error> {
+        let entry = opcode::AsyncCancel::new(user_data).build().user_data(CANCEL_USER_DATA);
+        self.push(&entry)
+    }
+
+    /// 取消操作 等到它们的最后一个完成事件
+    fn cancel_wait(&mut self, vec_user_data: &[u64]) -> Result<(), Error> {
+        for user_data in vec_user_data.iter() {
+            self.cancel(*user_data)?;
+        }
+        while vec_user_data.iter().any(|user_data| self.ops.contains_key(user_data)) {
+            enter_result(self.ring.submit_and_wait(1))?;
+            self.reap();
+        }
+        Ok(())
+    }
+
+    fn recv(&mut self, id: u64) -> Result<(), Error> {
+        let entry = match self.sockets.get_mut(&id) {
+            Some(socket) => opcode::Recv::new(
+                types::Fd(socket.fd),
+                socket.recv_buf.as_mut_ptr(),
+                socket.recv_buf.len() as u32,
+            )
+            .build(),
+            None => return Ok(()),
+        };
+        let user_data = self.submit_op(id, OpKind::Recv, entry)?;
+        if let Some(socket) = self.sockets.get_mut(&id) {
+            socket.read_op = Some(user_data);
+        }
+        Ok(())
+    }
+
+    fn writev(&mut self, id: u64) -> Result<(), Error> {
+        let entry = match self.sockets.get(&id) {
+            Some(socket) => {
+                opcode::Writev::new(types::Fd(socket.fd), socket.iovecs.as_ptr(), socket.iov_num as u32)
+                    .build()
+            }
+            None => return Ok(()),
+        };
+        let user_data = self.submit_op(id, OpKind::Writev, entry)?;
+        if let Some(socket) = self.sockets.get_mut(&id) {
+            socket.write_op = Some(user_data);
+        }
+        Ok(())
+    }
+
+    /// 连接上的 PollAdd 可写的记录到 write_op 其它记录到 read_op
+    fn socket_poll(&mut self, id: u64, kind: OpKind, events: u32, multi: bool) -> Result<(), Error> {
+        let fd = match self.sockets.get(&id) {
+            Some(socket) => socket.fd,
+            None => return Ok(()),
+        };
+        let user_data = self.poll_add(id, kind, fd, events, multi)?;
+        if let Some(socket) = self.sockets.get_mut(&id) {
+            match kind {
+                OpKind::WritevPoll | OpKind::WritePoll => socket.write_op = Some(user_data),
+                _ => socket.read_op = Some(user_data),
+            }
+        }
+        Ok(())
+    }
+
+    /// 取出完成的 recv 结果
+    /// 等待 PROXY protocol 头的 ReadPoll 取消后改用 recv
+    /// 没有结果也没有正在执行的 recv 时提交 recv
+    fn take_recv(&mut self, id: u64) -> Result<Option<i32>, Error> {
+        let (read_op, read_res) = match self.sockets.get_mut(&id) {
+            Some(socket) => (socket.read_op, socket.read_res.take()),
+            None => return Err(Error::NotConnected),
+        };
+        if let Some(user_data) = read_op {
+            if let Some(Op { kind: OpKind::ReadPoll, .. }) = self.ops.get(&user_data) {
+                self.ops.remove(&user_data);
+                self.cancel(user_data)?;
+                if let Some(socket) = self.sockets.get_mut(&id) {
+                    socket.read_op = None;
+                }
+            }
+        }
+        if read_res.is_none() && self.sockets.get(&id).is_some_and(|socket| socket.read_op.is_none()) {
+            self.recv(id)?;
+        }
+        Ok(read_res)
+    }
+
+    #[inline]
+    fn push_event(&mut self, id: u64, events: u32) {
+        self.events.push_back(libc::epoll_event { events, u64: id });
+    }
+
+    /// 取出所有完成事件 更新连接的状态 需要时生成 EPOLLIN EPOLLOUT 事件
+    fn reap(&mut self) {
+        let mut vec_cqe = mem::take(&mut self.vec_cqe);
+        vec_cqe.extend(
+            self.ring
+                .completion()
+                .map(|cqe| (cqe.user_data(), cqe.result(), cqe.flags())),
+        );
+        for (user_data, res, flags) in vec_cqe.drain(..) {
+            if user_data == CANCEL_USER_DATA {
+                continue;
+            }
+            let more = cqueue::more(flags);
+            let (id, kind) = match self.ops.get(&user_data) {
+                Some(op) => (op.id, op.kind),
+                None => continue,
+            };
+            if !more {
+                self.ops.remove(&user_data);
+            }
+            if let Err(err) = self.complete(user_data, id, kind, res, more) {
+                error!("io_uring id:{} {:?} error:{}", id, kind, err);
+                self.push_event(id, libc::EPOLLERR as u32);
+            }
+        }
+        self.vec_cqe = vec_cqe;
+    }
+
+    fn complete(&mut self, user_data: u64, id: u64, kind: OpKind, res: i32, more: bool) -> Result<(), Error> {
+        if res == -libc::ECANCELED {
+            return Ok(());
+        }
+        match kind {
+            OpKind::Reg => {
+                // EPOLLIN EPOLLOUT 和 POLLIN POLLOUT 的值相同
+                let events = if res < 0 { libc::EPOLLERR as u32 } else { res as u32 };
+                self.push_event(id, events);
+                // 完成队列溢出等原因 内核结束了多次触发 重新提交
+                let (fd, events) = match self.regs.get(&id) {
+                    Some(reg) if !more && reg.user_data == user_data => (reg.fd, reg.events),
+                    _ => return Ok(()),
+                };
+                let user_data = self.poll_add(id, OpKind::Reg, fd, events, true)?;
+                if let Some(reg) = self.regs.get_mut(&id) {
+                    reg.user_data = user_data;
+                }
+            }
+            OpKind::ReadPoll => {
+                match self.sockets.get(&id) {
+                    Some(socket) if socket.read_op == Some(user_data) => (),
+                    _ => return Ok(()),
+                }
+                let events = if res < 0 { libc::EPOLLERR as u32 } else { res as u32 };
+                self.push_event(id, events);
+                if !more {
+                    self.socket_poll(id, OpKind::ReadPoll, libc::EPOLLIN as u32, true)?;
+                }
+            }
+            OpKind::Recv => {
+                let socket = match self.sockets.get_mut(&id) {
+                    Some(socket) => socket,
+                    None => return Ok(()),
+                };
+                socket.read_op = None;
+                if res == -libc::EAGAIN {
+                    self.socket_poll(id, OpKind::RecvPoll, libc::EPOLLIN as u32, false)?;
+                } else {
+                    socket.read_res = Some(res);
+                    self.push_event(id, libc::EPOLLIN as u32);
+                }
+            }
+            // 出错时由 recv writev 返回错误
+            OpKind::RecvPoll => self.recv(id)?,
+            OpKind::WritevPoll => self.writev(id)?,
+            OpKind::Writev => {
+                let socket = match self.sockets.get_mut(&id) {
+                    Some(socket) => socket,
+                    None => return Ok(()),
+                };
+                socket.write_op = None;
+                if res == -libc::EAGAIN {
+                    self.socket_poll(id, OpKind::WritevPoll, libc::EPOLLOUT as u32, false)?;
+                } else {
+                    socket.write_res = Some(res);
+                    self.push_event(id, libc::EPOLLOUT as u32);
+                }
+            }
+            OpKind::WritePoll => {
+                if let Some(socket) = self.sockets.get_mut(&id) {
+                    socket.write_op = None;
+                    self.push_event(id, libc::EPOLLOUT as u32);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// io_uring 实现的网络事件
+/// TcpSocketRw 支持 read_data 和批量写时 recv writev 提交到 io_uring
+/// 完成后返回 EPOLLIN EPOLLOUT 事件 由 read write 处理结果并提交下一次 recv writev
+/// 不支持时 和 epoll 一样等待可读可写 由 TcpSocket 读写
+/// 新的操作只放入提交队列 在 wait 时一起提交 一次取出所有完成事件
+pub struct OSUring {
+    inner: RefCell<Ring>,
+    /// 到期时 wait 返回 TIMER_ID 事件
+    timer_fd: TimerFd,
+}
+
+impl Drop for OSUring {
+    /// 等待所有操作结束 内核不再使用连接的缓冲区
+    fn drop(&mut self) {
+        let ring = self.inner.get_mut();
+        // 移出后完成事件不会再提交新的操作
+        let sockets = mem::take(&mut ring.sockets);
+        ring.regs.clear();
+        let vec_user_data: Vec<u64> = ring.ops.keys().copied().collect();
+        if let Err(err) = ring.cancel_wait(&vec_user_data) {
+            error!("io_uring drop cancel error:{}", err);
+        }
+        drop(sockets);
+    }
+}
+
+impl OSUring {
+    /// 内核不支持时返回错误 由调用者改用 OSEpoll
+    pub fn new() -> Result<Self, Error> {
+        let ring = IoUring::new(RING_ENTRIES).map_err(|err| Error::from(err).context("io_uring_setup"))?;
+        // 多次触发的 PollAdd 要 5.13 带超时的等待要 5.11
+        // 5.13 同时加入了 resource tagging 用它判断内核版本
+        let params = ring.params();
+        if !params.is_feature_resource_tagging() || !params.is_feature_ext_arg() {
+            return Err(Error::Config("io_uring need linux 5.13 or later".into()));
+        }
+        let mut probe = Probe::new();
+        if let Err(err) = ring.submitter().register_probe(&mut probe) {
+            return Err(Error::from(err).context("io_uring register_probe"));
+        }
+        for code in [opcode::PollAdd::CODE, opcode::AsyncCancel::CODE, opcode::Recv::CODE, opcode::Writev::CODE] {
+            if !probe.is_supported(code) {
+                return Err(Error::Config(format!("io_uring opcode:{} not supported", code)));
+            }
+        }
+        let os_uring = OSUring {
+            inner: RefCell::new(Ring {
+                ring,
+                next_user_data: 0,
+                ops: HashMap::new(),
+                regs: HashMap::new(),
+                sockets: HashMap::new(),
+                events: VecDeque::new(),
+                vec_cqe: vec![],
+            }),
+            timer_fd: TimerFd::new()?,
+        };
+        os_uring.ctl_add_fd(TIMER_ID, os_uring.timer_fd.fd(), libc::EPOLLIN)?;
+        Ok(os_uring)
+    }
+
+    /// 设置 timerfd 到期时间
+    /// timestamp: 毫秒时间戳 None:停止
+    #[inline]
+    pub fn set_timer(&self, timestamp: Option<u64>) -> Result<(), Error> {
+        self.timer_fd.set(timestamp)
+    }
+
+    /// 读取 timerfd 清除到期事件
+    #[inline]
+    pub fn read_timer(&self) {
+        self.timer_fd.read()
+    }
+
+    /// 监听 正在连接的socket timerfd 等待事件
+    /// 多次触发的 PollAdd 和 EPOLLET 一样 每次有新事件触发一次
+    pub fn ctl_add_fd(&self, id: u64, fd: RawFd, ev: i32) -> Result<(), Error> {
+        let mut ring = self.inner.borrow_mut();
+        if ring.regs.contains_key(&id) {
+            return Err(Error::Config(format!("io_uring id:{} exists", id)));
+        }
+        let events = ev as u32;
+        let user_data = ring.poll_add(id, OpKind::Reg, fd, events, true)?;
+        ring.regs.insert(id, Reg { fd, events, user_data });
+        Ok(())
+    }
+
+    /// PollAdd 持有文件的引用 要马上提交删除 fd 关闭时连接才会断开
+    pub fn ctl_del_fd(&self, id: u64, _fd: RawFd) -> Result<(), Error> {
+        let mut ring = self.inner.borrow_mut();
+        let reg = match ring.regs.remove(&id) {
+            Some(reg) => reg,
+            None => return Err(Error::Config(format!("io_uring id:{} not exists", id))),
+        };
+        ring.ops.remove(&reg.user_data);
+        ring.cancel(reg.user_data)?;
+        ring.events.retain(|event| event.u64 != id);
+        enter_result(ring.ring.submit())
+    }
+
+    /// 加入连接 开始 recv 或等待可读
+    pub fn add_socket<MSG>(&self, id: u64, tcp_socket: &TcpSocket<MSG>) -> Result<(), Error> {
+        let mut ring = self.inner.borrow_mut();
+        if ring.sockets.contains_key(&id) {
+            return Err(Error::Config(format!("io_uring id:{} exists", id)));
+        }
+        let completion = tcp_socket.support_uring_io();
+        let (recv_buf, iovecs) = if completion {
+            let empty_iovec = libc::iovec {
+                iov_base: std::ptr::null_mut(),
+                iov_len: 0,
+            };
+            (
+                vec![0u8; RECV_BUFFER_SIZE].into_boxed_slice(),
+                vec![empty_iovec; WRITEV_MAX_MSG * 2].into_boxed_slice(),
+            )
+        } else {
+            (Box::default(), Box::default())
+        };
+        let socket = UringSocket {
+            fd: tcp_socket.socket.as_raw_fd(),
+            completion,
+            read_op: None,
+            read_res: None,
+            recv_buf,
+            write_op: None,
+            write_res: None,
+            iovecs,
+            iov_num: 0,
+            write_size: 0,
+        };
+        ring.sockets.insert(id, socket);
+        if completion && !tcp_socket.is_proxy_header_pending() {
+            ring.recv(id)
+        } else {
+            ring.socket_poll(id, OpKind::ReadPoll, libc::EPOLLIN as u32, true)
+        }
+    }
+
+    /// 删除连接 等待正在执行的 recv writev 取消后返回
+    /// 要在 TcpSocket drop 之前调用
+    pub fn del_socket(&self, id: u64) -> Result<(), Error> {
+        let mut ring = self.inner.borrow_mut();
+        // 移出后完成事件不会再提交新的操作 等待期间保持缓冲区有效
+        let socket = match ring.sockets.remove(&id) {
+            Some(socket) => socket,
+            None => return Err(Error::NotConnected),
+        };
+        let vec_user_data: Vec<u64> = socket.read_op.iter().chain(socket.write_op.iter()).copied().collect();
+        let result = ring.cancel_wait(&vec_user_data);
+        ring.events.retain(|event| event.u64 != id);
+        drop(socket);
+        result
+    }
+
+    /// 解析完成的 recv 数据 并提交下一次 recv
+    /// recv 还没有完成时返回空的 Data
+    pub fn read<MSG>(&self, id: u64, tcp_socket: &mut TcpSocket<MSG>, share_buffer: &mut Vec<u8>) -> ReadResult<MSG> {
+        let mut ring = self.inner.borrow_mut();
+        match ring.sockets.get(&id) {
+            Some(socket) if socket.completion => (),
+            Some(_) => {
+                drop(ring);
+                return tcp_socket.read(share_buffer);
+            }
+            None => return ReadResult::Error(vec![], Error::NotConnected),
+        }
+        let res = match ring.take_recv(id) {
+            Ok(Some(res)) => res,
+            Ok(None) => return ReadResult::Data(vec![]),
+            Err(err) => return ReadResult::Error(vec![], err),
+        };
+        if res <= 0 {
+            return ReadResult::Error(vec![], res_error(res));
+        }
+        let result = match ring.sockets.get(&id) {
+            Some(socket) => tcp_socket.read_data(&socket.recv_buf[..res as usize]),
+            None => return ReadResult::Error(vec![], Error::NotConnected),
+        };
+        match result {
+            ReadResult::Data(vec_msg) => match ring.recv(id) {
+                Ok(()) => ReadResult::Data(vec_msg),
+                Err(err) => ReadResult::Error(vec_msg, err),
+            },
+            result => result,
+        }
+    }
+
+    /// 正在关闭的连接读取并丢弃数据
+    /// recv 还没有完成时返回 WouldBlock
+    pub fn recv(&self, id: u64, socket: &mut TcpStream, buf: &mut [u8]) -> io::Result<usize> {
+        let mut ring = self.inner.borrow_mut();
+        match ring.sockets.get(&id) {
+            Some(socket) if socket.completion => (),
+            Some(_) => {
+                drop(ring);
+                return socket.read(buf);
+            }
+            None => return Err(io::ErrorKind::NotConnected.into()),
+        }
+        let res = match ring.take_recv(id) {
+            Ok(Some(res)) => res,
+            Ok(None) => return Err(io::ErrorKind::WouldBlock.into()),
+            Err(err) => return Err(io::Error::other(err)),
+        };
+        if res < 0 {
+            return Err(io::Error::from_raw_os_error(-res));
+        }
+        let size = std::cmp::min(res as usize, buf.len());
+        if let Some(socket) = ring.sockets.get(&id) {
+            buf[..size].copy_from_slice(&socket.recv_buf[..size]);
+        }
+        if res > 0 {
+            if let Err(err) = ring.recv(id) {
+                return Err(io::Error::other(err));
+            }
+        }
+        Ok(size)
+    }
+
+    /// 处理完成的 writev 移除已写完的消息 队列不为空时提交下一次 writev
+    /// 上一次 writev 还没有完成时直接返回
+    pub fn write<MSG>(&self, id: u64, tcp_socket: &mut TcpSocket<MSG>) -> Result<(), Error> {
+        let mut guard = self.inner.borrow_mut();
+        let ring = &mut *guard;
+        let socket = match ring.sockets.get_mut(&id) {
+            Some(socket) => socket,
+            None => return Err(Error::NotConnected),
+        };
+        if !socket.completion {
+            return match tcp_socket.write() {
+                WriteResult::Finish => Ok(()),
+                WriteResult::BufferFull if socket.write_op.is_none() => {
+                    ring.socket_poll(id, OpKind::WritePoll, libc::EPOLLOUT as u32, false)
+                }
+                WriteResult::BufferFull => Ok(()),
+                WriteResult::Error(err) => Err(err),
+            };
+        }
+        if socket.write_op.is_some() {
+            return Ok(());
+        }
+        if let Some(res) = socket.write_res.take() {
+            if res < 0 || (res == 0 && socket.write_size > 0) {
+                return Err(res_error(res));
+            }
+            tcp_socket.writev_done(res as usize)?;
+        }
+        let (iov_num, write_size) = tcp_socket.fill_iovecs(&mut socket.iovecs)?;
+        if iov_num == 0 {
+            return Ok(());
+        }
+        socket.iov_num = iov_num;
+        socket.write_size = write_size;
+        ring.writev(id)
+    }
+
+    /// 提交所有新的操作 等待完成事件
+    /// 上次还有没取完的事件时不等待
+    /// timeout: 毫秒 -1:一直等待
+    pub fn wait(&self, timeout: i32, events: &mut [libc::epoll_event]) -> Result<u32, Error> {
+        let mut guard = self.inner.borrow_mut();
+        let ring = &mut *guard;
+        let submitter = ring.ring.submitter();
+        let ret = if timeout == 0 || !ring.events.is_empty() {
+            submitter.submit()
+        } else if timeout < 0 {
+            submitter.submit_and_wait(1)
+        } else {
+            let ts = types::Timespec::from(Duration::from_millis(timeout as u64));
+            let args = types::SubmitArgs::new().timespec(&ts);
+            submitter.submit_with_args(1, &args)
+        };
+        enter_result(ret)?;
+        ring.reap();
+
+        // 超过 events.len() 的留到下次
+        let num = std::cmp::min(events.len(), ring.events.len());
+        for (event, ev) in events.iter_mut().zip(ring.events.drain(..num)) {
+            *event = ev;
+        }
+        Ok(num as u32)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::frame_codec::{FrameCodec, LanHead};
+    use crate::os_epoll::TIMER_ID;
+    use crate::os_uring::OSUring;
+    use crate::tcp_socket::TcpSocket;
+    use crate::tcp_socket_msg::MsgData;
+    use crate::tcp_socket_rw::ReadResult;
+    use std::net::{TcpListener, TcpStream};
+    use std::os::unix::io::AsRawFd;
+
+    /// 等到 id 的事件 返回事件
+    fn wait_event(os_uring: &OSUring, id: u64) -> u32 {
+        let mut events = vec![libc::epoll_event { events: 0, u64: 0 }; 8];
+        for _ in 0..100 {
+            let num = os_uring.wait(100, &mut events).unwrap();
+            if let Some(event) = events[..num as usize].iter().find(|event| event.u64 == id) {
+                return event.events;
+            }
+        }
+        panic!("wait id:{} timeout", id);
+    }
+
+    #[test]
+    fn test_uring_echo() {
+        let os_uring = match OSUring::new() {
+            Ok(os_uring) => os_uring,
+            // 内核不支持或被禁用
+            Err(_) => return,
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.set_nonblocking(true).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        server.set_nonblocking(true).unwrap();
+        let mut client = TcpSocket::new(client, Box::new(FrameCodec::<LanHead>::default()));
+        let mut server = TcpSocket::new(server, Box::new(FrameCodec::<LanHead>::default()));
+        os_uring.add_socket(1, &client).unwrap();
+        os_uring.add_socket(2, &server).unwrap();
+
+        // 大于 recv 缓冲区 要分多次 recv 和 writev
+        for pid in 1000..1100 {
+            let mut msg = MsgData::new_uid_pid(7, pid);
+            msg.buf = vec![(pid % 251) as u8; 1000].into();
+            client.push_vec_queue(msg).unwrap();
+        }
+        os_uring.write(1, &mut client).unwrap();
+        let mut vec_msg = vec![];
+        let mut share_buffer = vec![];
+        while vec_msg.len() < 100 {
+            if wait_event(&os_uring, 2) & libc::EPOLLIN as u32 == 0 {
+                continue;
+            }
+            match os_uring.read(2, &mut server, &mut share_buffer) {
+                ReadResult::Data(vec) => vec_msg.extend(vec),
+                ReadResult::Error(_, err) => panic!("read error:{}", err),
+            }
+            os_uring.write(1, &mut client).unwrap();
+        }
+        for (msg, pid) in vec_msg.iter().zip(1000..1100) {
+            assert_eq!(msg.pid, pid);
+            assert!(msg.buf[..] == vec![(pid % 251) as u8; 1000][..]);
+        }
+        assert_eq!(client.vec_queue_len(), 0);
+        assert_eq!(client.get_stats().write_msgs, 100);
+        assert_eq!(server.get_stats().read_msgs, 100);
+
+        // 对方关闭
+        os_uring.del_socket(1).unwrap();
+        drop(client);
+        loop {
+            wait_event(&os_uring, 2);
+            match os_uring.read(2, &mut server, &mut share_buffer) {
+                ReadResult::Data(vec) => assert!(vec.is_empty()),
+                ReadResult::Error(_, err) => {
+                    assert_eq!(err, crate::error::Error::PeerClosed);
+                    break;
+                }
+            }
+        }
+        os_uring.del_socket(2).unwrap();
+        assert!(os_uring.del_socket(2).is_err());
+
+        os_uring.ctl_add_fd(3, server.socket.as_raw_fd(), libc::EPOLLOUT).unwrap();
+        assert!(wait_event(&os_uring, 3) & libc::EPOLLOUT as u32 != 0);
+        os_uring.ctl_del_fd(3, server.socket.as_raw_fd()).unwrap();
+
+        let timestamp = mini_utils::time::timestamp() + 10;
+        os_uring.set_timer(Some(timestamp)).unwrap();
+        wait_event(&os_uring, TIMER_ID);
+        os_uring.read_timer();
+        assert!(mini_utils::time::timestamp() >= timestamp);
+    }
+}
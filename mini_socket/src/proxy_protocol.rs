@@ -0,0 +1,168 @@
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+use std::net::SocketAddr;
+
+/// v2 协议签名
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+/// v2 固定头长度: 签名12 + ver_cmd1 + fam1 + len2
+const V2_HEAD_SIZE: usize = 16;
+/// v1 头最大长度(包括\r\n)
+const V1_MAX_SIZE: usize = 107;
+
+/// 一次最多需要的字节数
+pub const PROXY_HEADER_MAX_SIZE: usize = V2_HEAD_SIZE + u16::MAX as usize;
+
+#[derive(Debug, PartialEq)]
+pub enum ProxyHeader {
+    /// 数据不完整 要等更多数据
+    Incomplete,
+    /// 头的字节数, 客户端真实地址
+    /// LOCAL 命令或 UNKNOWN 协议时地址为None 使用连接的地址
+    Complete(usize, Option<SocketAddr>),
+}
+
+/// 解析 HAProxy PROXY protocol v1 v2 头
+/// buf 是连接最前面的数据
+pub fn parse(buf: &[u8]) -> Result<ProxyHeader, String> {
+    if buf.is_empty() {
+        return Ok(ProxyHeader::Incomplete);
+    }
+    if buf[0] == V2_SIGNATURE[0] {
+        return parse_v2(buf);
+    }
+    parse_v1(buf)
+}
+
+/// PROXY TCP4 255.255.255.255 255.255.255.255 65535 65535\r\n
+fn parse_v1(buf: &[u8]) -> Result<ProxyHeader, String> {
+    let prefix = b"PROXY ";
+    let n = std::cmp::min(buf.len(), prefix.len());
+    if buf[..n] != prefix[..n] {
+        return Err("proxy protocol header error".into());
+    }
+    let end = match buf.windows(2).position(|w| w == b"\r\n") {
+        Some(pos) => pos,
+        None => {
+            if buf.len() >= V1_MAX_SIZE {
+                return Err("proxy protocol v1 header too long".into());
+            }
+            return Ok(ProxyHeader::Incomplete);
+        }
+    };
+    if end + 2 > V1_MAX_SIZE {
+        return Err("proxy protocol v1 header too long".into());
+    }
+    let line = match std::str::from_utf8(&buf[prefix.len()..end]) {
+        Ok(line) => line,
+        Err(err) => return Err(format!("proxy protocol v1 error:{}", err)),
+    };
+    let vec_field: Vec<&str> = line.split(' ').collect();
+    match vec_field[0] {
+        "UNKNOWN" => Ok(ProxyHeader::Complete(end + 2, None)),
+        "TCP4" | "TCP6" => {
+            if vec_field.len() != 5 {
+                return Err(format!("proxy protocol v1 error:{}", line));
+            }
+            let ip = match vec_field[1].parse::<IpAddr>() {
+                Ok(ip) => ip,
+                Err(err) => return Err(format!("proxy protocol v1 ip:{} {}", vec_field[1], err)),
+            };
+            if ip.is_ipv4() != (vec_field[0] == "TCP4") {
+                return Err(format!("proxy protocol v1 error:{}", line));
+            }
+            let port = match vec_field[3].parse::<u16>() {
+                Ok(port) => port,
+                Err(err) => return Err(format!("proxy protocol v1 port:{} {}", vec_field[3], err)),
+            };
+            Ok(ProxyHeader::Complete(end + 2, Some(SocketAddr::new(ip, port))))
+        }
+        _ => Err(format!("proxy protocol v1 error:{}", line)),
+    }
+}
+
+/// |signature:12|ver_cmd:1|fam:1|len:u16(大端)|addr:len|
+fn parse_v2(buf: &[u8]) -> Result<ProxyHeader, String> {
+    let n = std::cmp::min(buf.len(), V2_SIGNATURE.len());
+    if buf[..n] != V2_SIGNATURE[..n] {
+        return Err("proxy protocol header error".into());
+    }
+    if buf.len() < V2_HEAD_SIZE {
+        return Ok(ProxyHeader::Incomplete);
+    }
+    let ver_cmd = buf[12];
+    if ver_cmd >> 4 != 2 {
+        return Err(format!("proxy protocol v2 version:{}", ver_cmd >> 4));
+    }
+    let size = V2_HEAD_SIZE + u16::from_be_bytes([buf[14], buf[15]]) as usize;
+    if buf.len() < size {
+        return Ok(ProxyHeader::Incomplete);
+    }
+    let addr = &buf[V2_HEAD_SIZE..size];
+    match ver_cmd & 0x0F {
+        // LOCAL 负载均衡自己的连接(健康检查)
+        0 => Ok(ProxyHeader::Complete(size, None)),
+        1 => match buf[13] {
+            // TCP over IPv4
+            0x11 => {
+                if addr.len() < 12 {
+                    return Err("proxy protocol v2 ipv4 addr size error".into());
+                }
+                let ip = Ipv4Addr::new(addr[0], addr[1], addr[2], addr[3]);
+                let port = u16::from_be_bytes([addr[8], addr[9]]);
+                Ok(ProxyHeader::Complete(size, Some(SocketAddr::new(IpAddr::V4(ip), port))))
+            }
+            // TCP over IPv6
+            0x21 => {
+                if addr.len() < 36 {
+                    return Err("proxy protocol v2 ipv6 addr size error".into());
+                }
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(&addr[0..16]);
+                let ip = Ipv6Addr::from(octets);
+                let port = u16::from_be_bytes([addr[32], addr[33]]);
+                Ok(ProxyHeader::Complete(size, Some(SocketAddr::new(IpAddr::V6(ip), port))))
+            }
+            _ => Ok(ProxyHeader::Complete(size, None)),
+        },
+        cmd => Err(format!("proxy protocol v2 command:{}", cmd)),
+    }
+}
+
+#[test]
+fn test_parse_v1() {
+    let buf = b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\nGET /";
+    let addr = "192.168.0.1:56324".parse().ok();
+    assert_eq!(parse(buf), Ok(ProxyHeader::Complete(47, addr)));
+
+    let buf = b"PROXY TCP6 ::1 ::1 56324 443\r\n";
+    let addr = "[::1]:56324".parse().ok();
+    assert_eq!(parse(buf), Ok(ProxyHeader::Complete(buf.len(), addr)));
+
+    assert_eq!(parse(b"PROXY UNKNOWN\r\n"), Ok(ProxyHeader::Complete(15, None)));
+    assert_eq!(parse(b"PROX"), Ok(ProxyHeader::Incomplete));
+    assert_eq!(parse(b"PROXY TCP4 192.168"), Ok(ProxyHeader::Incomplete));
+    assert!(parse(b"GET / HTTP/1.1\r\n").is_err());
+    assert!(parse(b"PROXY TCP4 ::1 ::1 56324 443\r\n").is_err());
+}
+
+#[test]
+fn test_parse_v2() {
+    let mut buf = V2_SIGNATURE.to_vec();
+    buf.extend_from_slice(&[0x21, 0x11, 0, 12]);
+    buf.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2]);
+    buf.extend_from_slice(&56324u16.to_be_bytes());
+    buf.extend_from_slice(&443u16.to_be_bytes());
+    buf.extend_from_slice(b"data");
+    let addr = "10.0.0.1:56324".parse().ok();
+    assert_eq!(parse(&buf), Ok(ProxyHeader::Complete(28, addr)));
+    assert_eq!(parse(&buf[..20]), Ok(ProxyHeader::Incomplete));
+
+    // LOCAL
+    let mut buf = V2_SIGNATURE.to_vec();
+    buf.extend_from_slice(&[0x20, 0x00, 0, 0]);
+    assert_eq!(parse(&buf), Ok(ProxyHeader::Complete(16, None)));
+
+    buf[12] = 0x13;
+    assert!(parse(&buf).is_err());
+}
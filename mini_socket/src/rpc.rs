@@ -0,0 +1,225 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use log::warn;
+use mini_utils::wtimer::{IWTask, WTimer};
+
+use crate::tcp_connect_service::TcpConnectService;
+use crate::tcp_listen_service::TcpListenService;
+use crate::tcp_socket_rw::TcpSocketRw;
+use crate::tcp_socket_msg::{MsgData, EXT_REPLY, EXT_RPC_ERROR, MAX_TID};
+
+/// rpc请求失败的原因
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RpcError {
+    /// 超时没有收到回复
+    Timeout,
+    /// 连接断开 见 Rpc::cancel_all
+    Closed,
+    /// 对方用 reply_error 回复 (错误码, 错误信息)
+    Remote(u16, String),
+}
+
+pub type RpcCallback = Box<dyn FnOnce(Result<MsgData, RpcError>)>;
+
+/// 能放入超时任务的定时器
+/// TcpConnectService TcpListenService 放入后会重新设置 timerfd
+pub trait RpcTimer {
+    fn schedule(&mut self, delay: u64, task: Box<dyn IWTask>);
+}
+
+impl RpcTimer for WTimer {
+    fn schedule(&mut self, delay: u64, task: Box<dyn IWTask>) {
+        self.push_task(delay, delay, task);
+    }
+}
+
+impl<TBRW, MSG> RpcTimer for TcpConnectService<'_, TBRW, MSG>
+where
+    TBRW: TcpSocketRw<MSG> + Default + 'static,
+{
+    fn schedule(&mut self, delay: u64, task: Box<dyn IWTask>) {
+        TcpConnectService::schedule(self, delay, delay, task);
+    }
+}
+
+impl<TBRW, MSG> RpcTimer for TcpListenService<'_, TBRW, MSG>
+where
+    TBRW: TcpSocketRw<MSG> + Default + 'static,
+{
+    fn schedule(&mut self, delay: u64, task: Box<dyn IWTask>) {
+        TcpListenService::schedule(self, delay, delay, task);
+    }
+}
+
+struct Pending {
+    /// 请求序号 事务id回绕后区分新旧请求
+    seq: u64,
+    cb: RpcCallback,
+}
+
+#[derive(Default)]
+struct RpcInner {
+    next_tid: u32,
+    next_seq: u64,
+    pending: HashMap<u32, Pending>,
+}
+
+/// 在 MsgData 上的请求/回复
+/// 请求的 ext 带事务id 回复带相同的 pid 事务id和 EXT_REPLY
+/// 回复和请求的 pid 相同 proxy 像普通消息一样转发
+/// 单线程使用 回调在 on_msg 或定时器里执行
+#[derive(Default, Clone)]
+pub struct Rpc {
+    inner: Rc<RefCell<RpcInner>>,
+}
+
+impl Rpc {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 等待回复的请求数
+    pub fn pending_num(&self) -> usize {
+        self.inner.borrow().pending.len()
+    }
+
+    /// 生成请求消息 返回的消息由调用方发送
+    /// timeout(毫秒) 超时后 cb 收到 RpcError::Timeout
+    pub fn request<T, F>(&self, timer: &mut T, mut msg: MsgData, timeout: u64, cb: F) -> MsgData
+    where
+        T: RpcTimer + ?Sized,
+        F: FnOnce(Result<MsgData, RpcError>) + 'static,
+    {
+        let (tid, seq) = {
+            let mut inner = self.inner.borrow_mut();
+            let mut tid = inner.next_tid;
+            loop {
+                tid = if tid >= MAX_TID { 1 } else { tid + 1 };
+                if !inner.pending.contains_key(&tid) {
+                    break;
+                }
+            }
+            inner.next_tid = tid;
+            inner.next_seq += 1;
+            let seq = inner.next_seq;
+            inner.pending.insert(tid, Pending { seq, cb: Box::new(cb) });
+            (tid, seq)
+        };
+        timer.schedule(timeout, Box::new(RpcTimeout { inner: self.inner.clone(), tid, seq }));
+
+        msg.ext &= !(EXT_REPLY | EXT_RPC_ERROR);
+        msg.set_tid(tid);
+        msg
+    }
+
+    /// 处理收到的消息
+    /// 是等待中的请求的回复时执行回调 返回 None
+    /// 不是回复时原样返回 由调用方继续处理
+    pub fn on_msg(&self, msg: MsgData) -> Option<MsgData> {
+        if !msg.is_reply() || msg.get_tid() == 0 {
+            return Some(msg);
+        }
+        let pending = self.inner.borrow_mut().pending.remove(&msg.get_tid());
+        match pending {
+            Some(pending) => {
+                if msg.is_rpc_error() {
+                    (pending.cb)(Err(decode_error(&msg)));
+                } else {
+                    (pending.cb)(Ok(msg));
+                }
+            }
+            None => {
+                warn!("rpc reply tid:{} pid:{} not pending", msg.get_tid(), msg.pid);
+            }
+        }
+        None
+    }
+
+    /// 连接断开 所有等待中的请求回调 RpcError::Closed
+    pub fn cancel_all(&self) {
+        let pending: Vec<Pending> = self.inner.borrow_mut().pending.drain().map(|(_, p)| p).collect();
+        for p in pending {
+            (p.cb)(Err(RpcError::Closed));
+        }
+    }
+}
+
+/// 请求的回复 uid pid 事务id和请求相同
+pub fn reply(req: &MsgData, buf: Vec<u8>) -> MsgData {
+    let mut msg = MsgData { uid: req.uid, pid: req.pid, ext: EXT_REPLY, buf: buf.into() };
+    msg.set_tid(req.get_tid());
+    msg
+}
+
+/// 请求处理失败的回复 uid pid 事务id和请求相同 带 EXT_RPC_ERROR
+/// MsgData.buf(|code:u16|msg:utf8|)
+pub fn reply_error(req: &MsgData, code: u16, err: &str) -> MsgData {
+    let mut buf = Vec::with_capacity(2 + err.len());
+    buf.extend_from_slice(&code.to_le_bytes());
+    buf.extend_from_slice(err.as_bytes());
+    let mut msg = MsgData { uid: req.uid, pid: req.pid, ext: EXT_REPLY | EXT_RPC_ERROR, buf: buf.into() };
+    msg.set_tid(req.get_tid());
+    msg
+}
+
+fn decode_error(msg: &MsgData) -> RpcError {
+    if msg.buf.len() < 2 {
+        return RpcError::Remote(0, String::new());
+    }
+    let code = u16::from_le_bytes([msg.buf[0], msg.buf[1]]);
+    RpcError::Remote(code, String::from_utf8_lossy(&msg.buf[2..]).into_owned())
+}
+
+/// 请求超时任务
+/// WTimer 不能取消任务 到期时请求已回复就什么都不做
+struct RpcTimeout {
+    inner: Rc<RefCell<RpcInner>>,
+    tid: u32,
+    seq: u64,
+}
+
+impl IWTask for RpcTimeout {
+    fn execute(&mut self) -> bool {
+        let pending = {
+            let mut inner = self.inner.borrow_mut();
+            match inner.pending.get(&self.tid) {
+                Some(p) if p.seq == self.seq => inner.pending.remove(&self.tid),
+                _ => None,
+            }
+        };
+        if let Some(pending) = pending {
+            (pending.cb)(Err(RpcError::Timeout));
+        }
+        true
+    }
+}
+
+#[test]
+fn test_rpc() {
+    let rpc = Rpc::new();
+    let mut wtimer = WTimer::new(1);
+    let result: Rc<RefCell<Vec<Result<MsgData, RpcError>>>> = Rc::default();
+
+    let mut vec_req = Vec::new();
+    for _ in 0..3 {
+        let result = result.clone();
+        let req = rpc.request(&mut wtimer, MsgData::new_uid_pid(9, 1000), 10, move |r| result.borrow_mut().push(r));
+        vec_req.push(req);
+    }
+    assert_eq!(rpc.pending_num(), 3);
+    assert_eq!(vec_req.iter().map(|m| m.get_tid()).collect::<Vec<_>>(), vec![1, 2, 3]);
+    assert!(rpc.on_msg(vec_req[0].clone()).is_some());
+
+    assert!(rpc.on_msg(reply(&vec_req[0], vec![1, 2])).is_none());
+    assert!(rpc.on_msg(reply_error(&vec_req[1], 404, "not found")).is_none());
+    assert!(rpc.on_msg(reply(&vec_req[0], vec![1, 2])).is_none());
+    wtimer.scheduled(mini_utils::time::timestamp() + 100);
+
+    let result = result.borrow();
+    assert_eq!(result[0].as_ref().map(|m| &m.buf[..]).ok(), Some(&[1u8, 2][..]));
+    assert_eq!(result[1].as_ref().err(), Some(&RpcError::Remote(404, "not found".into())));
+    assert_eq!(result[2].as_ref().err(), Some(&RpcError::Timeout));
+    assert_eq!((result.len(), rpc.pending_num(), wtimer.task_num()), (3, 0, 0));
+}
@@ -0,0 +1,129 @@
+/// id 的高32位是 generation 低32位是下标
+const GENERATION_SHIFT: u64 = 32;
+
+struct Slot<T> {
+    /// 每次删除后加1 旧的id就找不到新的数据
+    generation: u32,
+    value: Option<T>,
+}
+
+/// 按下标存放的数组 删除的位置会重用
+/// id = generation << 32 | index, generation 从1开始 id 不会小于 1 << 32
+/// 查找 O(1) 不需要hash
+pub struct Slab<T> {
+    vec_slot: Vec<Slot<T>>,
+    /// 空闲的下标
+    vec_free: Vec<u32>,
+    len: usize,
+}
+
+impl<T> Slab<T> {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Slab {
+            vec_slot: Vec::with_capacity(capacity),
+            vec_free: vec![],
+            len: 0,
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[inline]
+    fn split_id(id: u64) -> (usize, u32) {
+        ((id as u32) as usize, (id >> GENERATION_SHIFT) as u32)
+    }
+
+    /// 返回数据的id
+    pub fn insert(&mut self, value: T) -> u64 {
+        self.len += 1;
+        if let Some(index) = self.vec_free.pop() {
+            let slot = &mut self.vec_slot[index as usize];
+            slot.value = Some(value);
+            return (slot.generation as u64) << GENERATION_SHIFT | index as u64;
+        }
+        let index = self.vec_slot.len() as u64;
+        self.vec_slot.push(Slot {
+            generation: 1,
+            value: Some(value),
+        });
+        1 << GENERATION_SHIFT | index
+    }
+
+    #[inline]
+    pub fn get(&self, id: u64) -> Option<&T> {
+        let (index, generation) = Self::split_id(id);
+        match self.vec_slot.get(index) {
+            Some(slot) if slot.generation == generation => slot.value.as_ref(),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self, id: u64) -> Option<&mut T> {
+        let (index, generation) = Self::split_id(id);
+        match self.vec_slot.get_mut(index) {
+            Some(slot) if slot.generation == generation => slot.value.as_mut(),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    pub fn contains(&self, id: u64) -> bool {
+        self.get(id).is_some()
+    }
+
+    pub fn remove(&mut self, id: u64) -> Option<T> {
+        let (index, generation) = Self::split_id(id);
+        let slot = match self.vec_slot.get_mut(index) {
+            Some(slot) if slot.generation == generation => slot,
+            _ => return None,
+        };
+        let value = slot.value.take()?;
+        // generation 不能为0
+        slot.generation = slot.generation.checked_add(1).unwrap_or(1);
+        self.vec_free.push(index as u32);
+        self.len -= 1;
+        Some(value)
+    }
+
+    /// 遍历所有数据 (id, value)
+    pub fn iter(&self) -> impl Iterator<Item = (u64, &T)> {
+        self.vec_slot.iter().enumerate().filter_map(|(index, slot)| {
+            slot.value
+                .as_ref()
+                .map(|value| ((slot.generation as u64) << GENERATION_SHIFT | index as u64, value))
+        })
+    }
+}
+
+#[test]
+fn test_slab_generation() {
+    let mut slab = Slab::with_capacity(2);
+    let id1 = slab.insert(1);
+    let id2 = slab.insert(2);
+    assert!(id1 >= 1 << GENERATION_SHIFT);
+    assert_eq!(slab.remove(id1), Some(1));
+    assert_eq!(slab.remove(id1), None);
+
+    // 重用下标 旧的id找不到新的数据
+    let id3 = slab.insert(3);
+    assert_eq!(id3 as u32, id1 as u32);
+    assert_ne!(id3, id1);
+    assert_eq!(slab.get(id1), None);
+    assert_eq!(slab.get(id3), Some(&3));
+    assert_eq!(slab.len(), 2);
+
+    let mut vec_id: Vec<u64> = slab.iter().map(|(id, _)| id).collect();
+    vec_id.sort();
+    let mut expect = vec![id2, id3];
+    expect.sort();
+    assert_eq!(vec_id, expect);
+}
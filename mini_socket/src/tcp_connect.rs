@@ -1,22 +1,56 @@
 use crate::tcp_connect_config::TcpConnectConfig;
 use crate::tcp_socket::TcpSocket;
-use std::cell::Cell;
+use std::net::SocketAddr;
+use std::net::TcpStream;
+
+/// 连接状态
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ConnectState {
+    /// 等待到 next_connect_timestamp 再连接
+    Wait,
+    /// 在解析线程中解析域名 结果在 tick 中处理
+    Resolving,
+    /// 非阻塞 connect 已发出 等待 EPOLLOUT
+    Connecting,
+    /// 连接成功
+    Connected,
+}
 
 pub struct TcpConnect<MSG> {
     cid: u64,
     config: TcpConnectConfig,
-    last_reconnect_timestamp: Cell<u64>,
+    state: ConnectState,
+    /// 开始连接的时间戳
+    connect_timestamp: u64,
+    /// 下次连接的时间戳
+    next_connect_timestamp: u64,
+    /// 连续失败次数 用于计算重连间隔
+    retry_num: u32,
+    /// 本轮域名解析的编号 丢弃过期的解析结果
+    resolve_id: u64,
+    /// 本轮解析出的地址
+    vec_addr: Vec<SocketAddr>,
+    /// 本轮正在尝试的地址下标
+    addr_idx: usize,
+    /// 正在连接的 socket
+    connecting_socket: Option<TcpStream>,
     tcp_socket_opt: Option<TcpSocket<MSG>>,
-    /////TcpSocket断开后可以先把数据存放到这里
 }
 
 impl<MSG> TcpConnect<MSG> {
-    pub fn new(cid: u64, config: TcpConnectConfig, tcp_socket: Option<TcpSocket<MSG>>) -> Self {
+    pub fn new(cid: u64, config: TcpConnectConfig) -> Self {
         TcpConnect {
             cid,
             config,
-            tcp_socket_opt: tcp_socket,
-            last_reconnect_timestamp: Cell::new(0),
+            state: ConnectState::Wait,
+            connect_timestamp: 0,
+            next_connect_timestamp: 0,
+            retry_num: 0,
+            resolve_id: 0,
+            vec_addr: vec![],
+            addr_idx: 0,
+            connecting_socket: None,
+            tcp_socket_opt: None,
         }
     }
     #[inline]
@@ -29,16 +63,83 @@ impl<MSG> TcpConnect<MSG> {
         &self.config
     }
 
-    /// 最后重连时间戳
     #[inline]
-    pub fn get_last_reconnect_timestamp(&self) -> u64 {
-        self.last_reconnect_timestamp.get()
+    pub fn get_state(&self) -> ConnectState {
+        self.state
+    }
+
+    #[inline]
+    pub fn set_state(&mut self, state: ConnectState) {
+        self.state = state;
+    }
+
+    /// 开始连接的时间戳
+    #[inline]
+    pub fn get_connect_timestamp(&self) -> u64 {
+        self.connect_timestamp
+    }
+
+    #[inline]
+    pub fn set_connect_timestamp(&mut self, timestamp: u64) {
+        self.connect_timestamp = timestamp;
+    }
+
+    /// 下次连接的时间戳
+    #[inline]
+    pub fn get_next_connect_timestamp(&self) -> u64 {
+        self.next_connect_timestamp
+    }
+
+    #[inline]
+    pub fn set_next_connect_timestamp(&mut self, timestamp: u64) {
+        self.next_connect_timestamp = timestamp;
+    }
+
+    /// 连续失败次数
+    #[inline]
+    pub fn get_retry_num(&self) -> u32 {
+        self.retry_num
+    }
+
+    #[inline]
+    pub fn set_retry_num(&mut self, retry_num: u32) {
+        self.retry_num = retry_num;
+    }
+
+    /// 本轮域名解析的编号
+    #[inline]
+    pub fn get_resolve_id(&self) -> u64 {
+        self.resolve_id
+    }
+
+    #[inline]
+    pub fn set_resolve_id(&mut self, resolve_id: u64) {
+        self.resolve_id = resolve_id;
+    }
+
+    /// 开始新一轮连接 保存解析出的地址
+    #[inline]
+    pub fn set_vec_addr(&mut self, vec_addr: Vec<SocketAddr>) {
+        self.vec_addr = vec_addr;
+        self.addr_idx = 0;
+    }
+
+    /// 本轮下一个要尝试的地址
+    #[inline]
+    pub fn next_addr(&mut self) -> Option<SocketAddr> {
+        let addr = self.vec_addr.get(self.addr_idx).copied();
+        self.addr_idx += 1;
+        addr
+    }
+
+    #[inline]
+    pub fn get_connecting_socket(&self) -> &Option<TcpStream> {
+        &self.connecting_socket
     }
 
-    /// 最后重连时间戳
     #[inline]
-    pub fn set_last_reconnect_timestamp(&self, timestamp: u64) {
-        self.last_reconnect_timestamp.set(timestamp);
+    pub fn set_connecting_socket(&mut self, socket: Option<TcpStream>) -> Option<TcpStream> {
+        std::mem::replace(&mut self.connecting_socket, socket)
     }
 
     #[inline]
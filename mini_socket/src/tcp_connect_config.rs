@@ -1,3 +1,7 @@
+use crate::error::Error;
+use crate::tcp_socket_option::{KeepAlive, SocketOption};
+use crate::tcp_socket_rw::RwConfig;
+
 #[derive(Debug, Clone)]
 pub struct TcpConnectConfig {
     /// 连接名
@@ -8,12 +12,20 @@ pub struct TcpConnectConfig {
     /// true--->有数据立刻发送减少延时
     pub tcp_nodelay: bool,
 
-    /// 要连接的socket_addr
-    pub socket_addr: String,
+    /// default:[0.0.0.0:8888]
+    /// 要连接的地址列表 例如:127.0.0.1:8888 或 proxy.local:8888
+    /// 每次重连都重新解析域名 按顺序尝试所有解析出的地址
+    pub vec_socket_addr: Vec<String>,
 
+    /// default:50
     /// 断线重连间隔，单位毫秒
+    /// 连续失败时每次翻倍 直到 reconnect_max_interval
     pub reconnect_interval: u16,
 
+    /// default:5000
+    /// 断线重连最大间隔，单位毫秒
+    pub reconnect_max_interval: u32,
+
     /// 待发的消息最大长度
     /// default: 10240
     pub msg_deque_size: usize,
@@ -33,6 +45,43 @@ pub struct TcpConnectConfig {
 
     /// 连接超时时长，单位毫秒
     pub connect_timeout_duration: u16,
+
+    /// default:5000
+    /// 域名解析超时时长，单位毫秒 超时后等待重连
+    pub resolve_timeout: u32,
+
+    /// default:0 不启用
+    /// 超过单帧上限的消息拆成分片发送 接收方重组
+    /// 重组后消息的最大字节数 两端要一致
+    pub chunk_max_size: usize,
+
+    /// default:0 不压缩
+    /// 包体字节数不小于这个值时用 lz4 压缩 设置 MsgData.ext 第2位
+    /// 第一条消息总是压缩 通知对方支持压缩 对方要支持解压
+    pub compress_threshold: usize,
+
+    /// default:None 不加密
+    /// 服务端的 X25519 公钥 设置后连接时先发 KeyExchange 之后所有消息加密
+    pub encrypt_server_key: Option<[u8; 32]>,
+
+    /// default: false
+    /// true--->包头后加4个字节的 CRC32C 校验 不一致时断开连接
+    /// 服务端要同样设置
+    pub checksum: bool,
+
+    /// default: false
+    /// true--->上游(net_msg_cb_fn)已满时 暂停读这个连接的数据
+    /// 没有交给上游的消息保存起来 tick 时重试 成功后恢复读
+    /// false-->丢弃没有交给上游的消息
+    pub pause_read_on_full: bool,
+
+    /// 连接的 socket 选项 见 SocketOption
+    pub socket_option: SocketOption,
+
+    /// default: false
+    /// true--->TCP_FASTOPEN_CONNECT 重连时第一个包随 SYN 发出
+    /// 要开启 net.ipv4.tcp_fastopen 的客户端位(1)
+    pub tcp_fastopen: bool,
 }
 
 impl TcpConnectConfig {
@@ -40,17 +89,33 @@ impl TcpConnectConfig {
         TcpConnectConfig {
             tcp_nodelay: true,
             reconnect_interval: 50,
+            reconnect_max_interval: 5000,
             msg_deque_size: 10240,
             socket_read_buffer: 0,
             socket_write_buffer: 0,
             connect_timeout_duration: 15,
+            resolve_timeout: 5000,
+            chunk_max_size: 0,
+            compress_threshold: 0,
+            encrypt_server_key: None,
+            checksum: false,
+            pause_read_on_full: false,
+            socket_option: SocketOption::default(),
+            tcp_fastopen: false,
             name: "Conn_Socket_Addr".into(),
-            socket_addr: "0.0.0.0:8888".into(),
+            vec_socket_addr: vec!["0.0.0.0:8888".into()],
         }
     }
 
+    /// 只连接这一个地址
     pub fn set_socket_addr(&mut self, val: String) -> &mut Self {
-        self.socket_addr = val.clone();
+        self.vec_socket_addr = vec![val];
+        self
+    }
+
+    /// 增加一个候选地址
+    pub fn add_socket_addr(&mut self, val: String) -> &mut Self {
+        self.vec_socket_addr.push(val);
         self
     }
 
@@ -60,6 +125,12 @@ impl TcpConnectConfig {
         self
     }
 
+    /// 断线重连最大间隔，单位毫秒
+    pub fn set_reconnect_max_interval(&mut self, val: u32) -> &mut Self {
+        self.reconnect_max_interval = val;
+        self
+    }
+
     /// 是否启用 TCP_NODELAY 选项
     /// false-->有数据立刻发送减少延时
     /// true--->缓存中积累定数据才发送
@@ -90,4 +161,91 @@ impl TcpConnectConfig {
         self.connect_timeout_duration = val;
         self
     }
+
+    /// 域名解析超时时长，单位毫秒
+    pub fn set_resolve_timeout(&mut self, val: u32) -> &mut Self {
+        self.resolve_timeout = val;
+        self
+    }
+
+    /// 分片重组后消息的最大字节数 0:不启用
+    pub fn set_chunk_max_size(&mut self, val: usize) -> &mut Self {
+        self.chunk_max_size = val;
+        self
+    }
+
+    /// 压缩的最小包体字节数 0:不压缩
+    pub fn set_compress_threshold(&mut self, val: usize) -> &mut Self {
+        self.compress_threshold = val;
+        self
+    }
+
+    /// 服务端的 X25519 公钥 None:不加密
+    pub fn set_encrypt_server_key(&mut self, val: Option<[u8; 32]>) -> &mut Self {
+        self.encrypt_server_key = val;
+        self
+    }
+
+    pub fn set_pause_read_on_full(&mut self, val: bool) -> &mut Self {
+        self.pause_read_on_full = val;
+        self
+    }
+
+    /// 包头后加 CRC32C 校验 服务端要同样设置
+    pub fn set_checksum(&mut self, val: bool) -> &mut Self {
+        self.checksum = val;
+        self
+    }
+
+    /// TCP 保活探测 单位:秒 None:不启用
+    pub fn set_keepalive(&mut self, val: Option<KeepAlive>) -> &mut Self {
+        self.socket_option.keepalive = val;
+        self
+    }
+
+    /// 发出的数据没有确认的最大时长 单位:毫秒 0:不设置
+    pub fn set_user_timeout(&mut self, val: u32) -> &mut Self {
+        self.socket_option.user_timeout = val;
+        self
+    }
+
+    /// SO_LINGER 只支持 Some(0): close 时直接发 RST None:不设置
+    pub fn set_linger(&mut self, val: Option<u16>) -> &mut Self {
+        self.socket_option.linger = val;
+        self
+    }
+
+    /// IP_TOS/IPV6_TCLASS 0:不设置
+    pub fn set_tos(&mut self, val: u8) -> &mut Self {
+        self.socket_option.tos = val;
+        self
+    }
+
+    /// 按 DSCP 设置 tos 只用低6位
+    pub fn set_dscp(&mut self, val: u8) -> &mut Self {
+        self.socket_option.tos = (val & 0x3f) << 2;
+        self
+    }
+
+    pub fn set_tcp_fastopen(&mut self, val: bool) -> &mut Self {
+        self.tcp_fastopen = val;
+        self
+    }
+
+    /// 检查 socket 选项 错误信息带上选项名
+    pub fn check(&self) -> Result<(), Error> {
+        self.socket_option.check().map_err(|err| err.context(&self.name))
+    }
+
+    /// 传给连接 TcpSocketRw 的配置
+    pub fn get_rw_config(&self) -> RwConfig {
+        RwConfig {
+            chunk_max_size: self.chunk_max_size,
+            compress_threshold: self.compress_threshold,
+            compress_first: true,
+            encrypt_secret: None,
+            encrypt_server_key: self.encrypt_server_key,
+            checksum: self.checksum,
+        }
+    }
 }
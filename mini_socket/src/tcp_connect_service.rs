@@ -1,37 +1,61 @@
+use crate::error::Error;
 use crate::tcp_socket_msg::SProtoId;
-use crate::os_epoll::OSEpoll;
+use crate::os_epoll::TIMER_ID;
+use crate::os_poll::OSPoll;
 use crate::os_socket;
 use crate::tcp_socket_rw::ReadResult;
 use crate::tcp_socket_rw::TcpSocketRw;
-use crate::tcp_socket_rw::WriteResult;
 
+use crate::tcp_connect::ConnectState;
 use crate::tcp_connect::TcpConnect;
 use crate::tcp_connect_config::TcpConnectConfig;
+use crate::tcp_socket_option::setsockopt;
 use libc;
-use log::{debug, error, warn};
+use log::{error, info, warn};
 use mini_utils::time;
-use std::io::Error;
+use mini_utils::wtimer::IWTask;
+use mini_utils::wtimer::WTimer;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::io;
 use std::marker::PhantomData;
 use std::net::SocketAddr;
 use std::net::TcpStream;
+use std::net::ToSocketAddrs;
+use std::sync::mpsc::{channel, Receiver, Sender};
 use std::os::unix::io::AsRawFd;
+use std::os::unix::io::FromRawFd;
 use std::os::unix::io::RawFd;
 use std::thread;
-use std::time::Duration;
 
 use crate::tcp_socket::TcpSocket;
 
-const EPOLL_IN_OUT: i32 = (libc::EPOLLOUT | libc::EPOLLIN) as i32;
-
 pub struct TcpConnectService<'a, TBRW, MSG> {
-    os_epoll: OSEpoll,
+    /// 要在 tcp_connect_hash_map 之前 drop 等待 io_uring 中的读写结束
+    os_poll: OSPoll,
     share_buffer: Vec<u8>,
     epoll_max_events: u16,
     phantom: PhantomData<TBRW>,
-    vec_tcp_connect: Vec<TcpConnect<MSG>>,
+    /// 下一个连接id 只增不减 删除的连接id不会重用
+    next_cid: u64,
+    tcp_connect_hash_map: HashMap<u64, TcpConnect<MSG>>,
     vec_epoll_event: Vec<libc::epoll_event>,
-    net_msg_cb_fn: &'a mut dyn Fn(u64, Vec<MSG>),
-    exc_msg_cb_fn: &'a mut dyn Fn(u64, SProtoId),
+    /// 重连间隔随机抖动用的 xorshift 状态
+    rand_state: u64,
+    /// 暂停读的连接 cid 没有交给上游的消息
+    read_paused: HashMap<u64, Vec<MSG>>,
+    /// 下一个域名解析编号
+    next_resolve_id: u64,
+    /// 解析线程返回 (cid, 解析编号, 地址)
+    resolve_sender: Sender<(u64, u64, Vec<SocketAddr>)>,
+    resolve_receiver: Receiver<(u64, u64, Vec<SocketAddr>)>,
+    /// 返回没有交给上游的消息 不为空表示上游已满
+    net_msg_cb_fn: &'a mut dyn Fn(u64, Vec<MSG>) -> Vec<MSG>,
+    /// 连接状态变化: Connecting, Connected, Disconnect(连接断开)
+    /// Disconnect 时带有断开原因
+    exc_msg_cb_fn: &'a mut dyn Fn(u64, SProtoId, Option<&Error>),
+    /// 定时任务 由 os_poll 的 timerfd 驱动
+    wtimer: WTimer,
 }
 
 impl<'a, TBRW, MSG> Drop for TcpConnectService<'a, TBRW, MSG> {
@@ -48,60 +72,440 @@ impl<'a, TBRW, MSG> TcpConnectService<'a, TBRW, MSG>
 where
     TBRW: TcpSocketRw<MSG> + Default + 'static,
 {
+    /// 不会阻塞连接 第一次 tick 时开始连接
+    /// 连接id 按 vec_tcp_connect_config 顺序从0开始
     pub fn new(
         vec_tcp_connect_config: Vec<TcpConnectConfig>,
-        net_msg_cb_fn: &'a mut dyn Fn(u64, Vec<MSG>),
-        exc_msg_cb_fn: &'a mut dyn Fn(u64, SProtoId),
-    ) -> Result<Self, String> {
-        let os_epoll: OSEpoll = OSEpoll::new()?;
-        let tcp_connect_num = vec_tcp_connect_config.len();
-        let share_buffer_size = Self::get_share_buffer_size(&vec_tcp_connect_config) * 3;
-        let vec_tcp_connect = init_tcp_connect::<TBRW, MSG>(&os_epoll, vec_tcp_connect_config);
-
-        Ok(TcpConnectService {
-            os_epoll,
+        net_msg_cb_fn: &'a mut dyn Fn(u64, Vec<MSG>) -> Vec<MSG>,
+        exc_msg_cb_fn: &'a mut dyn Fn(u64, SProtoId, Option<&Error>),
+    ) -> Result<Self, Error> {
+        let os_poll = OSPoll::new()?;
+        info!("tcp connect poll:{}", os_poll.name());
+        let (resolve_sender, resolve_receiver) = channel();
+        let mut service = TcpConnectService {
+            os_poll,
             net_msg_cb_fn,
             exc_msg_cb_fn,
-            vec_tcp_connect,
+            next_cid: 0,
+            wtimer: WTimer::new(1),
+            tcp_connect_hash_map: HashMap::new(),
             phantom: PhantomData,
-            epoll_max_events: tcp_connect_num as u16,
-            share_buffer: vec![0u8; share_buffer_size],
-            vec_epoll_event: vec![libc::epoll_event { events: 0, u64: 0 }; tcp_connect_num],
-        })
+            rand_state: time::timestamp() | 1,
+            read_paused: HashMap::new(),
+            next_resolve_id: 0,
+            resolve_sender,
+            resolve_receiver,
+            epoll_max_events: 1,
+            share_buffer: vec![],
+            vec_epoll_event: vec![libc::epoll_event { events: 0, u64: 0 }; 1],
+        };
+        for config in vec_tcp_connect_config {
+            service.add_connect(config)?;
+        }
+        Ok(service)
+    }
+
+    /// 增加一个连接 下次 tick 时开始连接
+    /// 返回连接id 配置错误时不增加
+    pub fn add_connect(&mut self, config: TcpConnectConfig) -> Result<u64, Error> {
+        config.check()?;
+        let cid = self.next_cid;
+        self.next_cid += 1;
+
+        let mut share_buffer_size = config.socket_read_buffer as usize * 3;
+        if share_buffer_size == 0 {
+            share_buffer_size = 1048576 * 3;
+        }
+        if self.share_buffer.len() < share_buffer_size {
+            self.share_buffer.resize(share_buffer_size, 0);
+        }
+
+        self.tcp_connect_hash_map.insert(cid, TcpConnect::new(cid, config));
+        // 多一个 timerfd 事件
+        let event_num = self.tcp_connect_hash_map.len() + 1;
+        if self.vec_epoll_event.len() < event_num {
+            self.vec_epoll_event.resize(event_num, libc::epoll_event { events: 0, u64: 0 });
+            self.epoll_max_events = event_num as u16;
+        }
+        Ok(cid)
+    }
+
+    /// 删除连接 返回还没有发送的消息
+    pub fn remove_connect(&mut self, cid: u64) -> Result<VecDeque<MSG>, Error> {
+        let mut tcp_connect = match self.tcp_connect_hash_map.remove(&cid) {
+            Some(tcp_connect) => tcp_connect,
+            None => return Err(Error::NotConnected),
+        };
+        self.read_paused.remove(&cid);
+        if let Some(socket) = tcp_connect.set_connecting_socket(None) {
+            epoll_del_fd(&self.os_poll, cid, socket.as_raw_fd());
+        }
+        match tcp_connect.get_tcp_socket_opt().take() {
+            Some(mut tcp_socket) => {
+                del_socket(&self.os_poll, cid, &tcp_socket);
+                Ok(tcp_socket.get_vec_queue())
+            }
+            None => Ok(VecDeque::new()),
+        }
     }
 
     pub fn tick(&mut self) {
+        self.resume_read();
         self.check_connect();
     }
 
-    pub fn get_conn_info(&self) -> Vec<(u16, String)> {
-        let len = self.vec_tcp_connect.len();
-        let mut vec_info = Vec::with_capacity(len);
-        for i in 0..len {
-            vec_info.push((i as u16, self.vec_tcp_connect[i].get_config().name.clone()));
+    /// 重新把暂停读的连接的消息交给上游 成功后恢复读
+    fn resume_read(&mut self) {
+        if self.read_paused.is_empty() {
+            return;
         }
+        let vec_cid: Vec<u64> = self.read_paused.keys().copied().collect();
+        for cid in vec_cid {
+            let vec_msg = match self.read_paused.remove(&cid) {
+                Some(vec_msg) => vec_msg,
+                None => continue,
+            };
+            let vec_rest = (self.net_msg_cb_fn)(cid, vec_msg);
+            if !vec_rest.is_empty() {
+                // 上游还是满的 下次tick再试
+                self.read_paused.insert(cid, vec_rest);
+                return;
+            }
+            // epoll 是边缘触发 要主动读完暂停期间收到的数据
+            self.read_event(cid);
+        }
+    }
+
+    /// 消息交给上游 上游已满时按配置暂停读或丢弃
+    fn deliver_msg(&mut self, cid: u64, vec_msg: Vec<MSG>) {
+        let vec_rest = (self.net_msg_cb_fn)(cid, vec_msg);
+        if vec_rest.is_empty() {
+            return;
+        }
+        let pause_read_on_full = match self.tcp_connect_hash_map.get(&cid) {
+            Some(tcp_connect) => tcp_connect.get_config().pause_read_on_full,
+            None => false,
+        };
+        if pause_read_on_full {
+            info!("cid:{} upstream full pause read", cid);
+            self.read_paused.insert(cid, vec_rest);
+        } else {
+            error!("cid:{} upstream full drop msg num:{}", cid, vec_rest.len());
+        }
+    }
+
+    /// 所有连接的 (连接id, 连接名) 按连接id排序
+    pub fn get_conn_info(&self) -> Vec<(u64, String)> {
+        let mut vec_info: Vec<(u64, String)> = self
+            .tcp_connect_hash_map
+            .iter()
+            .map(|(cid, tcp_connect)| (*cid, tcp_connect.get_config().name.clone()))
+            .collect();
+        vec_info.sort_by_key(|(cid, _)| *cid);
         vec_info
     }
 
+    /// 连接状态
+    #[inline]
+    pub fn get_state(&self, cid: u64) -> Option<ConnectState> {
+        self.tcp_connect_hash_map
+            .get(&cid)
+            .map(|tcp_connect| tcp_connect.get_state())
+    }
+
+    /// 到时间的开始连接 超时的换下一个地址
     fn check_connect(&mut self) {
-        for tcp_connect in &mut self.vec_tcp_connect {
-            if tcp_connect.get_tcp_socket_opt().is_none() {
-                tcp_connect
-                    .set_tcp_socket_opt(tcp_reconnect::<TBRW, MSG>(&self.os_epoll, tcp_connect));
+        self.check_resolve();
+        let ts = time::timestamp();
+        let vec_cid: Vec<u64> = self.tcp_connect_hash_map.keys().copied().collect();
+        for cid in vec_cid {
+            let tcp_connect = match self.tcp_connect_hash_map.get(&cid) {
+                Some(tcp_connect) => tcp_connect,
+                None => continue,
+            };
+            match tcp_connect.get_state() {
+                ConnectState::Wait => {
+                    if tcp_connect.get_next_connect_timestamp() <= ts {
+                        self.start_connect(cid);
+                    }
+                }
+                ConnectState::Resolving => {
+                    let timeout = tcp_connect.get_config().resolve_timeout as u64;
+                    if tcp_connect.get_connect_timestamp() + timeout < ts {
+                        warn!("cid:{} resolve timeout:{}ms", cid, timeout);
+                        self.connect_fail(cid);
+                    }
+                }
+                ConnectState::Connecting => {
+                    let timeout = tcp_connect.get_config().connect_timeout_duration as u64;
+                    if tcp_connect.get_connect_timestamp() + timeout < ts {
+                        warn!("cid:{} connect timeout:{}ms", cid, timeout);
+                        self.connect_fail(cid);
+                    }
+                }
+                ConnectState::Connected => {}
             }
         }
     }
 
+    /// 重新解析地址 开始新一轮连接
+    /// 都是IP地址时直接连接 有域名时在解析线程中解析 不阻塞网络线程
+    fn start_connect(&mut self, cid: u64) {
+        self.next_resolve_id += 1;
+        let resolve_id = self.next_resolve_id;
+        let tcp_connect = match self.tcp_connect_hash_map.get_mut(&cid) {
+            Some(tcp_connect) => tcp_connect,
+            None => return,
+        };
+        let vec_socket_addr = tcp_connect.get_config().vec_socket_addr.clone();
+        tcp_connect.set_vec_addr(vec![]);
+        tcp_connect.set_resolve_id(resolve_id);
+        tcp_connect.set_connect_timestamp(time::timestamp());
+        (self.exc_msg_cb_fn)(cid, SProtoId::Connecting, None);
+
+        let vec_ip: Vec<SocketAddr> = vec_socket_addr.iter().filter_map(|addr| addr.parse().ok()).collect();
+        if vec_ip.len() == vec_socket_addr.len() {
+            self.resolved(cid, vec_ip);
+            return;
+        }
+        tcp_connect.set_state(ConnectState::Resolving);
+        let sender = self.resolve_sender.clone();
+        let result = thread::Builder::new()
+            .name(format!("resolve_{}", cid))
+            .spawn(move || {
+                let mut vec_addr = vec![];
+                for socket_addr in vec_socket_addr.iter() {
+                    match socket_addr.to_socket_addrs() {
+                        Ok(iter) => vec_addr.extend(iter),
+                        Err(err) => error!("cid:{} resolve {} error:{}", cid, socket_addr, err),
+                    }
+                }
+                // 服务已删除时发送失败
+                let _ = sender.send((cid, resolve_id, vec_addr));
+            });
+        if let Err(err) = result {
+            error!("cid:{} spawn resolve thread error:{}", cid, err);
+            self.connect_fail(cid);
+        }
+    }
+
+    /// 处理解析线程返回的地址 超时或已删除的连接丢弃结果
+    fn check_resolve(&mut self) {
+        while let Ok((cid, resolve_id, vec_addr)) = self.resolve_receiver.try_recv() {
+            match self.tcp_connect_hash_map.get(&cid) {
+                Some(tcp_connect)
+                    if tcp_connect.get_state() == ConnectState::Resolving
+                        && tcp_connect.get_resolve_id() == resolve_id =>
+                {
+                    self.resolved(cid, vec_addr);
+                }
+                _ => info!("cid:{} drop resolve:{} result", cid, resolve_id),
+            }
+        }
+    }
+
+    /// 地址解析完成 按顺序连接
+    fn resolved(&mut self, cid: u64, vec_addr: Vec<SocketAddr>) {
+        if let Some(tcp_connect) = self.tcp_connect_hash_map.get_mut(&cid) {
+            tcp_connect.set_vec_addr(vec_addr);
+            tcp_connect.set_state(ConnectState::Connecting);
+            self.connect_next_addr(cid);
+        }
+    }
+
+    /// 连接本轮下一个地址 都失败了等待重连
+    fn connect_next_addr(&mut self, cid: u64) {
+        loop {
+            let tcp_connect = match self.tcp_connect_hash_map.get_mut(&cid) {
+                Some(tcp_connect) => tcp_connect,
+                None => return,
+            };
+            let addr = match tcp_connect.next_addr() {
+                Some(addr) => addr,
+                None => {
+                    self.retry_later(cid);
+                    return;
+                }
+            };
+            match connect_addr(&self.os_poll, cid, &addr, tcp_connect.get_config()) {
+                Ok((socket, is_connected)) => {
+                    tcp_connect.set_connect_timestamp(time::timestamp());
+                    if is_connected {
+                        self.connected(cid, socket);
+                    } else {
+                        tcp_connect.set_connecting_socket(Some(socket));
+                    }
+                    return;
+                }
+                Err(err) => warn!("cid:{} connect {} error:{}", cid, addr, err),
+            }
+        }
+    }
+
+    /// 连接可写或出错时 检查连接结果
+    fn connect_event(&mut self, cid: u64) {
+        let tcp_connect = match self.tcp_connect_hash_map.get_mut(&cid) {
+            Some(tcp_connect) => tcp_connect,
+            None => return,
+        };
+        let raw_fd = match tcp_connect.get_connecting_socket() {
+            Some(socket) => socket.as_raw_fd(),
+            None => return,
+        };
+        match os_socket::getsockopt::<i32>(raw_fd, libc::SOL_SOCKET, libc::SO_ERROR) {
+            Ok(0) => {
+                if let Some(socket) = tcp_connect.set_connecting_socket(None) {
+                    self.connected(cid, socket);
+                }
+            }
+            Ok(errno) => {
+                warn!("cid:{} connect error:{}", cid, io::Error::from_raw_os_error(errno));
+                self.connect_fail(cid);
+            }
+            Err(err) => {
+                warn!("cid:{} connect getsockopt error:{}", cid, err);
+                self.connect_fail(cid);
+            }
+        }
+    }
+
+    /// 关闭正在连接的socket 连接下一个地址
+    fn connect_fail(&mut self, cid: u64) {
+        let tcp_connect = match self.tcp_connect_hash_map.get_mut(&cid) {
+            Some(tcp_connect) => tcp_connect,
+            None => return,
+        };
+        if let Some(socket) = tcp_connect.set_connecting_socket(None) {
+            epoll_del_fd(&self.os_poll, cid, socket.as_raw_fd());
+        }
+        self.connect_next_addr(cid);
+    }
+
+    fn connected(&mut self, cid: u64, socket: TcpStream) {
+        let tcp_connect = match self.tcp_connect_hash_map.get_mut(&cid) {
+            Some(tcp_connect) => tcp_connect,
+            None => return,
+        };
+        if let Err(err) = init_socket(&socket, tcp_connect.get_config()) {
+            warn!("cid:{} init connect socket error:{}", cid, err);
+            epoll_del_fd(&self.os_poll, cid, socket.as_raw_fd());
+            self.connect_next_addr(cid);
+            return;
+        }
+        let mut tcp_socket_rw = TBRW::default();
+        tcp_socket_rw.set_config(&tcp_connect.get_config().get_rw_config());
+        let tcp_socket = TcpSocket::new(socket, Box::new(tcp_socket_rw));
+        // 改为监听可读
+        if let Err(err) = self.os_poll.add_socket(cid, &tcp_socket, true) {
+            warn!("cid:{} add connect socket error:{}", cid, err);
+            epoll_del_fd(&self.os_poll, cid, tcp_socket.socket.as_raw_fd());
+            self.connect_next_addr(cid);
+            return;
+        }
+        info!("cid:{} connect:{:?} success", cid, tcp_socket.socket.peer_addr());
+        tcp_connect.set_retry_num(0);
+        tcp_connect.set_state(ConnectState::Connected);
+        tcp_connect.set_tcp_socket_opt(Some(tcp_socket));
+        // 发送 hello_msg
+        if let Some(tcp_socket) = tcp_connect.get_tcp_socket_opt() {
+            if tcp_socket.vec_queue_len() > 0 {
+                if let Err(err) = self.os_poll.write(cid, tcp_socket) {
+                    warn!("cid:{} write hello msg err:{}", cid, err);
+                    self.connect_lost(cid, err);
+                    return;
+                }
+            }
+        }
+        (self.exc_msg_cb_fn)(cid, SProtoId::Connected, None);
+    }
+
+    /// 连接断开 等待重连
+    fn connect_lost(&mut self, cid: u64, err: Error) {
+        let tcp_connect = match self.tcp_connect_hash_map.get_mut(&cid) {
+            Some(tcp_connect) => tcp_connect,
+            None => return,
+        };
+        if let Some(tcp_socket) = tcp_connect.get_tcp_socket_opt().take() {
+            del_socket(&self.os_poll, cid, &tcp_socket);
+        }
+        if let Some(vec_msg) = self.read_paused.remove(&cid) {
+            warn!("cid:{} paused read drop msg num:{}", cid, vec_msg.len());
+        }
+        tcp_connect.set_retry_num(0);
+        self.retry_later(cid);
+        (self.exc_msg_cb_fn)(cid, SProtoId::Disconnect, Some(&err));
+    }
+
+    /// 指数退避 加随机抖动 避免所有连接同时重连
+    fn retry_later(&mut self, cid: u64) {
+        let rand = self.next_rand();
+        let tcp_connect = match self.tcp_connect_hash_map.get_mut(&cid) {
+            Some(tcp_connect) => tcp_connect,
+            None => return,
+        };
+        let config = tcp_connect.get_config();
+        let retry_num = tcp_connect.get_retry_num();
+        let interval = (config.reconnect_interval as u64) << std::cmp::min(retry_num, 16);
+        let interval = std::cmp::min(interval, config.reconnect_max_interval as u64);
+        // [interval/2, interval]
+        let delay = interval / 2 + rand % (interval / 2 + 1);
+
+        tcp_connect.set_retry_num(retry_num + 1);
+        tcp_connect.set_state(ConnectState::Wait);
+        tcp_connect.set_next_connect_timestamp(time::timestamp() + delay);
+        if retry_num > 0 {
+            warn!("cid:{} retry:{} reconnect after {}ms", cid, retry_num, delay);
+        }
+    }
+
+    fn next_rand(&mut self) -> u64 {
+        let mut x = self.rand_state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.rand_state = x;
+        x
+    }
+
     pub fn get_epoll_max_events(&self) -> u16 {
         self.epoll_max_events
     }
 
-    pub fn epoll_event(&mut self, wait_timeout: i32) -> Result<u32, String> {
-        match self.os_epoll.wait(wait_timeout, &mut self.vec_epoll_event) {
+    /// 增加定时任务 在 epoll_event 中执行
+    /// delay: 延迟毫秒 interval: 重复间隔毫秒 task.execute() 返回 true 后不再执行
+    /// 任务到期时 epoll_wait 会被唤醒 wait_timeout 不影响定时精度
+    pub fn schedule(&mut self, delay: u64, interval: u64, task: Box<dyn IWTask>) {
+        self.wtimer.push_task(delay, interval, task);
+        self.set_timer();
+    }
+
+    /// 把 timerfd 设置为下一个任务的到期时间
+    fn set_timer(&mut self) {
+        if let Err(err) = self.os_poll.set_timer(self.wtimer.next_expire()) {
+            error!("os_poll set_timer error:{}", err);
+        }
+    }
+
+    fn timer_event(&mut self) {
+        self.os_poll.read_timer();
+        self.wtimer.scheduled(time::timestamp());
+        self.set_timer();
+    }
+
+    pub fn epoll_event(&mut self, wait_timeout: i32) -> Result<u32, Error> {
+        match self.os_poll.wait(wait_timeout, &mut self.vec_epoll_event) {
             Ok(0) => Ok(0),
             Ok(epevs) => {
                 for n in 0..epevs as usize {
                     let event = self.vec_epoll_event[n];
+                    if event.u64 == TIMER_ID {
+                        self.timer_event();
+                        continue;
+                    }
+                    if self.get_state(event.u64) == Some(ConnectState::Connecting) {
+                        self.connect_event(event.u64);
+                        continue;
+                    }
                     if (event.events & libc::EPOLLIN as u32) != 0 {
                         self.read_event(event.u64);
                     }
@@ -109,7 +513,7 @@ where
                         self.write_event(event.u64);
                     }
                     if (event.events & libc::EPOLLERR as u32) != 0 {
-                        self.error_event(event.u64, Error::last_os_error().to_string());
+                        self.error_event(event.u64);
                     }
                 }
                 Ok(epevs)
@@ -120,73 +524,75 @@ where
 
     fn read_event(&mut self, cid: u64) {
         //info!("read id:{}", id);
-        if let Some(tcp_connect) = self.vec_tcp_connect.get_mut(cid as usize) {
-            if let Some(tcp_socket) = tcp_connect.get_tcp_socket_opt() {
-                //loop {
-                match tcp_socket.read(&mut self.share_buffer) {
-                    ReadResult::Data(vec_msg) => {
-                        (self.net_msg_cb_fn)(cid, vec_msg);
-                    }
-                    ReadResult::Error(vec_msg, err) => {
-                        (self.net_msg_cb_fn)(cid, vec_msg);
-                        error!("tcp_socket.read id:{} err:{}", cid, err);
-                        epoll_del_fd(&self.os_epoll, cid, tcp_socket.socket.as_raw_fd());
-                        tcp_connect.set_tcp_socket_opt(tcp_reconnect::<TBRW, MSG>(
-                            &self.os_epoll,
-                            &tcp_connect,
-                        ));
-                    }
-                }
+        if self.read_paused.contains_key(&cid) {
+            return;
+        }
+        let result = match self.tcp_connect_hash_map.get_mut(&cid) {
+            Some(tcp_connect) => match tcp_connect.get_tcp_socket_opt() {
+                Some(tcp_socket) => self.os_poll.read(cid, tcp_socket, &mut self.share_buffer),
+                None => return,
+            },
+            None => {
+                warn!("read_event tcp_connect_mgmt id no exitis:{}", cid);
+                return;
             }
-        //}
-        } else {
-            warn!("read_event tcp_connect_mgmt id no exitis:{}", cid);
         };
+        match result {
+            ReadResult::Data(vec_msg) => {
+                self.deliver_msg(cid, vec_msg);
+            }
+            ReadResult::Error(vec_msg, err) => {
+                let vec_rest = (self.net_msg_cb_fn)(cid, vec_msg);
+                if !vec_rest.is_empty() {
+                    error!("cid:{} upstream full drop msg num:{}", cid, vec_rest.len());
+                }
+                error!("tcp_socket.read id:{} err:{}", cid, err);
+                self.connect_lost(cid, err);
+            }
+        }
     }
 
     #[inline]
     pub fn write_msg(&mut self, cid: u64, msg: MSG) {
-        match self.vec_tcp_connect.get_mut(cid as usize) {
+        match self.tcp_connect_hash_map.get_mut(&cid) {
             Some(tcp_connect) => {
                 let msg_deque_size = tcp_connect.get_config().msg_deque_size;
                 if let Some(tcp_socket) = tcp_connect.get_tcp_socket_opt() {
                     if tcp_socket.vec_queue_len() > msg_deque_size {
                         warn!("cid:{} Msg Queue Is Full", cid);
-                        (self.exc_msg_cb_fn)(cid, SProtoId::MsgQueueFull);
+                        (self.exc_msg_cb_fn)(cid, SProtoId::MsgQueueFull, None);
                         return;
                     }
 
-                    tcp_socket.push_vec_queue(msg);
+                    if let Err(err) = tcp_socket.push_vec_queue(msg) {
+                        error!("cid:{} push_vec_queue err:{}", cid, err);
+                        self.connect_lost(cid, err);
+                        return;
+                    }
 
                     if tcp_socket.vec_queue_len() == 1 {
-                        if let Err(err) = write_data(&self.os_epoll, cid, tcp_socket) {
+                        if let Err(err) = self.os_poll.write(cid, tcp_socket) {
                             warn!("cid:{} write_data  err:{}", cid, err);
-                            epoll_del_fd(&self.os_epoll, cid, tcp_socket.socket.as_raw_fd());
-                            tcp_connect.set_tcp_socket_opt(tcp_reconnect::<TBRW, MSG>(
-                                &self.os_epoll,
-                                &tcp_connect,
-                            ));
+                            self.connect_lost(cid, err);
                         }
                     }
+                } else {
+                    warn!("write_msg cid:{} not connected", cid);
                 }
             }
             None => {
                 warn!("write_msg socket id no exitis:{}", cid);
-                (self.exc_msg_cb_fn)(cid, SProtoId::Disconnect);
+                (self.exc_msg_cb_fn)(cid, SProtoId::Disconnect, Some(&Error::NotConnected));
             }
         }
     }
 
     fn write_event(&mut self, cid: u64) {
-        if let Some(tcp_connect) = self.vec_tcp_connect.get_mut(cid as usize) {
+        if let Some(tcp_connect) = self.tcp_connect_hash_map.get_mut(&cid) {
             if let Some(tcp_socket) = tcp_connect.get_tcp_socket_opt() {
-                if let Err(err) = write_data(&self.os_epoll, cid, tcp_socket) {
+                if let Err(err) = self.os_poll.write(cid, tcp_socket) {
                     warn!("tcp_socket.writer.write cid:{} err:{}", cid, err);
-                    epoll_del_fd(&self.os_epoll, cid, tcp_socket.socket.as_raw_fd());
-                    tcp_connect.set_tcp_socket_opt(tcp_reconnect::<TBRW, MSG>(
-                        &self.os_epoll,
-                        &tcp_connect,
-                    ));
+                    self.connect_lost(cid, err);
                 }
             }
         } else {
@@ -194,169 +600,203 @@ where
         }
     }
 
-    fn error_event(&mut self, cid: u64, err: String) {
+    fn error_event(&mut self, cid: u64) {
+        let raw_fd = match self.tcp_connect_hash_map.get_mut(&cid) {
+            Some(tcp_connect) => match tcp_connect.get_tcp_socket_opt() {
+                Some(tcp_socket) => tcp_socket.socket.as_raw_fd(),
+                None => return,
+            },
+            None => return,
+        };
+        let err = os_socket::socket_error(raw_fd);
         warn!("error_event cid:{} error:{}", cid, err);
-        if let Some(tcp_connect) = self.vec_tcp_connect.get_mut(cid as usize) {
-            if let Some(tcp_socket) = tcp_connect.get_tcp_socket_opt() {
-                epoll_del_fd(&self.os_epoll, cid, tcp_socket.socket.as_raw_fd());
-            }
-            tcp_connect
-                .set_tcp_socket_opt(tcp_reconnect::<TBRW, MSG>(&self.os_epoll, &tcp_connect));
-        }
+        self.connect_lost(cid, err);
     }
 
-    fn get_share_buffer_size(vec_tcp_connect_config: &Vec<TcpConnectConfig>) -> usize {
-        let mut buffer_size = 0;
-        for cfg in vec_tcp_connect_config {
-            if cfg.socket_read_buffer > buffer_size {
-                buffer_size = cfg.socket_read_buffer;
-            }
-        }
-        if buffer_size == 0{
-            buffer_size = 1048576;
-        }
-        buffer_size as usize
+}
+
+/// 新建非阻塞socket 发起连接 加入epoll等待可写
+/// 返回 socket, 是否已连接
+fn connect_addr(
+    os_poll: &OSPoll,
+    cid: u64,
+    addr: &SocketAddr,
+    config: &TcpConnectConfig,
+) -> Result<(TcpStream, bool), Error> {
+    // raw_fd 交给 TcpStream 管理 出错时自动关闭
+    let socket = unsafe { TcpStream::from_raw_fd(os_socket::tcp_socket(addr)?) };
+    // 要在 connect 之前设置
+    if config.tcp_fastopen {
+        setsockopt(socket.as_raw_fd(), libc::SOL_TCP, libc::TCP_FASTOPEN_CONNECT, 1, "TCP_FASTOPEN_CONNECT")?;
     }
+    let is_connected = os_socket::connect(socket.as_raw_fd(), addr)?;
+    os_poll.ctl_add_fd(cid, socket.as_raw_fd(), libc::EPOLLOUT)?;
+    Ok((socket, is_connected))
 }
 
+/// 连接成功后设置socket选项
+fn init_socket(
+    socket: &TcpStream,
+    config: &TcpConnectConfig,
+) -> Result<(), Error> {
+    if let Err(err) = socket.set_nodelay(config.tcp_nodelay) {
+        return Err(Error::from(err).context("set_tcp_nodelay"));
+    }
+    let raw_fd = socket.as_raw_fd();
+    if config.socket_read_buffer > 0 {
+        os_socket::setsockopt(
+            raw_fd,
+            libc::SOL_SOCKET,
+            libc::SO_RCVBUF,
+            config.socket_read_buffer,
+        )?;
+    }
+    if config.socket_write_buffer > 0 {
+        os_socket::setsockopt(
+            raw_fd,
+            libc::SOL_SOCKET,
+            libc::SO_SNDBUF,
+            config.socket_write_buffer,
+        )?;
+    }
+    config.socket_option.apply(socket)
+}
 
-fn init_tcp_connect<TBRW, MSG>(
-    os_epoll: &OSEpoll,
-    vec_tcp_connect_config: Vec<TcpConnectConfig>,
-) -> Vec<TcpConnect<MSG>>
-where
-    TBRW: TcpSocketRw<MSG> + Default + 'static,
-{
-    let mut id = 0;
-    let connect_num = vec_tcp_connect_config.len();
-    let mut vec_tcp_connect = Vec::with_capacity(connect_num);
-    for connect_config in vec_tcp_connect_config {
-        match new_tcp_socket::<TBRW, MSG>(os_epoll, id, &connect_config) {
-            Ok(tcp_socket) => {
-                vec_tcp_connect.push(TcpConnect::new(id, connect_config, Some(tcp_socket)));
-            }
-            Err(err) => {
-                error!(
-                    "tcp_connect addr {} error:{}",
-                    connect_config.socket_addr, err
-                );
-                vec_tcp_connect.push(TcpConnect::new(id, connect_config, None));
-            }
-        }
-        id += 1;
+fn epoll_del_fd(os_poll: &OSPoll, cid: u64, raw_fd: RawFd) {
+    if let Err(err) = os_poll.ctl_del_fd(cid, raw_fd) {
+        warn!("os_poll.ctl_del_fd({}) Error:{}", cid, err);
     }
-    vec_tcp_connect
 }
 
-fn epoll_del_fd(os_epoll: &OSEpoll, cid: u64, raw_fd: RawFd) {
-    if let Err(err) = os_epoll.ctl_del_fd(cid, raw_fd) {
-        warn!("os_epoll.ctl_del_fd({}) Error:{}", cid, err);
+/// 要在 tcp_socket drop 之前调用
+fn del_socket<MSG>(os_poll: &OSPoll, cid: u64, tcp_socket: &TcpSocket<MSG>) {
+    if let Err(err) = os_poll.del_socket(cid, tcp_socket.socket.as_raw_fd()) {
+        warn!("os_poll.del_socket({}) Error:{}", cid, err);
     }
 }
 
-fn write_data<MSG>(
-    os_epoll: &OSEpoll,
-    cid: u64,
-    tcp_socket: &mut TcpSocket<MSG>,
-) -> Result<(), String> {
-    match tcp_socket.write() {
-        WriteResult::Finish => {
-            if tcp_socket.epevs == libc::EPOLLIN {
-                return Ok(());
-            }
-            tcp_socket.epevs = libc::EPOLLIN;
-            return os_epoll.ctl_mod_fd(cid, tcp_socket.socket.as_raw_fd(), libc::EPOLLIN);
+#[cfg(test)]
+mod test {
+    use crate::error::Error;
+    use crate::tcp_connect::ConnectState;
+    use crate::tcp_connect_config::TcpConnectConfig;
+    use crate::tcp_connect_service::TcpConnectService;
+    use crate::tcp_socket_msg::SProtoId;
+    use crate::tcp_socket_rw::{ReadResult, TcpSocketRw, WriteResult};
+    use mini_utils::time;
+    use mini_utils::wtimer::IWTask;
+    use std::cell::{Cell, RefCell};
+    use std::net::{TcpListener, TcpStream};
+    use std::rc::Rc;
+
+    /// 写总是返回缓冲区满 消息留在队列里
+    #[derive(Default)]
+    struct QueueRw;
+
+    impl TcpSocketRw<u32> for QueueRw {
+        fn write(&mut self, _socket: &mut TcpStream, _msg: &mut u32) -> WriteResult {
+            WriteResult::BufferFull
         }
-        WriteResult::BufferFull => {
-            if tcp_socket.epevs == EPOLL_IN_OUT {
-                return Ok(());
-            }
-            tcp_socket.epevs = EPOLL_IN_OUT;
-            return os_epoll.ctl_mod_fd(cid, tcp_socket.socket.as_raw_fd(), EPOLL_IN_OUT);
+        fn read(&mut self, _socket: &mut TcpStream, _buf: &mut Vec<u8>) -> ReadResult<u32> {
+            ReadResult::Data(vec![])
         }
-        WriteResult::Error(err) => return Err(err),
     }
-}
 
-/// 断线重连
-fn tcp_reconnect<TBRW, MSG>(
-    os_epoll: &OSEpoll,
-    tcp_connect: &TcpConnect<MSG>,
-    //tcp_socket_rw: Box<dyn TcpSocketRw<MSG>>,
-) -> Option<TcpSocket<MSG>>
-where
-    TBRW: TcpSocketRw<MSG> + Default + 'static,
-{
-    let ts = time::timestamp();
-    if tcp_connect.get_last_reconnect_timestamp()
-        + tcp_connect.get_config().reconnect_interval as u64
-        > ts
-    {
-        return None;
-    }
+    #[test]
+    fn test_connect_candidate_addr() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        // 第一个地址拒绝连接 换下一个域名地址
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap();
+        let closed_port = closed.local_addr().unwrap().port();
+        drop(closed);
+
+        let mut config = TcpConnectConfig::new();
+        config
+            .set_socket_addr(format!("127.0.0.1:{}", closed_port))
+            .add_socket_addr(format!("localhost:{}", port))
+            .set_connect_timeout_duration(1000);
+
+        let vec_state = RefCell::new(vec![]);
+        let mut net_msg_cb_fn = |_cid: u64, _vec_msg: Vec<u32>| vec![];
+        let mut exc_msg_cb_fn =
+            |_cid: u64, spid: SProtoId, _err: Option<&Error>| vec_state.borrow_mut().push(spid);
+        let mut service: TcpConnectService<QueueRw, u32> =
+            TcpConnectService::new(vec![config], &mut net_msg_cb_fn, &mut exc_msg_cb_fn).unwrap();
+        // 有域名时在解析线程中解析
+        service.tick();
+        assert_eq!(service.get_state(0), Some(ConnectState::Resolving));
 
-    tcp_connect.set_last_reconnect_timestamp(ts);
-    match new_tcp_socket::<TBRW, MSG>(os_epoll, tcp_connect.get_cid(), tcp_connect.get_config()) {
-        Ok(tcp_socket) => {
-            return Some(tcp_socket);
+        for _ in 0..1000 {
+            service.tick();
+            service.epoll_event(1).unwrap();
+            if vec_state.borrow().contains(&SProtoId::Connected) {
+                break;
+            }
         }
-        Err(err) => {
-            error!(
-                "tcp_reconnect {} error:{}",
-                tcp_connect.get_config().socket_addr,
-                err
-            );
-            return None;
+        drop(service);
+        assert_eq!(vec_state.borrow()[0], SProtoId::Connecting);
+        assert!(vec_state.borrow().contains(&SProtoId::Connected));
+    }
+
+    #[test]
+    fn test_add_remove_connect() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut config = TcpConnectConfig::new();
+        config.set_socket_addr(listener.local_addr().unwrap().to_string());
+
+        let connected = RefCell::new(vec![]);
+        let mut net_msg_cb_fn = |_cid: u64, _vec_msg: Vec<u32>| vec![];
+        let mut exc_msg_cb_fn = |cid: u64, spid: SProtoId, _err: Option<&Error>| {
+            if spid == SProtoId::Connected {
+                connected.borrow_mut().push(cid);
+            }
+        };
+        let mut service: TcpConnectService<QueueRw, u32> =
+            TcpConnectService::new(vec![], &mut net_msg_cb_fn, &mut exc_msg_cb_fn).unwrap();
+
+        assert!(service.add_connect(TcpConnectConfig::new().set_linger(Some(2)).clone()).is_err());
+        let cid = service.add_connect(config).unwrap();
+        for _ in 0..1000 {
+            service.tick();
+            service.epoll_event(1).unwrap();
+            if !connected.borrow().is_empty() {
+                break;
+            }
         }
+        service.write_msg(cid, 1);
+        service.write_msg(cid, 2);
+        assert_eq!(service.remove_connect(cid).unwrap(), vec![1, 2]);
+        assert!(service.remove_connect(cid).is_err());
+        assert!(service.get_conn_info().is_empty());
+        drop(service);
+        assert_eq!(connected.borrow()[..], [cid]);
     }
-}
 
-/// 新建链接
-fn new_tcp_socket<TBRW, MSG>(
-    os_epoll: &OSEpoll,
-    cid: u64,
-    config: &TcpConnectConfig,
-) -> Result<TcpSocket<MSG>, String>
-where
-    TBRW: TcpSocketRw<MSG> + Default + 'static,
-{
-    match config.socket_addr.parse::<SocketAddr>() {
-        Ok(addr) => {
-            let duration = Duration::from_millis(config.connect_timeout_duration as u64);
-            match TcpStream::connect_timeout(&addr, duration) {
-                Ok(socket) => {
-                    if let Err(err) = socket.set_nonblocking(true) {
-                        return Err(format!("set_nonblocking:{}", err.to_string()));
-                    }
+    struct CountTask(Rc<Cell<u32>>);
 
-                    if let Err(err) = socket.set_nodelay(config.tcp_nodelay) {
-                        return Err(format!("set_tcp_nodelay:{}", err));
-                    }
+    impl IWTask for CountTask {
+        fn execute(&mut self) -> bool {
+            self.0.set(self.0.get() + 1);
+            self.0.get() >= 3
+        }
+    }
 
-                    let raw_fd = socket.as_raw_fd();
-                    if let Err(err) = os_epoll.ctl_add_fd(cid, raw_fd, libc::EPOLLIN) {
-                        return Err(format!("os_epoll ctl_add_fd error:{}", err));
-                    }
+    #[test]
+    fn test_schedule() {
+        let mut net_msg_cb_fn = |_cid: u64, _vec_msg: Vec<u32>| vec![];
+        let mut exc_msg_cb_fn = |_cid: u64, _spid: SProtoId, _err: Option<&Error>| {};
+        let mut service: TcpConnectService<QueueRw, u32> =
+            TcpConnectService::new(vec![], &mut net_msg_cb_fn, &mut exc_msg_cb_fn).unwrap();
 
-                    os_socket::setsockopt(
-                        raw_fd,
-                        libc::SOL_SOCKET,
-                        libc::SO_RCVBUF,
-                        config.socket_read_buffer,
-                    )?;
-
-                    os_socket::setsockopt(
-                        raw_fd,
-                        libc::SOL_SOCKET,
-                        libc::SO_SNDBUF,
-                        config.socket_write_buffer,
-                    )?;
-                    debug!("connect:{} success", config.socket_addr);
-                    return Ok(TcpSocket::new(socket, Box::new(TBRW::default())));
-                }
-                Err(err) => return Err(err.to_string()),
-            }
+        let count = Rc::new(Cell::new(0));
+        service.schedule(10, 10, Box::new(CountTask(count.clone())));
+        // timerfd 唤醒 epoll_wait 不用等到超时
+        let begin = time::timestamp();
+        while count.get() < 3 {
+            service.epoll_event(5000).unwrap();
         }
-        Err(err) => Err(err.to_string()),
+        assert!(time::timestamp() - begin < 1000);
+        assert_eq!(service.os_poll.wait(0, &mut service.vec_epoll_event), Ok(0));
     }
 }
@@ -1,32 +1,100 @@
 use crate::os_socket;
+use crate::tcp_listen_config::{ListenAddr, TcpListenConfig};
+use crate::tcp_socket_option::setsockopt;
+use crate::error::Error;
+use std::net::SocketAddr;
 use std::net::TcpListener;
 use std::os::unix::io::AsRawFd;
+use std::os::unix::io::FromRawFd;
 
 pub struct TcpListen {
     listen: TcpListener,
+    socket_addr: SocketAddr,
 }
 
 impl TcpListen {
-    pub fn new(socket_addr: &String) -> Result<Self, String> {
-        let listen = match TcpListener::bind(socket_addr) {
-            Ok(listen) => listen,
-            Err(err) => return Err(err.to_string()),
+    pub fn new(listen_addr: &ListenAddr, config: &TcpListenConfig) -> Result<Self, Error> {
+        let addr = match listen_addr.socket_addr.parse::<SocketAddr>() {
+            Ok(addr) => addr,
+            Err(err) => return Err(Error::Config(format!("{} {}", listen_addr.socket_addr, err))),
         };
 
-        if let Err(err) = listen.set_nonblocking(true) {
-            return Err(format!("listen.set_nonblocking{}", err));
-        }
-
+        // raw_fd 交给 TcpListener 管理 出错时自动关闭
+        let listen = unsafe { TcpListener::from_raw_fd(os_socket::tcp_socket(&addr)?) };
         let raw_fd = listen.as_raw_fd();
 
         os_socket::setsockopt(raw_fd, libc::SOL_SOCKET, libc::SO_REUSEADDR, 1)?;
-        os_socket::setsockopt(raw_fd, libc::SOL_TCP, libc::TCP_DEFER_ACCEPT, 3)?;
+        if addr.is_ipv6() {
+            let ipv6_only = listen_addr.ipv6_only as i32;
+            os_socket::setsockopt(raw_fd, libc::IPPROTO_IPV6, libc::IPV6_V6ONLY, ipv6_only)?;
+        }
+        if config.tcp_fastopen > 0 {
+            setsockopt(raw_fd, libc::SOL_TCP, libc::TCP_FASTOPEN, config.tcp_fastopen as i32, "TCP_FASTOPEN")?;
+        }
 
-        Ok(TcpListen { listen })
+        let (storage, len) = os_socket::socket_addr_to_raw(&addr);
+        unsafe {
+            let sockaddr = &storage as *const _ as *const libc::sockaddr;
+            if libc::bind(raw_fd, sockaddr, len) == -1 {
+                return Err(Error::last_os_error().context(&format!("bind {}", addr)));
+            }
+            if libc::listen(raw_fd, config.listen_backlog) == -1 {
+                return Err(Error::last_os_error().context(&format!("listen {}", addr)));
+            }
+        }
+
+        if config.defer_accept > 0 {
+            setsockopt(raw_fd, libc::SOL_TCP, libc::TCP_DEFER_ACCEPT, config.defer_accept as i32, "TCP_DEFER_ACCEPT")?;
+        }
+
+        // 端口为0时由系统分配 取实际绑定的地址
+        let socket_addr = match listen.local_addr() {
+            Ok(local_addr) => local_addr,
+            Err(_) => addr,
+        };
+        Ok(TcpListen {
+            listen,
+            socket_addr,
+        })
     }
 
     #[inline]
     pub fn get_listen(&self) -> &TcpListener {
         &self.listen
     }
+
+    /// 监听的地址
+    #[inline]
+    pub fn get_socket_addr(&self) -> &SocketAddr {
+        &self.socket_addr
+    }
+}
+
+#[test]
+fn test_listen_dual_stack() {
+    use std::net::TcpStream;
+    let listen_addr = ListenAddr::new("[::]:0", false);
+    match TcpListen::new(&listen_addr, &TcpListenConfig::new()) {
+        Ok(tcp_listen) => {
+            let port = tcp_listen.get_socket_addr().port();
+            // 双栈监听 IPv4 连接也能连上
+            assert!(TcpStream::connect(("127.0.0.1", port)).is_ok());
+            assert!(TcpStream::connect(("::1", port)).is_ok());
+        }
+        // 只有系统没有启用IPv6时才允许失败
+        Err(err) => assert!(TcpListener::bind("[::1]:0").is_err(), "TcpListen::new [::]:0 error:{}", err),
+    }
+}
+
+#[test]
+fn test_listen_option() {
+    let mut config = TcpListenConfig::new();
+    config.set_listen_backlog(64).set_defer_accept(3).set_tcp_fastopen(16);
+    let tcp_listen = TcpListen::new(&ListenAddr::new("127.0.0.1:0", false), &config).unwrap();
+    let raw_fd = tcp_listen.get_listen().as_raw_fd();
+    assert_eq!(os_socket::getsockopt::<i32>(raw_fd, libc::SOL_TCP, libc::TCP_DEFER_ACCEPT).unwrap(), 3);
+    assert_eq!(os_socket::getsockopt::<i32>(raw_fd, libc::SOL_TCP, libc::TCP_FASTOPEN).unwrap(), 16);
+    // 监听的 socket TCP_INFO 的 tcpi_sacked 是 backlog
+    let info = os_socket::getsockopt::<libc::tcp_info>(raw_fd, libc::SOL_TCP, libc::TCP_INFO).unwrap();
+    assert_eq!(info.tcpi_sacked, 64);
 }
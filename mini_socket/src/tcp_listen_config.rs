@@ -1,3 +1,29 @@
+use crate::error::Error;
+use crate::tcp_socket_option::{KeepAlive, SocketOption};
+use crate::tcp_socket_rw::RwConfig;
+
+/// 监听地址
+#[derive(Debug, Clone)]
+pub struct ListenAddr {
+    /// 例如:0.0.0.0:9999 或 [::]:9999
+    pub socket_addr: String,
+
+    /// default: true
+    /// 只对IPv6地址有效(IPV6_V6ONLY)
+    /// true--->只接收IPv6连接
+    /// false-->双栈 同时接收IPv4连接
+    pub ipv6_only: bool,
+}
+
+impl ListenAddr {
+    pub fn new(socket_addr: &str, ipv6_only: bool) -> Self {
+        ListenAddr {
+            ipv6_only,
+            socket_addr: socket_addr.to_string(),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct TcpListenConfig {
     /// default: true;
@@ -20,6 +46,7 @@ pub struct TcpListenConfig {
 
     /// default: 1毫秒
     /// epoll等待网络事件时长
+    /// schedule 的定时任务由 timerfd 唤醒 不受这个时长影响
     pub epoll_wait_timeout: i32,
     
     /// defalut: 256
@@ -27,8 +54,62 @@ pub struct TcpListenConfig {
     /// 局域网设置建议设置2048以上
     pub msg_deque_size: usize,
 
-    /// default:0.0.0.0:9999
-    pub bind_socket_addr: String,
+    /// default:0 不启用
+    /// 待发送消息数达到高水位时通知 SProtoId::QueueHighMark
+    /// 应小于 msg_deque_size 到达 msg_deque_size 后按 close_on_queue_full 处理
+    pub msg_deque_high_mark: usize,
+
+    /// default:0
+    /// 超过高水位后 待发送消息数降到低水位时通知 SProtoId::QueueLowMark
+    pub msg_deque_low_mark: usize,
+
+    /// default:0 不启用
+    /// 单位:毫秒 待发送消息数持续在高水位以上超过这个时长 断开连接
+    pub high_mark_timeout: u64,
+
+    /// default: false
+    /// true--->上游(net_msg_cb_fn)已满时 暂停读这个连接的数据
+    /// 没有交给上游的消息保存起来 定时重试 成功后恢复读
+    /// false-->丢弃没有交给上游的消息
+    pub pause_read_on_full: bool,
+
+    /// default: false
+    /// true--->发送队列已满时断开连接 断开原因 Error::QueueFull
+    /// false-->丢弃这条消息 通知 SProtoId::MsgQueueFull
+    pub close_on_queue_full: bool,
+
+    /// default: false
+    /// true--->连接的数据以 HAProxy PROXY protocol v1/v2 头开始
+    /// 解析出客户端真实地址后 再把数据交给 TcpSocketRw
+    /// 只在负载均衡后面时启用 否则客户端可以伪造地址
+    pub proxy_protocol: bool,
+
+    /// default:0 不启用
+    /// 超过单帧上限的消息拆成分片发送 接收方重组
+    /// 重组后消息的最大字节数 两端要一致
+    pub chunk_max_size: usize,
+
+    /// default:0 不压缩
+    /// 包体字节数不小于这个值时用 lz4 压缩 设置 MsgData.ext 第2位
+    /// 收到客户端压缩过的消息后才压缩 旧的客户端不会收到压缩的消息
+    /// 广播 组播共用的包体不压缩
+    pub compress_threshold: usize,
+
+    /// default:None 不加密
+    /// 服务端的 X25519 私钥 用 msg_crypto::gen_keypair 生成 公钥配置在客户端
+    /// 设置后客户端连接后要先发 KeyExchange 之后所有消息加密 否则断开
+    /// 收到 KeyExchange 之前发给这个连接的消息会断开连接 不会发出明文
+    pub encrypt_secret: Option<[u8; 32]>,
+
+    /// default: false
+    /// true--->包头后加4个字节的 CRC32C 校验 不一致时断开连接
+    /// 客户端要同样设置 用于跨不可靠网络的局域网连接
+    pub checksum: bool,
+
+    /// default:[0.0.0.0:9999]
+    /// 监听地址列表 每个地址有自己的listen id
+    /// listen id 按列表顺序从0开始
+    pub vec_listen_addr: Vec<ListenAddr>,
 
     /// default:0
     /// 设置太少会阻塞网络通信
@@ -40,7 +121,29 @@ pub struct TcpListenConfig {
     /// 外网要设置大小防攻击，一般8192
     /// 局域网设置为:0 由系统分配 tcp_window_scaling = 1
     pub socket_write_buffer: u32,
-    
+
+    /// 每个连接的 socket 选项 见 SocketOption
+    pub socket_option: SocketOption,
+
+    /// default:1024
+    /// 等待accept的连接队列长度 listen(backlog)
+    /// 系统会截断到 net.core.somaxconn
+    pub listen_backlog: i32,
+
+    /// default:0 不设置
+    /// 单位:秒 连接收到数据后才 accept TCP_DEFER_ACCEPT
+    /// 连接后不发数据的对方 accept 会延迟这个时长以上
+    pub defer_accept: u32,
+
+    /// default:0 不启用
+    /// TCP_FASTOPEN 等待三次握手完成的 TFO 连接队列长度
+    /// 要开启 net.ipv4.tcp_fastopen 的服务端位(2)
+    pub tcp_fastopen: u32,
+
+    /// default:3000
+    /// 单位:毫秒 close_after_flush 后等待消息发完和对方关闭的最长时间
+    /// 超时后直接关闭连接
+    pub close_linger: u64,
 }
 
 impl TcpListenConfig {
@@ -54,7 +157,22 @@ impl TcpListenConfig {
             epoll_wait_timeout: 1,
             socket_read_buffer: 0,
             socket_write_buffer: 0,
-            bind_socket_addr: "0.0.0.0:9999".into(),
+            msg_deque_high_mark: 0,
+            msg_deque_low_mark: 0,
+            high_mark_timeout: 0,
+            pause_read_on_full: false,
+            close_on_queue_full: false,
+            proxy_protocol: false,
+            chunk_max_size: 0,
+            compress_threshold: 0,
+            encrypt_secret: None,
+            checksum: false,
+            socket_option: SocketOption::default(),
+            listen_backlog: 1024,
+            defer_accept: 0,
+            tcp_fastopen: 0,
+            close_linger: 3000,
+            vec_listen_addr: vec![ListenAddr::new("0.0.0.0:9999", true)],
         }
     }
 
@@ -93,8 +211,16 @@ impl TcpListenConfig {
         self
     }
 
-    pub fn set_bind_socket_addr(&mut self, val: &String) -> &mut Self {
-        self.bind_socket_addr = val.clone();
+    /// 只监听这一个地址
+    pub fn set_bind_socket_addr(&mut self, val: &str) -> &mut Self {
+        self.vec_listen_addr = vec![ListenAddr::new(val, true)];
+        self
+    }
+
+    /// 增加一个监听地址
+    /// ipv6_only: false 时 IPv6地址同时接收IPv4连接
+    pub fn add_bind_socket_addr(&mut self, val: &str, ipv6_only: bool) -> &mut Self {
+        self.vec_listen_addr.push(ListenAddr::new(val, ipv6_only));
         self
     }
 
@@ -102,4 +228,134 @@ impl TcpListenConfig {
         self.msg_deque_size = val;
         self
     }
+
+    /// 待发送消息数的高低水位 high:0 不启用
+    pub fn set_msg_deque_mark(&mut self, high: usize, low: usize) -> &mut Self {
+        self.msg_deque_high_mark = high;
+        self.msg_deque_low_mark = low;
+        self
+    }
+
+    pub fn set_high_mark_timeout(&mut self, val: u64) -> &mut Self {
+        self.high_mark_timeout = val;
+        self
+    }
+
+    pub fn set_pause_read_on_full(&mut self, val: bool) -> &mut Self {
+        self.pause_read_on_full = val;
+        self
+    }
+
+    pub fn set_close_on_queue_full(&mut self, val: bool) -> &mut Self {
+        self.close_on_queue_full = val;
+        self
+    }
+
+    pub fn set_proxy_protocol(&mut self, val: bool) -> &mut Self {
+        self.proxy_protocol = val;
+        self
+    }
+
+    /// 分片重组后消息的最大字节数 0:不启用
+    pub fn set_chunk_max_size(&mut self, val: usize) -> &mut Self {
+        self.chunk_max_size = val;
+        self
+    }
+
+    /// 压缩的最小包体字节数 0:不压缩
+    pub fn set_compress_threshold(&mut self, val: usize) -> &mut Self {
+        self.compress_threshold = val;
+        self
+    }
+
+    /// 服务端的 X25519 私钥 None:不加密
+    pub fn set_encrypt_secret(&mut self, val: Option<[u8; 32]>) -> &mut Self {
+        self.encrypt_secret = val;
+        self
+    }
+
+    /// 包头后加 CRC32C 校验 客户端要同样设置
+    pub fn set_checksum(&mut self, val: bool) -> &mut Self {
+        self.checksum = val;
+        self
+    }
+
+    /// TCP 保活探测 单位:秒 None:不启用
+    pub fn set_keepalive(&mut self, val: Option<KeepAlive>) -> &mut Self {
+        self.socket_option.keepalive = val;
+        self
+    }
+
+    /// 发出的数据没有确认的最大时长 单位:毫秒 0:不设置
+    pub fn set_user_timeout(&mut self, val: u32) -> &mut Self {
+        self.socket_option.user_timeout = val;
+        self
+    }
+
+    /// SO_LINGER 只支持 Some(0): close 时直接发 RST None:不设置
+    pub fn set_linger(&mut self, val: Option<u16>) -> &mut Self {
+        self.socket_option.linger = val;
+        self
+    }
+
+    /// IP_TOS/IPV6_TCLASS 0:不设置
+    pub fn set_tos(&mut self, val: u8) -> &mut Self {
+        self.socket_option.tos = val;
+        self
+    }
+
+    /// 按 DSCP 设置 tos 只用低6位
+    pub fn set_dscp(&mut self, val: u8) -> &mut Self {
+        self.socket_option.tos = (val & 0x3f) << 2;
+        self
+    }
+
+    pub fn set_listen_backlog(&mut self, val: i32) -> &mut Self {
+        self.listen_backlog = val;
+        self
+    }
+
+    /// 单位:秒 0:不设置
+    pub fn set_defer_accept(&mut self, val: u32) -> &mut Self {
+        self.defer_accept = val;
+        self
+    }
+
+    /// TFO 队列长度 0:不启用
+    pub fn set_tcp_fastopen(&mut self, val: u32) -> &mut Self {
+        self.tcp_fastopen = val;
+        self
+    }
+
+    /// close_after_flush 后关闭连接的最长时间 单位:毫秒
+    pub fn set_close_linger(&mut self, val: u64) -> &mut Self {
+        self.close_linger = val;
+        self
+    }
+
+    /// 检查 socket 选项 错误信息带上选项名
+    pub fn check(&self) -> Result<(), Error> {
+        if self.listen_backlog <= 0 {
+            return Err(Error::Config(format!("listen_backlog:{} must > 0", self.listen_backlog)));
+        }
+        if self.defer_accept > i32::MAX as u32 {
+            return Err(Error::Config(format!("TCP_DEFER_ACCEPT:{} too large", self.defer_accept)));
+        }
+        if self.tcp_fastopen > i32::MAX as u32 {
+            return Err(Error::Config(format!("TCP_FASTOPEN:{} too large", self.tcp_fastopen)));
+        }
+        self.socket_option.check()
+    }
+
+    /// 传给每个连接 TcpSocketRw 的配置
+    pub fn get_rw_config(&self) -> RwConfig {
+        RwConfig {
+            chunk_max_size: self.chunk_max_size,
+            compress_threshold: self.compress_threshold,
+            compress_first: false,
+            encrypt_secret: self.encrypt_secret,
+            encrypt_server_key: None,
+            checksum: self.checksum,
+        }
+    }
 }
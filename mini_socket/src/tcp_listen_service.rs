@@ -1,38 +1,87 @@
-use crate::os_epoll::OSEpoll;
+use crate::error::Error;
+use crate::os_epoll::TIMER_ID;
+use crate::os_poll::OSPoll;
 use crate::os_socket;
 use crate::tcp_listen::TcpListen;
 use crate::tcp_listen_config::TcpListenConfig;
 use crate::tcp_socket_mgmt::TcpSocketMgmt;
 use crate::tcp_socket_rw::ReadResult;
 use crate::tcp_socket_rw::TcpSocketRw;
-use crate::tcp_socket_rw::WriteResult;
 use crate::tcp_socket_msg::{SProtoId};
 
 use libc;
 use log::{error, info, warn};
-use std::io::Error;
+use mini_utils::time;
+use mini_utils::wtimer::IWTask;
+use mini_utils::wtimer::WTimer;
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::io::ErrorKind;
 use std::marker::PhantomData;
+use std::mem;
+use std::net::SocketAddr;
+use std::net::Shutdown;
 use std::net::TcpStream;
 use std::os::unix::io::AsRawFd;
+use std::rc::Rc;
 
 use std::thread;
 
-use crate::tcp_socket::TcpSocket;
+use crate::tcp_socket::TcpSocketStats;
 
-const LISTEN_ID: u64 = 0;
-const EPOLL_IN_OUT: i32 = (libc::EPOLLOUT | libc::EPOLLIN) as i32;
+/// 上游已满时 重试交给上游的间隔 单位:毫秒
+const RESUME_READ_INTERVAL: u64 = 1;
+
+/// 内部定时任务 到期后在 timer_event 中处理
+#[derive(Debug, Clone, Copy)]
+enum ServiceTimer {
+    /// 重新把暂停读的连接的消息交给上游
+    ResumeRead,
+    /// 检查连接在高水位以上是否超时
+    HighMark(u64),
+    /// 检查正在关闭的连接是否超过 close_linger
+    Closing(u64),
+}
+
+/// 到期时只记录 ServiceTimer 执行时要访问 TcpListenService
+struct ServiceTask {
+    timer: ServiceTimer,
+    expired: Rc<RefCell<Vec<ServiceTimer>>>,
+}
+
+impl IWTask for ServiceTask {
+    fn execute(&mut self) -> bool {
+        self.expired.borrow_mut().push(self.timer);
+        true
+    }
+}
 
 pub struct TcpListenService<'a, TBRW, MSG> {
-    os_epoll: OSEpoll,
+    /// 要在 tcp_socket_mgmt 之前 drop 等待 io_uring 中的读写结束
+    os_poll: OSPoll,
     share_buffer: Vec<u8>,
-    tcp_listen: TcpListen,
+    /// 下标就是 listen id
+    vec_tcp_listen: Vec<TcpListen>,
     phantom: PhantomData<TBRW>,
     config: &'a TcpListenConfig,
     tcp_socket_mgmt: TcpSocketMgmt<MSG>,
     vec_epoll_event: Vec<libc::epoll_event>,
-    net_msg_cb_fn: &'a mut dyn Fn(u64, Vec<MSG>),
-    exc_msg_cb_fn: &'a mut dyn Fn(u64, SProtoId),
+    /// 暂停读的连接 cid 没有交给上游的消息
+    read_paused: HashMap<u64, Vec<MSG>>,
+    /// 待发送消息数在高水位以上的连接 cid 超过高水位的时间
+    high_mark_cid: HashMap<u64, u64>,
+    /// close_after_flush 正在关闭的连接 cid (关闭的截止时间, 对方已关闭)
+    closing_cid: HashMap<u64, (u64, bool)>,
+    /// 返回没有交给上游的消息 不为空表示上游已满
+    net_msg_cb_fn: &'a mut dyn Fn(u64, Vec<MSG>) -> Vec<MSG>,
+    /// Disconnect 时带有断开原因
+    exc_msg_cb_fn: &'a mut dyn Fn(u64, SProtoId, Option<&Error>),
+    /// 定时任务 由 os_poll 的 timerfd 驱动
+    wtimer: WTimer,
+    /// 已到期还没处理的内部定时任务
+    expired_timer: Rc<RefCell<Vec<ServiceTimer>>>,
+    /// 已经有 ResumeRead 定时任务
+    resume_read_scheduled: bool,
 }
 
 impl<'a, TBRW, MSG> Drop for TcpListenService<'a, TBRW, MSG> {
@@ -51,20 +100,30 @@ where
 {
     pub fn new(
         config: &'a TcpListenConfig,
-        net_msg_cb_fn: &'a mut dyn Fn(u64, Vec<MSG>),
-        exc_msg_cb_fn: &'a mut dyn Fn(u64, SProtoId),
-    ) -> Result<Self, String> {
-        let os_epoll: OSEpoll = OSEpoll::new()?;
+        net_msg_cb_fn: &'a mut dyn Fn(u64, Vec<MSG>) -> Vec<MSG>,
+        exc_msg_cb_fn: &'a mut dyn Fn(u64, SProtoId, Option<&Error>),
+    ) -> Result<Self, Error> {
+        let os_poll = OSPoll::new()?;
+        info!("tcp listen poll:{}", os_poll.name());
 
-        let tcp_listen = TcpListen::new(&config.bind_socket_addr)?;
-        let rawfd = tcp_listen.get_listen().as_raw_fd();
-        os_epoll.ctl_add_fd(LISTEN_ID, rawfd, libc::EPOLLIN)?;
+        if config.vec_listen_addr.is_empty() {
+            return Err(Error::Config("TcpListenConfig vec_listen_addr is empty".into()));
+        }
+        config.check()?;
+
+        let mut vec_tcp_listen = Vec::with_capacity(config.vec_listen_addr.len());
+        for listen_addr in config.vec_listen_addr.iter() {
+            let listen_id = vec_tcp_listen.len() as u64;
+            let tcp_listen = TcpListen::new(listen_addr, config)?;
+            let rawfd = tcp_listen.get_listen().as_raw_fd();
+            os_poll.ctl_add_fd(listen_id, rawfd, libc::EPOLLIN)?;
+            info!("tcp listen id:{} addr:{}", listen_id, tcp_listen.get_socket_addr());
+            vec_tcp_listen.push(tcp_listen);
+        }
 
-        let tcp_socket_mgmt = TcpSocketMgmt::new(
-            LISTEN_ID,
-            config.max_tcp_socket,
-            config.msg_deque_size as usize,
-        );
+        let mut tcp_socket_mgmt =
+            TcpSocketMgmt::new(config.max_tcp_socket, config.msg_deque_size as usize);
+        tcp_socket_mgmt.set_rw_config(config.get_rw_config());
 
         let mut share_buffer_size = config.socket_read_buffer as usize * 2;
         if share_buffer_size == 0 {
@@ -72,13 +131,19 @@ where
         }
 
         Ok(TcpListenService {
-            os_epoll,
+            os_poll,
             config,
-            tcp_listen,
+            vec_tcp_listen,
             net_msg_cb_fn,
             exc_msg_cb_fn,
             tcp_socket_mgmt,
             phantom: PhantomData,
+            read_paused: HashMap::new(),
+            wtimer: WTimer::new(1),
+            expired_timer: Rc::new(RefCell::new(vec![])),
+            resume_read_scheduled: false,
+            high_mark_cid: HashMap::new(),
+            closing_cid: HashMap::new(),
             share_buffer: vec![0u8; share_buffer_size],
             vec_epoll_event: vec![
                 libc::epoll_event { events: 0, u64: 0 };
@@ -87,31 +152,169 @@ where
         })
     }
 
-    fn write_data(
-        cid: u64,
-        os_epoll: &OSEpoll,
-        tcp_socket: &mut TcpSocket<MSG>,
-    ) -> Result<(), String> {
-        match tcp_socket.write() {
-            WriteResult::Finish => {
-                if tcp_socket.epevs == libc::EPOLLIN {
-                    return Ok(());
-                }
-                tcp_socket.epevs = libc::EPOLLIN;
-                return os_epoll.ctl_mod_fd(cid, tcp_socket.socket.as_raw_fd(), libc::EPOLLIN);
+    /// 增加内部定时任务
+    fn schedule_timer(&mut self, delay: u64, timer: ServiceTimer) {
+        let task = ServiceTask { timer, expired: self.expired_timer.clone() };
+        self.schedule(delay, 0, Box::new(task));
+    }
+
+    /// 有暂停读的连接时 定时重试交给上游
+    fn schedule_resume_read(&mut self) {
+        if self.resume_read_scheduled || self.read_paused.is_empty() {
+            return;
+        }
+        self.resume_read_scheduled = true;
+        self.schedule_timer(RESUME_READ_INTERVAL, ServiceTimer::ResumeRead);
+    }
+
+    /// 重新把暂停读的连接的消息交给上游 成功后恢复读
+    fn resume_read(&mut self) {
+        self.resume_read_scheduled = false;
+        let vec_cid: Vec<u64> = self.read_paused.keys().copied().collect();
+        for cid in vec_cid {
+            let vec_msg = match self.read_paused.remove(&cid) {
+                Some(vec_msg) => vec_msg,
+                None => continue,
+            };
+            let vec_rest = (self.net_msg_cb_fn)(cid, vec_msg);
+            if !vec_rest.is_empty() {
+                // 上游还是满的 稍后再试
+                self.read_paused.insert(cid, vec_rest);
+                break;
+            }
+            // epoll 是边缘触发 要主动读完暂停期间收到的数据
+            self.read_event(cid);
+        }
+        self.schedule_resume_read();
+    }
+
+    /// 连接在高水位以上超过 high_mark_timeout 时断开
+    fn check_high_mark_timeout(&mut self, cid: u64) {
+        let timeout = self.config.high_mark_timeout;
+        match self.high_mark_cid.get(&cid) {
+            // 中间降到过低水位又超过高水位的 由新的定时任务检查
+            Some(ts) if *ts + timeout < time::timestamp() => (),
+            _ => return,
+        }
+        self.del_tcp_socket(cid);
+        warn!("cid:{} above high mark timeout:{}ms", cid, timeout);
+        let err = Error::HighMarkTimeout(timeout);
+        (self.exc_msg_cb_fn)(cid, SProtoId::Disconnect, Some(&err));
+    }
+
+    /// 发完队列中的消息再关闭连接
+    /// 不再读数据 不再接受新消息 发完后 shutdown(Write)
+    /// 对方关闭或超过 close_linger 后关闭 关闭时不回调 exc_msg_cb_fn
+    /// 返回false: 连接不存在
+    pub fn close_after_flush(&mut self, cid: u64) -> bool {
+        if self.closing_cid.contains_key(&cid) {
+            return true;
+        }
+        if self.tcp_socket_mgmt.get_tcp_socket(cid).is_none() {
+            info!("close_after_flush cid:{} no exist", cid);
+            return false;
+        }
+        self.high_mark_cid.remove(&cid);
+        if let Some(vec_msg) = self.read_paused.remove(&cid) {
+            warn!("cid:{} closing drop paused msg num:{}", cid, vec_msg.len());
+        }
+        self.closing_cid.insert(cid, (time::timestamp() + self.config.close_linger, false));
+        self.schedule_timer(self.config.close_linger + 1, ServiceTimer::Closing(cid));
+        self.flush_closing(cid);
+        true
+    }
+
+    /// 正在关闭的连接 队列发完后 shutdown(Write) 等待对方关闭
+    /// 对方已经关闭时 发完直接关闭连接
+    fn flush_closing(&mut self, cid: u64) {
+        let tcp_socket = match self.tcp_socket_mgmt.get_tcp_socket(cid) {
+            Some(tcp_socket) => tcp_socket,
+            None => return,
+        };
+        let result = match self.os_poll.write(cid, tcp_socket) {
+            Ok(()) if tcp_socket.vec_queue_len() == 0 => {
+                tcp_socket.socket.shutdown(Shutdown::Write).map(|_| true).map_err(Error::from)
+            }
+            result => result.map(|_| false),
+        };
+        match result {
+            Ok(true) if matches!(self.closing_cid.get(&cid), Some((_, true))) => self.del_tcp_socket(cid),
+            Ok(_) => (),
+            Err(err) => {
+                info!("cid:{} closing flush err:{}", cid, err);
+                self.del_tcp_socket(cid);
             }
-            WriteResult::BufferFull => {
-                if tcp_socket.epevs == EPOLL_IN_OUT {
-                    return Ok(());
+        }
+    }
+
+    /// 正在关闭的连接 丢弃收到的数据 对方关闭且消息已发完后关闭连接
+    fn read_closing(&mut self, cid: u64) {
+        let tcp_socket = match self.tcp_socket_mgmt.get_tcp_socket(cid) {
+            Some(tcp_socket) => tcp_socket,
+            None => return,
+        };
+        loop {
+            match self.os_poll.recv(cid, &mut tcp_socket.socket, &mut self.share_buffer) {
+                Ok(0) => {
+                    // 对方只关闭了写 继续发送 边缘触发不会再有读事件 发完后由 flush_closing 关闭
+                    if tcp_socket.vec_queue_len() > 0 {
+                        if let Some((_, peer_closed)) = self.closing_cid.get_mut(&cid) {
+                            *peer_closed = true;
+                        }
+                        return;
+                    }
+                    break;
+                }
+                Ok(_) => continue,
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => return,
+                Err(ref err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    info!("cid:{} closing read err:{}", cid, err);
+                    break;
                 }
-                tcp_socket.epevs = EPOLL_IN_OUT;
-                return os_epoll.ctl_mod_fd(cid , tcp_socket.socket.as_raw_fd(), EPOLL_IN_OUT);
             }
-            WriteResult::Error(err) => return Err(err),
+        }
+        self.del_tcp_socket(cid);
+    }
+
+    /// 正在关闭的连接超过 close_linger 时关闭
+    fn check_closing(&mut self, cid: u64) {
+        match self.closing_cid.get(&cid) {
+            Some((ts, _)) if *ts < time::timestamp() => (),
+            _ => return,
+        }
+        warn!("cid:{} close linger timeout:{}ms", cid, self.config.close_linger);
+        self.del_tcp_socket(cid);
+    }
+
+    /// 消息交给上游 上游已满时按配置暂停读或丢弃
+    fn deliver_msg(&mut self, cid: u64, vec_msg: Vec<MSG>) {
+        let vec_rest = (self.net_msg_cb_fn)(cid, vec_msg);
+        if vec_rest.is_empty() {
+            return;
+        }
+        if self.config.pause_read_on_full {
+            info!("cid:{} upstream full pause read", cid);
+            self.read_paused.insert(cid, vec_rest);
+            self.schedule_resume_read();
+        } else {
+            error!("cid:{} upstream full drop msg num:{}", cid, vec_rest.len());
         }
     }
 
-    pub fn tick(&mut self) {}
+    /// 待发送消息数降到低水位 通知上层
+    fn check_low_mark(&mut self, cid: u64) {
+        if !self.high_mark_cid.contains_key(&cid) {
+            return;
+        }
+        let low_mark = std::cmp::min(self.config.msg_deque_low_mark, self.config.msg_deque_high_mark);
+        if let Some(tcp_socket) = self.tcp_socket_mgmt.get_tcp_socket(cid) {
+            if tcp_socket.vec_queue_len() <= low_mark {
+                self.high_mark_cid.remove(&cid);
+                (self.exc_msg_cb_fn)(cid, SProtoId::QueueLowMark, None);
+            }
+        }
+    }
 
     /// 获取连接的 tcp_sokcet 数量
     #[inline]
@@ -119,15 +322,80 @@ where
         self.tcp_socket_mgmt.tcp_socket_count()
     }
 
-    pub fn epoll_event(&mut self, wait_timeout: i32) -> Result<u32, String> {
+    /// 监听地址的数量 listen id: 0..listen_num
+    #[inline]
+    pub fn listen_num(&self) -> u64 {
+        self.vec_tcp_listen.len() as u64
+    }
+
+    /// 根据 listen id 获取监听的地址
+    #[inline]
+    pub fn get_listen_addr(&self, listen_id: u64) -> Option<&SocketAddr> {
+        self.vec_tcp_listen
+            .get(listen_id as usize)
+            .map(|tcp_listen| tcp_listen.get_socket_addr())
+    }
+
+    /// 连接是从哪个监听地址accept的
+    #[inline]
+    pub fn get_listen_id(&self, cid: u64) -> Option<u64> {
+        self.tcp_socket_mgmt.get_listen_id(cid)
+    }
+
+    /// 连接的地址 时间及收发统计
+    #[inline]
+    pub fn get_stats(&self, cid: u64) -> Option<&TcpSocketStats> {
+        self.tcp_socket_mgmt.get_stats(cid)
+    }
+
+    /// 遍历所有连接的统计 (cid, stats)
+    #[inline]
+    pub fn iter_stats(&self) -> impl Iterator<Item = (u64, &TcpSocketStats)> {
+        self.tcp_socket_mgmt.iter_stats()
+    }
+
+    /// 增加定时任务 在 epoll_event 中执行
+    /// delay: 延迟毫秒 interval: 重复间隔毫秒 task.execute() 返回 true 后不再执行
+    /// 任务到期时 epoll_wait 会被唤醒 wait_timeout 不影响定时精度
+    pub fn schedule(&mut self, delay: u64, interval: u64, task: Box<dyn IWTask>) {
+        self.wtimer.push_task(delay, interval, task);
+        self.set_timer();
+    }
+
+    /// 把 timerfd 设置为下一个任务的到期时间
+    fn set_timer(&mut self) {
+        if let Err(err) = self.os_poll.set_timer(self.wtimer.next_expire()) {
+            error!("os_poll set_timer error:{}", err);
+        }
+    }
+
+    fn timer_event(&mut self) {
+        self.os_poll.read_timer();
+        self.wtimer.scheduled(time::timestamp());
+        let vec_timer = mem::take(&mut *self.expired_timer.borrow_mut());
+        for timer in vec_timer {
+            match timer {
+                ServiceTimer::ResumeRead => self.resume_read(),
+                ServiceTimer::HighMark(cid) => self.check_high_mark_timeout(cid),
+                ServiceTimer::Closing(cid) => self.check_closing(cid),
+            }
+        }
+        self.set_timer();
+    }
+
+    pub fn epoll_event(&mut self, wait_timeout: i32) -> Result<u32, Error> {
         // todo 根据测试代码 死循环向同一条连接中发数据 wait 200多毫秒才会触发一次事件
-        match self.os_epoll.wait(wait_timeout, &mut self.vec_epoll_event) {
+        match self.os_poll.wait(wait_timeout, &mut self.vec_epoll_event) {
             Ok(0) => Ok(0),
             Ok(epevs) => {
                 for n in 0..epevs as usize {
                     let event = self.vec_epoll_event[n];
-                    if event.u64 == LISTEN_ID {
-                        self.accept_event();
+                    if event.u64 == TIMER_ID {
+                        self.timer_event();
+                        continue;
+                    }
+                    if event.u64 < self.listen_num() {
+                        self.accept_event(event.u64);
                         continue;
                     }
                     if (event.events & libc::EPOLLIN as u32) != 0 {
@@ -137,7 +405,7 @@ where
                         self.write_event(event.u64);
                     }
                     if (event.events & libc::EPOLLERR as u32) != 0 {
-                        self.error_event(event.u64, Error::last_os_error().to_string());
+                        self.error_event(event.u64);
                     }
                 }
                 return Ok(epevs);
@@ -148,74 +416,168 @@ where
 
     fn read_event(&mut self, cid: u64) {
         //info!("read id:{}", cid);
-        if let Some(tcp_socket) = self.tcp_socket_mgmt.get_tcp_socket(cid) {
-            match tcp_socket.read(&mut self.share_buffer) {
-                ReadResult::Data(vec_msg) => {
-                    (self.net_msg_cb_fn)(cid, vec_msg);
-                }
-                ReadResult::Error(vec_msg, err) => {
-                    self.del_tcp_socket(cid);
-                    (self.net_msg_cb_fn)(cid, vec_msg);
-                    (self.exc_msg_cb_fn)(cid, SProtoId::Disconnect);
-                    error!("tcp_socket.read id:{} err:{}", cid, err);
+        if self.read_paused.contains_key(&cid) {
+            return;
+        }
+        if self.closing_cid.contains_key(&cid) {
+            self.read_closing(cid);
+            return;
+        }
+        let result = if let Some(tcp_socket) = self.tcp_socket_mgmt.get_tcp_socket(cid) {
+            if tcp_socket.is_proxy_header_pending() {
+                match tcp_socket.read_proxy_header(&mut self.share_buffer) {
+                    Ok(true) => {
+                        if let Some(addr) = tcp_socket.get_stats().peer_addr {
+                            info!("cid:{} proxy protocol client addr:{}", cid, addr);
+                        }
+                        self.os_poll.read(cid, tcp_socket, &mut self.share_buffer)
+                    }
+                    Ok(false) => return,
+                    Err(err) => ReadResult::Error(vec![], err),
                 }
+            } else {
+                self.os_poll.read(cid, tcp_socket, &mut self.share_buffer)
             }
         } else {
             warn!("read_event tcp_socket_mgmt id no exitis:{}", cid);
+            return;
         };
+        match result {
+            ReadResult::Data(vec_msg) => {
+                self.deliver_msg(cid, vec_msg);
+            }
+            ReadResult::Error(vec_msg, err) => {
+                self.del_tcp_socket(cid);
+                let vec_rest = (self.net_msg_cb_fn)(cid, vec_msg);
+                if !vec_rest.is_empty() {
+                    error!("cid:{} upstream full drop msg num:{}", cid, vec_rest.len());
+                }
+                error!("tcp_socket.read id:{} err:{}", cid, err);
+                (self.exc_msg_cb_fn)(cid, SProtoId::Disconnect, Some(&err));
+            }
+        }
     }
 
+    /// 消息放入连接的发送队列
+    /// 返回false: 连接不存在 正在关闭 队列已满 或写数据出错
     #[inline]
-    pub fn write_msg(&mut self, cid: u64, msg: MSG) {
+    pub fn write_msg(&mut self, cid: u64, msg: MSG) -> bool {
+        if self.closing_cid.contains_key(&cid) {
+            info!("write_msg cid:{} is closing", cid);
+            return false;
+        }
         let msg_deque_size = self.tcp_socket_mgmt.get_msg_deque_size();
         match self.tcp_socket_mgmt.get_tcp_socket(cid) {
             Some(tcp_socket) => {
                 if tcp_socket.vec_queue_len() > msg_deque_size {
-                    info!("cid:{} Msg Queue Is Full", cid);
-                    (self.exc_msg_cb_fn)(cid, SProtoId::MsgQueueFull);
-                    return;
+                    if self.config.close_on_queue_full {
+                        self.del_tcp_socket(cid);
+                        warn!("cid:{} Msg Queue Is Full disconnect", cid);
+                        (self.exc_msg_cb_fn)(cid, SProtoId::Disconnect, Some(&Error::QueueFull));
+                    } else {
+                        info!("cid:{} Msg Queue Is Full", cid);
+                        (self.exc_msg_cb_fn)(cid, SProtoId::MsgQueueFull, None);
+                    }
+                    return false;
+                }
+                if let Err(err) = tcp_socket.push_vec_queue(msg) {
+                    self.del_tcp_socket(cid);
+                    error!("cid:{} push_vec_queue err:{}", cid, err);
+                    (self.exc_msg_cb_fn)(cid, SProtoId::Disconnect, Some(&err));
+                    return false;
                 }
-                tcp_socket.push_vec_queue(msg);
 
                 if tcp_socket.vec_queue_len() == 1 {
-                    if let Err(err) = Self::write_data(cid, &self.os_epoll,tcp_socket) {
+                    if let Err(err) = self.os_poll.write(cid, tcp_socket) {
                         self.del_tcp_socket(cid);
                         info!("cid:{} write_data  err:{}", cid, err);
-                        (self.exc_msg_cb_fn)(cid, SProtoId::Disconnect);
+                        (self.exc_msg_cb_fn)(cid, SProtoId::Disconnect, Some(&err));
+                        return false;
                     }
                 }
+
+                let high_mark = self.config.msg_deque_high_mark;
+                if high_mark > 0
+                    && tcp_socket.vec_queue_len() >= high_mark
+                    && !self.high_mark_cid.contains_key(&cid)
+                {
+                    self.high_mark_cid.insert(cid, time::timestamp());
+                    (self.exc_msg_cb_fn)(cid, SProtoId::QueueHighMark, None);
+                    let timeout = self.config.high_mark_timeout;
+                    if timeout > 0 {
+                        self.schedule_timer(timeout + 1, ServiceTimer::HighMark(cid));
+                    }
+                }
+                true
             }
             None => {
                 info!("write_msg socket id:{} no exitis", cid);
-                (self.exc_msg_cb_fn)(cid, SProtoId::Disconnect);
+                (self.exc_msg_cb_fn)(cid, SProtoId::Disconnect, Some(&Error::NotConnected));
+                false
+            }
+        }
+    }
+
+    /// 把同一条消息发给多个连接
+    /// 每个连接单独编码包头 包体用 MsgBuf::Shared 时所有连接共用一份
+    /// 返回发送失败的连接id
+    pub fn broadcast(&mut self, vec_cid: &[u64], msg: MSG) -> Vec<u64>
+    where
+        MSG: Clone,
+    {
+        let mut vec_fail = vec![];
+        if let Some((last, cids)) = vec_cid.split_last() {
+            for cid in cids {
+                if !self.write_msg(*cid, msg.clone()) {
+                    vec_fail.push(*cid);
+                }
+            }
+            if !self.write_msg(*last, msg) {
+                vec_fail.push(*last);
             }
         }
+        vec_fail
     }
 
     fn write_event(&mut self, cid: u64) {
+        if self.closing_cid.contains_key(&cid) {
+            self.flush_closing(cid);
+            return;
+        }
         if let Some(tcp_socket) = self.tcp_socket_mgmt.get_tcp_socket(cid) {
-            if let Err(err) = Self::write_data(cid, &self.os_epoll, tcp_socket) {
+            if let Err(err) = self.os_poll.write(cid, tcp_socket) {
                 self.del_tcp_socket(cid);
                 warn!("write_event cid:{} err:{}", cid, err);
-                (self.exc_msg_cb_fn)(cid, SProtoId::Disconnect);
+                (self.exc_msg_cb_fn)(cid, SProtoId::Disconnect, Some(&err));
+                return;
             }
+            self.check_low_mark(cid);
         } else {
             error!("write_event cid:{} no exist", cid);
         }
     }
 
-    fn error_event(&mut self, cid: u64, err: String) {
+    fn error_event(&mut self, cid: u64) {
+        let err = match self.tcp_socket_mgmt.get_tcp_socket(cid) {
+            Some(tcp_socket) => os_socket::socket_error(tcp_socket.socket.as_raw_fd()),
+            None => return,
+        };
+        let is_closing = self.closing_cid.contains_key(&cid);
         self.del_tcp_socket(cid);
+        if is_closing {
+            info!("error_event closing cid:{} error:{}", cid, err);
+            return;
+        }
         error!("error_event cid:{} error:{}", cid, err);
-        (self.exc_msg_cb_fn)(cid, SProtoId::Disconnect);
+        (self.exc_msg_cb_fn)(cid, SProtoId::Disconnect, Some(&err));
     }
 
-    fn accept_event(&mut self) {
+    fn accept_event(&mut self, listen_id: u64) {
         loop {
-            match self.tcp_listen.get_listen().accept() {
+            match self.vec_tcp_listen[listen_id as usize].get_listen().accept() {
                 Ok((socket, addr)) => {
-                    self.new_socket(socket);
-                    info!("tcp listen serrver new_socket:{}", addr)
+                    self.new_socket(listen_id, socket);
+                    info!("tcp listen id:{} serrver new_socket:{}", listen_id, addr)
                 }
                 Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
                 Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
@@ -223,7 +585,7 @@ where
             }
         }
     }
-    fn new_socket(&mut self, socket: TcpStream) {
+    fn new_socket(&mut self, listen_id: u64, socket: TcpStream) {
         if let Err(err) = socket.set_nonblocking(true) {
             error!("new_socket set_nonblocking:{}", err);
             return;
@@ -259,16 +621,31 @@ where
                 return;
             }
         }
+
+        if let Err(err) = self.config.socket_option.apply(&socket) {
+            error!("new_socket {}", err);
+            return;
+        }
         
-        match self.tcp_socket_mgmt.add_tcp_socket::<TBRW>(socket) {
+        match self.tcp_socket_mgmt.add_tcp_socket::<TBRW>(listen_id, socket) {
             Ok(cid) => {
                 info!("tcp_socket_mgmt.add_tcp_socket cid:{}", cid);
-                match self.os_epoll.ctl_add_fd(cid, raw_fd, libc::EPOLLIN) {
-                    Ok(()) => (),
-                    Err(err) => {
-                        error!("os_epoll ctl_add_fd error:{}", err);
+                if let Some(tcp_socket) = self.tcp_socket_mgmt.get_tcp_socket(cid) {
+                    // 要在 add_socket 之前设置 io_uring 等到头读完再提交 recv
+                    if self.config.proxy_protocol {
+                        tcp_socket.set_proxy_header_pending(true);
+                    }
+                    if let Err(err) = self.os_poll.add_socket(cid, tcp_socket, false) {
+                        error!("os_poll add_socket error:{}", err);
+                    }
+                    // 发送 hello_msg
+                    if tcp_socket.vec_queue_len() > 0 {
+                        if let Err(err) = self.os_poll.write(cid, tcp_socket) {
+                            self.del_tcp_socket(cid);
+                            warn!("cid:{} write hello msg err:{}", cid, err);
+                        }
                     }
-                };
+                }
             }
             Err(err) => {
                 error!("new_socket:{}", err);
@@ -276,13 +653,18 @@ where
         }
     }
     pub fn del_tcp_socket(&mut self, cid: u64) {
+        self.high_mark_cid.remove(&cid);
+        self.closing_cid.remove(&cid);
+        if let Some(vec_msg) = self.read_paused.remove(&cid) {
+            warn!("cid:{} paused read drop msg num:{}", cid, vec_msg.len());
+        }
         match self.tcp_socket_mgmt.del_tcp_socket(cid) {
             Ok(tcp_socket) => {
                 let rawfd = tcp_socket.socket.as_raw_fd();
-                if let Err(err) = self.os_epoll.ctl_del_fd(cid, rawfd) {
-                    warn!("os_epoll.ctl_del_fd({}) Error:{}", cid, err);
+                if let Err(err) = self.os_poll.del_socket(cid, rawfd) {
+                    warn!("os_poll.del_socket({}) Error:{}", cid, err);
                 }else{
-                    warn!("os_epoll.ctl_del_fd({})", cid);
+                    warn!("os_poll.del_socket({})", cid);
                 }
             }
             Err(err) => {
@@ -291,3 +673,167 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::error::Error;
+    use crate::frame_codec::{FrameCodec, WanHead};
+    use crate::tcp_listen_config::TcpListenConfig;
+    use crate::tcp_listen_service::TcpListenService;
+    use crate::tcp_socket_msg::{MsgData, SProtoId};
+    use std::cell::Cell;
+    use std::io::Read;
+    use std::net::{Shutdown, TcpStream};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_multi_listen() {
+        let mut config = TcpListenConfig::new();
+        config.set_bind_socket_addr("127.0.0.1:0").add_bind_socket_addr("127.0.0.1:0", true);
+        let mut net_msg_cb_fn = |_cid: u64, _vec_msg: Vec<MsgData>| vec![];
+        let mut exc_msg_cb_fn = |cid: u64, spid: SProtoId, err: Option<&Error>| {
+            panic!("cid:{} {:?} {:?}", cid, spid, err);
+        };
+        let mut service: TcpListenService<FrameCodec<WanHead>, MsgData> =
+            TcpListenService::new(&config, &mut net_msg_cb_fn, &mut exc_msg_cb_fn).unwrap();
+        assert_eq!(service.listen_num(), 2);
+
+        // 每个监听地址连一个 按客户端地址找到连接
+        let vec_client: Vec<TcpStream> = (0..2)
+            .map(|listen_id| TcpStream::connect(*service.get_listen_addr(listen_id).unwrap()).unwrap())
+            .collect();
+        while service.tcp_socket_count() < 2 {
+            service.epoll_event(10).unwrap();
+        }
+        for (listen_id, client) in vec_client.iter().enumerate() {
+            let client_addr = client.local_addr().unwrap();
+            let cid = service
+                .iter_stats()
+                .find(|(_, stats)| stats.peer_addr == Some(client_addr))
+                .map(|(cid, _)| cid)
+                .unwrap();
+            assert_eq!(service.get_listen_id(cid), Some(listen_id as u64));
+        }
+    }
+
+    #[test]
+    fn test_close_after_flush() {
+        let mut config = TcpListenConfig::new();
+        config.set_bind_socket_addr("127.0.0.1:0");
+        let mut net_msg_cb_fn = |_cid: u64, _vec_msg: Vec<MsgData>| vec![];
+        let mut exc_msg_cb_fn = |cid: u64, spid: SProtoId, err: Option<&Error>| {
+            panic!("cid:{} {:?} {:?}", cid, spid, err);
+        };
+        let mut service: TcpListenService<FrameCodec<WanHead>, MsgData> =
+            TcpListenService::new(&config, &mut net_msg_cb_fn, &mut exc_msg_cb_fn).unwrap();
+
+        let addr = *service.get_listen_addr(0).unwrap();
+        let client = thread::spawn(move || {
+            let mut socket = TcpStream::connect(addr).unwrap();
+            let mut buf = vec![];
+            socket.read_to_end(&mut buf).unwrap();
+            buf.len()
+        });
+        while service.tcp_socket_count() == 0 {
+            service.epoll_event(10).unwrap();
+        }
+        let cid = service.iter_stats().next().unwrap().0;
+        for pid in 1000..1003 {
+            let mut msg = MsgData::new_uid_pid(cid, pid);
+            msg.buf = vec![7u8; 100000].into();
+            assert!(service.write_msg(cid, msg));
+        }
+        assert!(service.close_after_flush(cid));
+        assert!(!service.write_msg(cid, MsgData::new_uid_pid(cid, 1003)));
+
+        // 客户端读完后关闭 服务端关闭连接
+        while service.tcp_socket_count() > 0 {
+            service.epoll_event(10).unwrap();
+        }
+        assert_eq!(client.join().unwrap(), 3 * (10 + 100000));
+        assert!(!service.close_after_flush(cid));
+    }
+
+    #[test]
+    fn test_close_after_peer_shutdown() {
+        let mut config = TcpListenConfig::new();
+        config
+            .set_bind_socket_addr("127.0.0.1:0")
+            .set_socket_write_buffer(4096)
+            .set_close_linger(60000);
+        let mut net_msg_cb_fn = |_cid: u64, _vec_msg: Vec<MsgData>| vec![];
+        let mut exc_msg_cb_fn = |cid: u64, spid: SProtoId, err: Option<&Error>| {
+            panic!("cid:{} {:?} {:?}", cid, spid, err);
+        };
+        let mut service: TcpListenService<FrameCodec<WanHead>, MsgData> =
+            TcpListenService::new(&config, &mut net_msg_cb_fn, &mut exc_msg_cb_fn).unwrap();
+
+        // 客户端收到数据后关闭写 等一会再读完
+        let addr = *service.get_listen_addr(0).unwrap();
+        let client = thread::spawn(move || {
+            let mut socket = TcpStream::connect(addr).unwrap();
+            let mut buf = vec![0u8; 1];
+            socket.read_exact(&mut buf).unwrap();
+            socket.shutdown(Shutdown::Write).unwrap();
+            thread::sleep(Duration::from_millis(100));
+            socket.read_to_end(&mut buf).unwrap();
+            buf.len()
+        });
+        while service.tcp_socket_count() == 0 {
+            service.epoll_event(10).unwrap();
+        }
+        let cid = service.iter_stats().next().unwrap().0;
+        for pid in 1000..1010 {
+            let mut msg = MsgData::new_uid_pid(cid, pid);
+            msg.buf = vec![7u8; 500000].into();
+            assert!(service.write_msg(cid, msg));
+        }
+        assert!(service.close_after_flush(cid));
+
+        // 对方已关闭 发完就关闭 不用等 close_linger
+        let start = Instant::now();
+        while service.tcp_socket_count() > 0 {
+            service.epoll_event(10).unwrap();
+            assert!(start.elapsed() < Duration::from_secs(5));
+        }
+        assert_eq!(client.join().unwrap(), 10 * (10 + 500000));
+    }
+
+    #[test]
+    fn test_close_on_queue_full() {
+        let mut config = TcpListenConfig::new();
+        config
+            .set_bind_socket_addr("127.0.0.1:0")
+            .set_socket_write_buffer(4096)
+            .set_msg_deque_size(4)
+            .set_close_on_queue_full(true);
+        let disconnect = Cell::new(None);
+        let mut net_msg_cb_fn = |_cid: u64, _vec_msg: Vec<MsgData>| vec![];
+        let mut exc_msg_cb_fn = |cid: u64, spid: SProtoId, err: Option<&Error>| {
+            assert_eq!(spid, SProtoId::Disconnect);
+            disconnect.set(Some((cid, err.cloned())));
+        };
+        let mut service: TcpListenService<FrameCodec<WanHead>, MsgData> =
+            TcpListenService::new(&config, &mut net_msg_cb_fn, &mut exc_msg_cb_fn).unwrap();
+
+        // 客户端不读数据
+        let _socket = TcpStream::connect(*service.get_listen_addr(0).unwrap()).unwrap();
+        while service.tcp_socket_count() == 0 {
+            service.epoll_event(10).unwrap();
+        }
+        let cid = service.iter_stats().next().unwrap().0;
+        let mut num = 0;
+        loop {
+            let mut msg = MsgData::new_uid_pid(cid, 1000);
+            msg.buf = vec![7u8; 100000].into();
+            if !service.write_msg(cid, msg) {
+                break;
+            }
+            num += 1;
+            assert!(num < 1000);
+        }
+        assert_eq!(service.tcp_socket_count(), 0);
+        assert_eq!(disconnect.take(), Some((cid, Some(Error::QueueFull))));
+    }
+}
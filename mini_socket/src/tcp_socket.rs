@@ -1,27 +1,178 @@
+use crate::error::Error;
+use crate::proxy_protocol;
+use crate::proxy_protocol::ProxyHeader;
+use crate::tcp_socket_rw::CompressStats;
 use crate::tcp_socket_rw::ReadResult;
 use crate::tcp_socket_rw::TcpSocketRw;
 use crate::tcp_socket_rw::WriteResult;
 use std::collections::VecDeque;
+use std::io;
+use std::io::ErrorKind;
 use std::mem;
+use std::net::SocketAddr;
 use std::net::TcpStream;
+use std::os::unix::io::AsRawFd;
+
+use mini_utils::time;
+
+/// 一次 writev 最多写入的消息数
+/// 每条消息占用2个iovec(包头,包体)
+pub(crate) const WRITEV_MAX_MSG: usize = 256;
+
+/// 连接的地址 时间及收发统计
+/// 时间是毫秒时间戳
+/// 字节数按 head_size() + body() 统计 消息完整读写后才计入
+#[derive(Clone, Debug, Default)]
+pub struct TcpSocketStats {
+    /// 客户端地址 启用 PROXY protocol 时是头里的真实地址
+    pub peer_addr: Option<SocketAddr>,
+    /// 启用 PROXY protocol 时 负载均衡的地址
+    pub proxy_addr: Option<SocketAddr>,
+    pub local_addr: Option<SocketAddr>,
+    pub accept_time: u64,
+    pub last_read_time: u64,
+    pub last_write_time: u64,
+    pub read_bytes: u64,
+    pub read_msgs: u64,
+    pub write_bytes: u64,
+    pub write_msgs: u64,
+    /// 发送队列最大长度
+    pub peak_queue_len: usize,
+    /// 压缩的统计
+    pub compress: CompressStats,
+}
 
 pub struct TcpSocket<MSG> {
     pub epevs: i32,
+    /// 从哪个监听地址accept的连接
+    listen_id: u64,
     pub socket: TcpStream,
     vec_deque: VecDeque<MSG>,
+    /// 等待发送的分片 每条大消息一组
+    /// 每次只有一个分片在 vec_deque 中 写完后再放入下一个
+    vec_chunk: VecDeque<VecDeque<MSG>>,
+    /// vec_deque 中分片的下标
+    chunk_idx: Option<usize>,
     pub tcp_socket_rw: Box<dyn TcpSocketRw<MSG>>,
+    /// 批量写: 队列前 head_num 条消息已编码好的包头
+    vec_head: Vec<u8>,
+    /// 批量写: 已编码包头的消息数
+    head_num: usize,
+    /// 批量写: 队列第一条消息(包头+包体)已写入的字节数
+    write_pos: usize,
+    stats: TcpSocketStats,
+    /// 等待 PROXY protocol 头
+    proxy_header_pending: bool,
 }
 
 impl<MSG> TcpSocket<MSG> {
-    pub fn new(socket: TcpStream, tcp_socket_rw: Box<dyn TcpSocketRw<MSG>>) -> Self {
+    /// tcp_socket_rw 的 hello_msg 放在发送队列最前面
+    pub fn new(socket: TcpStream, mut tcp_socket_rw: Box<dyn TcpSocketRw<MSG>>) -> Self {
+        let stats = TcpSocketStats {
+            peer_addr: socket.peer_addr().ok(),
+            local_addr: socket.local_addr().ok(),
+            accept_time: time::timestamp(),
+            ..Default::default()
+        };
+        let mut vec_deque = VecDeque::new();
+        if let Some(msg) = tcp_socket_rw.hello_msg() {
+            vec_deque.push_back(msg);
+        }
         TcpSocket {
+            stats,
             socket,
             epevs: 0,
+            listen_id: 0,
             tcp_socket_rw,
-            vec_deque: VecDeque::new(),
+            vec_deque,
+            vec_chunk: VecDeque::new(),
+            chunk_idx: None,
+            vec_head: vec![],
+            head_num: 0,
+            write_pos: 0,
+            proxy_header_pending: false,
         }
     }
 
+    /// 从哪个监听地址accept的连接
+    #[inline]
+    pub fn get_listen_id(&self) -> u64 {
+        self.listen_id
+    }
+
+    #[inline]
+    pub fn set_listen_id(&mut self, listen_id: u64) {
+        self.listen_id = listen_id;
+    }
+
+    /// 连接的地址 时间及收发统计
+    #[inline]
+    pub fn get_stats(&self) -> &TcpSocketStats {
+        &self.stats
+    }
+
+    /// 等待 PROXY protocol 头 读到头之前不把数据交给 TcpSocketRw
+    #[inline]
+    pub fn is_proxy_header_pending(&self) -> bool {
+        self.proxy_header_pending
+    }
+
+    #[inline]
+    pub fn set_proxy_header_pending(&mut self, val: bool) {
+        self.proxy_header_pending = val;
+    }
+
+    /// 读取 PROXY protocol 头 记录客户端真实地址
+    /// 先 MSG_PEEK 解析 完整后只从tcp buffer中取走头的字节
+    /// Ok(false): 数据不完整 等下次可读
+    pub fn read_proxy_header(&mut self, buffer: &mut Vec<u8>) -> Result<bool, Error> {
+        let fd = self.socket.as_raw_fd();
+        let peek_size = std::cmp::min(buffer.len(), proxy_protocol::PROXY_HEADER_MAX_SIZE);
+        let size = loop {
+            let ret = unsafe {
+                libc::recv(fd, buffer.as_mut_ptr() as *mut libc::c_void, peek_size, libc::MSG_PEEK)
+            };
+            if ret > 0 {
+                break ret as usize;
+            }
+            if ret == 0 {
+                return Err(Error::PeerClosed);
+            }
+            let err = io::Error::last_os_error();
+            match err.kind() {
+                ErrorKind::WouldBlock => return Ok(false),
+                ErrorKind::Interrupted => continue,
+                _ => return Err(err.into()),
+            }
+        };
+
+        let (head_size, addr) = match proxy_protocol::parse(&buffer[..size]) {
+            Ok(ProxyHeader::Incomplete) => {
+                if size == peek_size {
+                    return Err(Error::Protocol("proxy protocol header too long".into()));
+                }
+                return Ok(false);
+            }
+            Ok(ProxyHeader::Complete(head_size, addr)) => (head_size, addr),
+            Err(err) => return Err(Error::Protocol(err)),
+        };
+
+        // 取走头的字节 后面的数据留给 TcpSocketRw
+        let ret = unsafe { libc::recv(fd, buffer.as_mut_ptr() as *mut libc::c_void, head_size, 0) };
+        if ret < 0 {
+            return Err(Error::last_os_error().context("recv proxy protocol header"));
+        }
+        if ret != head_size as isize {
+            return Err(Error::Protocol(format!("recv proxy protocol header size:{} != {}", ret, head_size)));
+        }
+        if let Some(addr) = addr {
+            self.stats.proxy_addr = self.stats.peer_addr.take();
+            self.stats.peer_addr = Some(addr);
+        }
+        self.proxy_header_pending = false;
+        Ok(true)
+    }
+
     /// 获取当前消息列队长度
     #[inline]
     pub fn vec_queue_len(&self) -> usize {
@@ -29,25 +180,94 @@ impl<MSG> TcpSocket<MSG> {
     }
 
     /// 把数据存放到当前 socket 队列里
+    /// 大消息拆成分片 一条大消息在队列中只占一个位置
+    /// 返回错误: seal_frame 失败 消息已丢弃 连接要断开
     #[inline]
-    pub fn push_vec_queue(&mut self, msg: MSG) {
-        self.vec_deque.push_back(msg)
+    pub fn push_vec_queue(&mut self, msg: MSG) -> Result<(), Error> {
+        let msg = self.tcp_socket_rw.prepare_msg(msg);
+        self.stats.compress = self.tcp_socket_rw.get_compress_stats();
+        if self.tcp_socket_rw.need_chunk(&msg) {
+            let vec_chunk = self.tcp_socket_rw.chunk_msg(msg).into();
+            self.vec_chunk.push_back(vec_chunk);
+            if self.chunk_idx.is_none() {
+                self.next_chunk()?;
+            }
+        } else {
+            let msg = self.tcp_socket_rw.seal_frame(msg)?;
+            self.vec_deque.push_back(msg);
+        }
+        if self.stats.peak_queue_len < self.vec_deque.len() {
+            self.stats.peak_queue_len = self.vec_deque.len();
+        }
+        Ok(())
+    }
+
+    /// 把下一个分片放到队列尾部
+    fn next_chunk(&mut self) -> Result<(), Error> {
+        while let Some(vec_chunk) = self.vec_chunk.front_mut() {
+            if let Some(chunk) = vec_chunk.pop_front() {
+                let chunk = self.tcp_socket_rw.seal_frame(chunk)?;
+                self.vec_deque.push_back(chunk);
+                self.chunk_idx = Some(self.vec_deque.len() - 1);
+                return Ok(());
+            }
+            self.vec_chunk.pop_front();
+        }
+        self.chunk_idx = None;
+        Ok(())
+    }
+
+    /// 移除已写完的第一条消息
+    fn pop_front(&mut self) -> Result<(), Error> {
+        self.vec_deque.pop_front();
+        match self.chunk_idx {
+            Some(0) => return self.next_chunk(),
+            Some(idx) => self.chunk_idx = Some(idx - 1),
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// 消息编码后的字节数
+    #[inline]
+    fn msg_size(&self, msg: &MSG) -> u64 {
+        (self.tcp_socket_rw.head_size() + self.tcp_socket_rw.body(msg).len()) as u64
     }
 
     /// 获取 TcpSocket 队列里的所有数据
     /// 用于断连后把数据转移到新的链接中
-    #[inline]
+    /// 正在发送的大消息已有分片写入旧的连接 丢弃它剩下的分片
+    /// 启用加密时 队列中的消息已用旧连接的密钥加密 不能再发送
     pub fn get_vec_queue(&mut self) -> VecDeque<MSG> {
-        mem::replace(&mut self.vec_deque, VecDeque::new())
+        // 包头要在新的连接中重新编码
+        self.vec_head.clear();
+        self.head_num = 0;
+        self.write_pos = 0;
+        let mut vec_deque = mem::replace(&mut self.vec_deque, VecDeque::new());
+        if let Some(idx) = self.chunk_idx.take() {
+            vec_deque.remove(idx);
+            self.vec_chunk.pop_front();
+        }
+        for vec_chunk in self.vec_chunk.drain(..) {
+            vec_deque.extend(vec_chunk);
+        }
+        vec_deque
     }
 
     /// 把数据写到tcp buffer中
     pub fn write(&mut self) -> WriteResult {
-        let socket = &mut self.socket;
+        if self.tcp_socket_rw.head_size() > 0 {
+            return self.writev();
+        }
         while let Some(msg) = self.vec_deque.front_mut() {
-            match self.tcp_socket_rw.write(socket, msg) {
+            match self.tcp_socket_rw.write(&mut self.socket, msg) {
                 WriteResult::Finish => {
-                    self.vec_deque.pop_front();
+                    self.stats.write_bytes += self.msg_size(&self.vec_deque[0]);
+                    self.stats.write_msgs += 1;
+                    self.stats.last_write_time = time::timestamp();
+                    if let Err(err) = self.pop_front() {
+                        return WriteResult::Error(err);
+                    }
                 }
                 WriteResult::BufferFull => return WriteResult::BufferFull,
                 WriteResult::Error(err) => return WriteResult::Error(err),
@@ -56,11 +276,277 @@ impl<MSG> TcpSocket<MSG> {
         WriteResult::Finish
     }
 
+    /// 把队列里的多条消息用一次 writev 写到tcp buffer中
+    fn writev(&mut self) -> WriteResult {
+        let empty_iovec = libc::iovec {
+            iov_base: std::ptr::null_mut(),
+            iov_len: 0,
+        };
+        let mut iovecs = [empty_iovec; WRITEV_MAX_MSG * 2];
+
+        loop {
+            let (iov_num, total_size) = match self.fill_iovecs(&mut iovecs) {
+                Ok((0, _)) => return WriteResult::Finish,
+                Ok(num) => num,
+                Err(err) => return WriteResult::Error(err),
+            };
+
+            let ret = unsafe {
+                libc::writev(self.socket.as_raw_fd(), iovecs.as_ptr(), iov_num as libc::c_int)
+            };
+            if ret == -1 {
+                let err = io::Error::last_os_error();
+                match err.kind() {
+                    ErrorKind::WouldBlock => return WriteResult::BufferFull,
+                    ErrorKind::Interrupted => continue, //系统中断 writev
+                    _ => return WriteResult::Error(err.into()),
+                }
+            }
+            if ret == 0 && total_size > 0 {
+                return WriteResult::Error(Error::PeerClosed);
+            }
+            if let Err(err) = self.writev_done(ret as usize) {
+                return WriteResult::Error(err);
+            }
+            if (ret as usize) < total_size {
+                return WriteResult::BufferFull;
+            }
+        }
+    }
+
+    /// 批量写: 编码队列前面消息的包头 把包头 包体填入 iovecs
+    /// 跳过第一条消息已写入的字节 返回 (iovec 数, 字节数) 队列为空时 iovec 数为0
+    /// io_uring 的 writev 完成前 不能再调用 write fill_iovecs
+    pub(crate) fn fill_iovecs(&mut self, iovecs: &mut [libc::iovec]) -> Result<(usize, usize), Error> {
+        let head_size = self.tcp_socket_rw.head_size();
+        let msg_num = std::cmp::min(self.vec_deque.len(), std::cmp::min(WRITEV_MAX_MSG, iovecs.len() / 2));
+        if msg_num == 0 {
+            return Ok((0, 0));
+        }
+
+        // 编码还没有编码的包头
+        if self.head_num < msg_num {
+            self.vec_head.resize(msg_num * head_size, 0);
+            while self.head_num < msg_num {
+                let pos = self.head_num * head_size;
+                let head = &mut self.vec_head[pos..pos + head_size];
+                let msg = &self.vec_deque[self.head_num];
+                self.tcp_socket_rw.encode_head(msg, head)?;
+                self.head_num += 1;
+            }
+        }
+
+        // 填充 iovec 跳过第一条消息已写入的字节
+        let mut skip = self.write_pos;
+        let mut iov_num = 0;
+        let mut total_size = 0;
+        for i in 0..msg_num {
+            let head = &self.vec_head[i * head_size..(i + 1) * head_size];
+            let body = self.tcp_socket_rw.body(&self.vec_deque[i]);
+            for data in [head, body].iter() {
+                if skip >= data.len() {
+                    skip -= data.len();
+                    continue;
+                }
+                let data = &data[skip..];
+                skip = 0;
+                iovecs[iov_num] = libc::iovec {
+                    iov_base: data.as_ptr() as *mut libc::c_void,
+                    iov_len: data.len(),
+                };
+                iov_num += 1;
+                total_size += data.len();
+            }
+        }
+        Ok((iov_num, total_size))
+    }
+
+    /// 批量写: 写入了 size 字节 移除已完整写入的消息
+    pub(crate) fn writev_done(&mut self, size: usize) -> Result<(), Error> {
+        if size > 0 {
+            self.stats.last_write_time = time::timestamp();
+        }
+        let head_size = self.tcp_socket_rw.head_size();
+        let mut wsize = self.write_pos + size;
+        let mut finish_num = 0;
+        while finish_num < self.head_num {
+            let msg_size = self.msg_size(&self.vec_deque[0]);
+            if (wsize as u64) < msg_size {
+                break;
+            }
+            wsize -= msg_size as usize;
+            self.stats.write_bytes += msg_size;
+            finish_num += 1;
+            self.pop_front()?;
+        }
+        self.write_pos = wsize;
+        self.stats.write_msgs += finish_num as u64;
+        self.head_num -= finish_num;
+        self.vec_head.drain(0..finish_num * head_size);
+        Ok(())
+    }
+
+    /// TcpSocketRw 支持批量写和 read_data 时 io_uring 直接提交 recv writev
+    #[inline]
+    pub fn support_uring_io(&self) -> bool {
+        self.tcp_socket_rw.head_size() > 0 && self.tcp_socket_rw.support_read_data()
+    }
+
+    /// 解析 io_uring 收到的数据
+    pub fn read_data(&mut self, data: &[u8]) -> ReadResult<MSG> {
+        let result = self.tcp_socket_rw.read_data(data);
+        self.read_stats(&result);
+        result
+    }
+
+    /// 读到消息后更新统计
+    fn read_stats(&mut self, result: &ReadResult<MSG>) {
+        let vec_msg = match result {
+            ReadResult::Data(vec_msg) => vec_msg,
+            ReadResult::Error(vec_msg, _) => vec_msg,
+        };
+        if !vec_msg.is_empty() {
+            for msg in vec_msg.iter() {
+                self.stats.read_bytes += self.msg_size(msg);
+            }
+            self.stats.read_msgs += vec_msg.len() as u64;
+            self.stats.last_read_time = time::timestamp();
+            self.stats.compress = self.tcp_socket_rw.get_compress_stats();
+        }
+    }
+
     /// 从tcp buffer中读取数据
     /// share_buffer: 共享缓冲区
     #[inline]
     pub fn read(&mut self, share_buffer: &mut Vec<u8>) -> ReadResult<MSG> {
         let socket = &mut self.socket;
-        self.tcp_socket_rw.read(socket, share_buffer)
+        let result = self.tcp_socket_rw.read(socket, share_buffer);
+        self.read_stats(&result);
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::error::Error;
+    use crate::os_socket;
+    use crate::tcp_socket::TcpSocket;
+    use crate::tcp_socket_rw::{ReadResult, TcpSocketRw, WriteResult};
+    use std::io::Read;
+    use std::net::{TcpListener, TcpStream};
+    use std::os::unix::io::AsRawFd;
+    use std::thread;
+
+    /// 包头: 包体字节数(u16) + 包序号(u16)
+    struct TestRw {
+        id: u16,
+        /// 0:不拆分片
+        chunk_size: usize,
+    }
+
+    impl TcpSocketRw<Vec<u8>> for TestRw {
+        fn write(&mut self, _socket: &mut TcpStream, _msg: &mut Vec<u8>) -> WriteResult {
+            WriteResult::Error(Error::Config("batch only".into()))
+        }
+        fn read(&mut self, _socket: &mut TcpStream, _buf: &mut Vec<u8>) -> ReadResult<Vec<u8>> {
+            ReadResult::Data(vec![])
+        }
+        fn head_size(&self) -> usize {
+            4
+        }
+        fn encode_head(&mut self, msg: &Vec<u8>, head: &mut [u8]) -> Result<(), Error> {
+            head[0..2].copy_from_slice(&(msg.len() as u16).to_le_bytes());
+            head[2..4].copy_from_slice(&self.id.to_le_bytes());
+            self.id = self.id.wrapping_add(1);
+            Ok(())
+        }
+        fn body<'b>(&self, msg: &'b Vec<u8>) -> &'b [u8] {
+            msg
+        }
+        fn need_chunk(&self, msg: &Vec<u8>) -> bool {
+            self.chunk_size > 0 && msg.len() > self.chunk_size
+        }
+        fn chunk_msg(&mut self, msg: Vec<u8>) -> Vec<Vec<u8>> {
+            msg.chunks(self.chunk_size).map(|chunk| chunk.to_vec()).collect()
+        }
+    }
+
+    #[test]
+    fn test_chunk_interleave() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let socket = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut peer, _) = listener.accept().unwrap();
+        let mut tcp_socket = TcpSocket::new(socket, Box::new(TestRw { id: 0, chunk_size: 100 }));
+        tcp_socket.push_vec_queue(vec![1u8; 250]).unwrap();
+        tcp_socket.push_vec_queue(vec![2u8; 3]).unwrap();
+        tcp_socket.push_vec_queue(vec![3u8; 150]).unwrap();
+        // 大消息在队列中只占一个位置
+        assert_eq!(tcp_socket.vec_queue_len(), 2);
+        assert!(tcp_socket.write() == WriteResult::Finish);
+        drop(tcp_socket);
+
+        let mut data = vec![];
+        peer.read_to_end(&mut data).unwrap();
+        let mut vec_body = vec![];
+        let mut pos = 0;
+        while pos < data.len() {
+            let size = u16::from_le_bytes([data[pos], data[pos + 1]]) as usize;
+            vec_body.push((data[pos + 4], size));
+            pos += 4 + size;
+        }
+        // 小消息插在分片之间 下一条大消息等上一条的分片写完
+        let expect = vec![(1, 100), (2, 3), (1, 100), (1, 50), (3, 100), (3, 50)];
+        assert_eq!(vec_body, expect);
+    }
+
+    #[test]
+    fn test_writev_partial_write() {
+        let msg_num: usize = 20000;
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let reader = thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            let mut data = vec![];
+            socket.read_to_end(&mut data).unwrap();
+            data
+        });
+
+        let socket = TcpStream::connect(addr).unwrap();
+        socket.set_nonblocking(true).unwrap();
+        // 发送缓冲区很小 一定会写不完
+        os_socket::setsockopt(socket.as_raw_fd(), libc::SOL_SOCKET, libc::SO_SNDBUF, 4096).unwrap();
+        let mut tcp_socket = TcpSocket::new(socket, Box::new(TestRw { id: 0, chunk_size: 0 }));
+        let mut expect = vec![];
+        for i in 0..msg_num {
+            let msg = vec![(i % 251) as u8; i % 300];
+            expect.extend_from_slice(&(msg.len() as u16).to_le_bytes());
+            expect.extend_from_slice(&(i as u16).to_le_bytes());
+            expect.extend_from_slice(&msg);
+            tcp_socket.push_vec_queue(msg).unwrap();
+        }
+
+        let mut buffer_full_num = 0;
+        loop {
+            match tcp_socket.write() {
+                WriteResult::Finish => break,
+                WriteResult::BufferFull => {
+                    buffer_full_num += 1;
+                    thread::yield_now();
+                }
+                WriteResult::Error(err) => panic!("write error:{}", err),
+            }
+        }
+        assert_eq!(tcp_socket.vec_queue_len(), 0);
+        let stats = tcp_socket.get_stats();
+        assert_eq!(stats.write_bytes, expect.len() as u64);
+        assert_eq!(stats.write_msgs, msg_num as u64);
+        assert_eq!(stats.peak_queue_len, msg_num);
+        assert_eq!(stats.peer_addr, Some(addr));
+        drop(tcp_socket);
+
+        let data = reader.join().unwrap();
+        assert_eq!(data.len(), expect.len());
+        assert!(data == expect);
+        assert!(buffer_full_num > 0);
     }
 }
@@ -1,58 +1,38 @@
+use crate::error::Error;
+use crate::slab::Slab;
 use crate::tcp_socket::TcpSocket;
+use crate::tcp_socket::TcpSocketStats;
+use crate::tcp_socket_rw::RwConfig;
 use crate::tcp_socket_rw::TcpSocketRw;
-use std::collections::HashMap;
 use std::net::TcpStream;
 
+/// cid = generation << 32 | 下标
+/// 连接断开后旧的 cid 不会找到新的连接
+/// cid 不会小于 1 << 32 不会和监听ID冲突
 pub struct TcpSocketMgmt<MSG> {
-    //不会等于零
-    next_cid: u64,
-    /// 监听ID
-    listen_id: u64,
+    /// 最大连接数
+    max_socket: usize,
     /// 待发的消息队列最大长度
     msg_deque_size: usize,
-    /// 可以优化使用别的数据结构
-    tcp_socket_hash_map: HashMap<u64, TcpSocket<MSG>>,
+    /// 传给每个连接 TcpSocketRw 的配置
+    rw_config: RwConfig,
+    tcp_socket_slab: Slab<TcpSocket<MSG>>,
 }
 
 impl<MSG> TcpSocketMgmt<MSG> {
-    pub fn new(listen_id: u64, max_socket: u32, msg_deque_size: usize) -> Self {
-        let tcp_socket_hash_map: HashMap<u64, TcpSocket<MSG>>;
-        if max_socket < 8 {
-            tcp_socket_hash_map = HashMap::with_capacity(8);
-        } else {
-            tcp_socket_hash_map = HashMap::with_capacity(max_socket as usize);
-        }
-
+    pub fn new(max_socket: u32, msg_deque_size: usize) -> Self {
+        let max_socket = std::cmp::max(max_socket as usize, 8);
         TcpSocketMgmt {
-            listen_id,
-            next_cid: 0,
+            max_socket,
             msg_deque_size,
-            tcp_socket_hash_map,
-        }
-    }
-
-    fn next_cid(&self) -> u64 {
-        let mut cid = self.next_cid;
-        loop {
-            cid += 1;
-            if cid == 0 || cid == u64::MAX {
-                cid = 1;
-            }
-
-            if cid == self.listen_id {
-                cid += 1;
-            }
-
-            if self.tcp_socket_hash_map.contains_key(&cid) {
-                continue;
-            }
-            return cid;
+            rw_config: RwConfig::default(),
+            tcp_socket_slab: Slab::with_capacity(max_socket),
         }
     }
 
     #[inline]
     pub fn tcp_socket_count(&self) -> u32 {
-        self.tcp_socket_hash_map.len() as u32
+        self.tcp_socket_slab.len() as u32
     }
 
     #[inline]
@@ -60,32 +40,64 @@ impl<MSG> TcpSocketMgmt<MSG> {
         self.msg_deque_size
     }
 
+    #[inline]
+    pub fn set_rw_config(&mut self, rw_config: RwConfig) {
+        self.rw_config = rw_config;
+    }
+
     #[inline]
     pub fn get_tcp_socket(&mut self, cid: u64) -> Option<&mut TcpSocket<MSG>> {
-        self.tcp_socket_hash_map.get_mut(&cid)
+        self.tcp_socket_slab.get_mut(cid)
+    }
+
+    /// 连接是从哪个监听地址accept的
+    #[inline]
+    pub fn get_listen_id(&self, cid: u64) -> Option<u64> {
+        self.tcp_socket_slab
+            .get(cid)
+            .map(|tcp_socket| tcp_socket.get_listen_id())
+    }
+
+    /// 连接的地址 时间及收发统计
+    #[inline]
+    pub fn get_stats(&self, cid: u64) -> Option<&TcpSocketStats> {
+        self.tcp_socket_slab
+            .get(cid)
+            .map(|tcp_socket| tcp_socket.get_stats())
+    }
+
+    /// 遍历所有连接的统计 (cid, stats)
+    #[inline]
+    pub fn iter_stats(&self) -> impl Iterator<Item = (u64, &TcpSocketStats)> {
+        self.tcp_socket_slab
+            .iter()
+            .map(|(cid, tcp_socket)| (cid, tcp_socket.get_stats()))
     }
 
     #[inline]
-    pub fn del_tcp_socket(&mut self, cid: u64) -> Result<TcpSocket<MSG>, String> {
-        if let Some(tcp_socket) = self.tcp_socket_hash_map.remove(&cid) {
+    pub fn del_tcp_socket(&mut self, cid: u64) -> Result<TcpSocket<MSG>, Error> {
+        if let Some(tcp_socket) = self.tcp_socket_slab.remove(cid) {
             Ok(tcp_socket)
         } else {
-            Err(format!("cid:{} not exists", cid))
+            Err(Error::NotConnected)
         }
     }
 
-    pub fn add_tcp_socket<TBRW>(&mut self, socket: TcpStream) -> Result<u64, String>
+    pub fn add_tcp_socket<TBRW>(
+        &mut self,
+        listen_id: u64,
+        socket: TcpStream,
+    ) -> Result<u64, Error>
     where
         TBRW: TcpSocketRw<MSG> + Default + 'static,
     {
-        if self.tcp_socket_hash_map.len() == self.tcp_socket_hash_map.capacity() {
-            return Err("Max Socket Connect Number".into());
+        if self.tcp_socket_slab.len() >= self.max_socket {
+            return Err(Error::Config(format!("max tcp socket:{}", self.max_socket)));
         }
-        self.next_cid = self.next_cid();
-        self.tcp_socket_hash_map.insert(
-            self.next_cid,
-            TcpSocket::new(socket, Box::new(TBRW::default())),
-        );
-        Ok(self.next_cid)
+        let mut tcp_socket_rw = TBRW::default();
+        tcp_socket_rw.set_config(&self.rw_config);
+        let mut tcp_socket = TcpSocket::new(socket, Box::new(tcp_socket_rw));
+        tcp_socket.set_listen_id(listen_id);
+        Ok(self.tcp_socket_slab.insert(tcp_socket))
     }
 }
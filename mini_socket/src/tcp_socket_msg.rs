@@ -1,3 +1,7 @@
+use crate::error::Error;
+use std::ops::Deref;
+use std::sync::Arc;
+
 /*
 pub enum NetMsg{
     /// 正常消息
@@ -24,7 +28,29 @@ impl SrvMsg {
 
 }
 
-/// ext用于：第1位加密，第2位压缩,3~12协议版本，13~32位事务id
+/// MsgData.ext 第1位 包体已加密
+pub const EXT_ENCRYPT: u32 = 1;
+
+/// MsgData.ext 第2位 包体已压缩
+pub const EXT_COMPRESS: u32 = 1 << 1;
+
+/// MsgData.ext 第3~12位 协议版本
+pub const EXT_VERSION_SHIFT: u32 = 2;
+pub const EXT_VERSION_MASK: u32 = 0x3FF << EXT_VERSION_SHIFT;
+/// 协议版本最大值
+pub const MAX_VERSION: u16 = 0x3FF;
+
+/// MsgData.ext 第13~30位 事务id 0表示不是rpc消息
+pub const EXT_TID_SHIFT: u32 = 12;
+pub const EXT_TID_MASK: u32 = 0x3FFFF << EXT_TID_SHIFT;
+/// 事务id最大值
+pub const MAX_TID: u32 = 0x3FFFF;
+/// MsgData.ext 第31位 rpc错误回复 pid 和请求相同 见 rpc::reply_error
+pub const EXT_RPC_ERROR: u32 = 1 << 30;
+/// MsgData.ext 第32位 rpc回复 见 rpc
+pub const EXT_REPLY: u32 = 1 << 31;
+
+/// ext用于：第1位加密，第2位压缩,3~12协议版本，13~30位事务id,第31位rpc错误,第32位rpc回复
 #[derive(Clone)]
 pub struct MsgData {
     /// 用户协议id
@@ -35,7 +61,71 @@ pub struct MsgData {
     /// 用户Id不能为0
     pub uid: u64,
     /// 协议对应数据
-    pub buf: Vec<u8>,
+    pub buf: MsgBuf,
+}
+
+/// 消息的包体数据
+/// 广播时用 Shared 多个连接共用同一份数据 clone 只增加引用计数
+#[derive(Clone, Debug)]
+pub enum MsgBuf {
+    /// 独占的数据
+    Owned(Vec<u8>),
+    /// 引用计数共享的只读数据
+    Shared(Arc<Vec<u8>>),
+}
+
+impl MsgBuf {
+    #[inline]
+    pub fn shared(buf: Vec<u8>) -> Self {
+        MsgBuf::Shared(Arc::new(buf))
+    }
+
+    /// 转成共享数据 已经是 Shared 的不变
+    #[inline]
+    pub fn into_shared(self) -> Self {
+        match self {
+            MsgBuf::Owned(buf) => MsgBuf::Shared(Arc::new(buf)),
+            shared => shared,
+        }
+    }
+
+    #[inline]
+    pub fn is_shared(&self) -> bool {
+        matches!(self, MsgBuf::Shared(_))
+    }
+
+    /// 取出数据 Shared 只有一个引用时不复制
+    pub fn into_vec(self) -> Vec<u8> {
+        match self {
+            MsgBuf::Owned(buf) => buf,
+            MsgBuf::Shared(buf) => Arc::try_unwrap(buf).unwrap_or_else(|buf| (*buf).clone()),
+        }
+    }
+}
+
+impl Default for MsgBuf {
+    #[inline]
+    fn default() -> Self {
+        MsgBuf::Owned(vec![])
+    }
+}
+
+impl Deref for MsgBuf {
+    type Target = [u8];
+    #[inline]
+    fn deref(&self) -> &[u8] {
+        match self {
+            MsgBuf::Owned(buf) => buf,
+            MsgBuf::Shared(buf) => buf,
+        }
+    }
+}
+
+impl From<Vec<u8>> for MsgBuf {
+    #[inline]
+    fn from(buf: Vec<u8>) -> Self {
+        MsgBuf::Owned(buf)
+    }
 }
 
 
@@ -45,16 +135,227 @@ impl MsgData {
         self.uid
     }
 
+    /// 协议版本 ext 第3~12位
+    #[inline]
+    pub fn get_version(&self)->u16{
+        ((self.ext & EXT_VERSION_MASK) >> EXT_VERSION_SHIFT) as u16
+    }
+
+    /// 设置协议版本 超过 MAX_VERSION 的位被丢弃
+    #[inline]
+    pub fn set_version(&mut self, version: u16){
+        let version = ((version as u32) << EXT_VERSION_SHIFT) & EXT_VERSION_MASK;
+        self.ext = (self.ext & !EXT_VERSION_MASK) | version;
+    }
+
+    /// 断线重连会话中发给客户端的消息序号 只保存低10位
+    /// 放在 ext 第3~12位 发给客户端的消息不用协议版本
+    #[inline]
+    pub fn set_session_seq(&mut self, seq: u64){
+        self.set_version((seq & MAX_VERSION as u64) as u16);
+    }
+
+    /// 客户端用序号的低10位算出完整的序号 见 SProtoId::SessionToken
+    /// recv_seq: 上一条收到的消息的序号 连续丢失超过 MAX_VERSION 条时算不准
+    #[inline]
+    pub fn get_session_seq(&self, recv_seq: u64)->u64{
+        let low = self.get_version() as u64;
+        recv_seq + (low.wrapping_sub(recv_seq) & MAX_VERSION as u64)
+    }
+
+    /// 事务id ext 第13~30位 0表示不是rpc消息
+    #[inline]
+    pub fn get_tid(&self)->u32{
+        (self.ext & EXT_TID_MASK) >> EXT_TID_SHIFT
+    }
+
+    /// 设置事务id 超过 MAX_TID 的位被丢弃
+    #[inline]
+    pub fn set_tid(&mut self, tid: u32){
+        let tid = (tid << EXT_TID_SHIFT) & EXT_TID_MASK;
+        self.ext = (self.ext & !EXT_TID_MASK) | tid;
+    }
+
+    /// 是否是rpc回复 ext 第32位
+    #[inline]
+    pub fn is_reply(&self)->bool{
+        self.ext & EXT_REPLY != 0
+    }
+
+    /// 是否是rpc错误回复 ext 第31位
+    #[inline]
+    pub fn is_rpc_error(&self)->bool{
+        self.ext & EXT_RPC_ERROR != 0
+    }
+
+    /// 把包体转成共享数据 之后 clone 不再复制包体
+    #[inline]
+    pub fn into_shared(mut self)->Self{
+        self.buf = self.buf.into_shared();
+        self
+    }
+
     #[inline]
     /// pid(协议id)
     pub fn new_pid(pid: u16)->Self{
-        MsgData{uid:0, pid, ext:0, buf: vec![]}   
+        MsgData{uid:0, pid, ext:0, buf: MsgBuf::default()}   
     }
 
     #[inline]
     /// uid(链接Id用户id),pid(协议id)
     pub fn new_uid_pid(uid: u64, pid: u16)->Self{
-        MsgData{uid, pid, ext:0, buf: vec![]}   
+        MsgData{uid, pid, ext:0, buf: MsgBuf::default()}   
+    }
+
+    /// 连接断开的消息
+    /// uid(链接Id用户id), reason(断开原因)
+    pub fn new_disconnect(uid: u64, reason: DisReason)->Self{
+        let buf = (reason as u16).to_le_bytes().to_vec();
+        MsgData{uid, pid: SProtoId::Disconnect as u16, ext:0, buf: buf.into()}
+    }
+
+    /// Disconnect 消息的断开原因
+    /// 没有带原因的旧消息返回 DisReason::Unknown
+    pub fn get_dis_reason(&self)->DisReason{
+        if self.buf.len() < 2 {
+            return DisReason::Unknown;
+        }
+        DisReason::new(u16::from_le_bytes([self.buf[0], self.buf[1]]))
+    }
+
+    /// 客户端协议版本不支持 之后断开连接
+    /// uid(链接Id), min max(支持的版本范围)
+    pub fn new_version_not_support(uid: u64, min: u16, max: u16)->Self{
+        let mut buf = Vec::with_capacity(4);
+        buf.extend_from_slice(&min.to_le_bytes());
+        buf.extend_from_slice(&max.to_le_bytes());
+        MsgData{uid, pid: SProtoId::VersionNotSupport as u16, ext:0, buf: buf.into()}
+    }
+
+    /// 服务发给多个用户的消息
+    /// pid(用户协议id), vec_uid(用户Id列表), payload(协议对应数据)
+    pub fn new_multicast(pid: u16, vec_uid: &[u64], payload: &[u8])->Self{
+        let buf = MulticastData::encode(pid, vec_uid, payload);
+        MsgData{uid:0, pid: SProtoId::Multicast as u16, ext:0, buf: buf.into()}
+    }
+
+    /// 服务发给所有已认证用户的消息
+    /// pid(用户协议id), payload(协议对应数据)
+    pub fn new_broadcast(pid: u16, payload: &[u8])->Self{
+        let mut buf = Vec::with_capacity(2 + payload.len());
+        buf.extend_from_slice(&pid.to_le_bytes());
+        buf.extend_from_slice(payload);
+        MsgData{uid:0, pid: SProtoId::Broadcast as u16, ext:0, buf: buf.into()}
+    }
+
+    /// 用户加入组
+    /// uid(用户Id), name(组名)
+    pub fn new_join_group(uid: u64, name: &str)->Self{
+        let buf = name.as_bytes().to_vec();
+        MsgData{uid, pid: SProtoId::JoinGroup as u16, ext:0, buf: buf.into()}
+    }
+
+    /// 用户离开组
+    /// uid(用户Id), name(组名)
+    pub fn new_leave_group(uid: u64, name: &str)->Self{
+        let buf = name.as_bytes().to_vec();
+        MsgData{uid, pid: SProtoId::LeaveGroup as u16, ext:0, buf: buf.into()}
+    }
+
+    /// 服务发给组内所有用户的消息
+    /// pid(用户协议id), name(组名), payload(协议对应数据)
+    pub fn new_publish_group(pid: u16, name: &str, payload: &[u8])->Self{
+        let buf = PublishGroupData::encode(pid, name, payload);
+        MsgData{uid:0, pid: SProtoId::PublishGroup as u16, ext:0, buf: buf.into()}
+    }
+}
+
+/// SProtoId::PublishGroup 的包体
+/// |pid:u16|name_len:u16|name|payload|
+pub struct PublishGroupData<'a> {
+    /// 用户协议id
+    pub pid: u16,
+    /// 组名
+    pub name: &'a str,
+    /// 协议对应数据
+    pub payload: &'a [u8],
+}
+
+impl<'a> PublishGroupData<'a> {
+    pub fn encode(pid: u16, name: &str, payload: &[u8]) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(4 + name.len() + payload.len());
+        buf.extend_from_slice(&pid.to_le_bytes());
+        buf.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        buf.extend_from_slice(name.as_bytes());
+        buf.extend_from_slice(payload);
+        buf
+    }
+
+    pub fn decode(buf: &'a [u8]) -> Result<Self, String> {
+        if buf.len() < 4 {
+            return Err(format!("publish group data size:{} too small", buf.len()));
+        }
+        let pid = u16::from_le_bytes([buf[0], buf[1]]);
+        let name_end = 4 + u16::from_le_bytes([buf[2], buf[3]]) as usize;
+        if buf.len() < name_end {
+            return Err(format!("publish group name_end:{} data size:{}", name_end, buf.len()));
+        }
+        let name = match std::str::from_utf8(&buf[4..name_end]) {
+            Ok(name) => name,
+            Err(err) => return Err(format!("publish group name error:{}", err)),
+        };
+        Ok(PublishGroupData {
+            pid,
+            name,
+            payload: &buf[name_end..],
+        })
+    }
+}
+
+/// SProtoId::Multicast, SProtoId::MulticastFail 的包体
+/// |pid:u16|id_num:u32|id:u64*id_num|payload|
+pub struct MulticastData<'a> {
+    /// 用户协议id
+    pub pid: u16,
+    /// 用户Id 或 连接id
+    pub vec_id: Vec<u64>,
+    /// 协议对应数据
+    pub payload: &'a [u8],
+}
+
+impl<'a> MulticastData<'a> {
+    pub fn encode(pid: u16, vec_id: &[u64], payload: &[u8]) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(6 + vec_id.len() * 8 + payload.len());
+        buf.extend_from_slice(&pid.to_le_bytes());
+        buf.extend_from_slice(&(vec_id.len() as u32).to_le_bytes());
+        for id in vec_id {
+            buf.extend_from_slice(&id.to_le_bytes());
+        }
+        buf.extend_from_slice(payload);
+        buf
+    }
+
+    pub fn decode(buf: &'a [u8]) -> Result<Self, String> {
+        if buf.len() < 6 {
+            return Err(format!("multicast data size:{} too small", buf.len()));
+        }
+        let pid = u16::from_le_bytes([buf[0], buf[1]]);
+        let id_num = u32::from_le_bytes([buf[2], buf[3], buf[4], buf[5]]) as usize;
+        let payload_pos = 6 + id_num * 8;
+        if buf.len() < payload_pos {
+            return Err(format!("multicast id_num:{} data size:{}", id_num, buf.len()));
+        }
+        let mut vec_id = Vec::with_capacity(id_num);
+        for chunk in buf[6..payload_pos].chunks_exact(8) {
+            let mut id = [0u8; 8];
+            id.copy_from_slice(chunk);
+            vec_id.push(u64::from_le_bytes(id));
+        }
+        Ok(MulticastData {
+            pid,
+            vec_id,
+            payload: &buf[payload_pos..],
+        })
     }
 }
 
@@ -62,6 +363,7 @@ impl MsgData {
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum SProtoId {
     /// 有服务加入
+    /// MsgData.buf(|pid:u16|pid:u16|...|) 服务处理的协议id
     ServerJoin = 0,
 
     /// 有服务退出
@@ -82,6 +384,7 @@ pub enum SProtoId {
     AuthNotPass = 4,
     
     /// 断开网络或网络已断开
+    /// MsgData.buf(|reason:u16|) 断开原因 见 DisReason
     Disconnect = 5,
 
     /// 用户数据异常
@@ -99,6 +402,83 @@ pub enum SProtoId {
     /// 消息队列已满
     /// 线程或服务繁忙
     MsgQueueFull = 9,
+
+    /// 服务发给多个用户
+    /// MsgData.buf(MulticastData 用户Id列表)
+    /// proxy 转给网络线程时 MsgData.uid(服务id) MulticastData(连接id列表)
+    Multicast = 10,
+
+    /// 服务发给所有已认证的用户
+    /// MsgData.buf(|pid:u16|payload|)
+    Broadcast = 11,
+
+    /// 多播或广播时发送失败的用户
+    /// MsgData.buf(MulticastData 用户Id列表 没有payload)
+    /// 网络线程发给 proxy 时 MsgData.uid(服务id) MulticastData(连接id列表)
+    MulticastFail = 12,
+
+    /// 用户加入组 组不存在时创建
+    /// MsgData.uid(用户Id) MsgData.buf(组名)
+    JoinGroup = 13,
+
+    /// 用户离开组 组内没有用户时删除
+    /// MsgData.uid(用户Id) MsgData.buf(组名)
+    LeaveGroup = 14,
+
+    /// 服务发给组内所有用户
+    /// MsgData.buf(PublishGroupData)
+    /// 发送失败的用户用 MulticastFail 返回
+    PublishGroup = 15,
+
+    /// 查询组信息
+    /// 请求 MsgData.buf(组名)
+    /// 返回 MsgData.buf(|group_count:u32|group_size:u32|组名|)
+    GroupInfo = 16,
+
+    /// 连接待发送消息数达到高水位
+    /// 可以暂停给这个连接推送不重要的消息
+    QueueHighMark = 17,
+
+    /// 连接待发送消息数降到低水位
+    QueueLowMark = 18,
+
+    /// 主动连接 开始连接
+    Connecting = 19,
+
+    /// 主动连接 连接成功
+    /// 连接断开时通知 Disconnect
+    Connected = 20,
+
+    /// 超过单帧上限的大消息的分片
+    /// 只在 TcpSocketRw 内部使用 接收方重组后交给上层
+    /// MsgData.buf(|pid:u16|total_size:u32|offset:u32|data|) 见 msg_chunk
+    MsgChunk = 21,
+
+    /// 加密连接的第一条消息 客户端的临时公钥
+    /// 只在 TcpSocketRw 内部使用 见 msg_crypto
+    /// MsgData.buf(|client_key:32|)
+    KeyExchange = 22,
+
+    /// AuthRequest 的协议版本(MsgData.ext 第3~12位)不支持
+    /// proxy 发给客户端后断开连接
+    /// MsgData.buf(|min:u16|max:u16|) 支持的版本范围
+    VersionNotSupport = 23,
+
+    /// 断线重连的令牌 协议版本支持断线重连的客户端认证通过后 proxy 发给客户端 每次重连成功后更换
+    /// 之后发给客户端的用户协议消息(pid 不是 SProtoId) 按顺序从1编号 ext 第3~12位是序号的低10位
+    /// 客户端用 MsgData::get_session_seq 记录收到的序号 序号跳过说明中间的消息丢失了
+    /// MsgData.buf(|token:16|)
+    SessionToken = 25,
+
+    /// 客户端重连后代替 AuthRequest 发送 协议版本和 AuthRequest 一样放在 ext
+    /// 成功时收到新的 SessionToken 和没有收到的消息 服务不会收到断线和登录
+    /// 失败时收到 AuthNotPass 要重新 AuthRequest
+    /// MsgData.buf(|token:16|recv_seq:u64|)
+    SessionResume = 26,
+
+    /// 客户端确认收到的消息序号 proxy 释放已确认的消息
+    /// MsgData.buf(|recv_seq:u64|)
+    SessionAck = 27,
         
     EnumMaxValue = 255,
 }
@@ -123,7 +503,168 @@ impl SProtoId {
             7=> Self::ServerBusy,
             8=> Self::ServerRunExc,
             9=> Self::MsgQueueFull,
+            10=> Self::Multicast,
+            11=> Self::Broadcast,
+            12=> Self::MulticastFail,
+            13=> Self::JoinGroup,
+            14=> Self::LeaveGroup,
+            15=> Self::PublishGroup,
+            16=> Self::GroupInfo,
+            17=> Self::QueueHighMark,
+            18=> Self::QueueLowMark,
+            19=> Self::Connecting,
+            20=> Self::Connected,
+            21=> Self::MsgChunk,
+            22=> Self::KeyExchange,
+            23=> Self::VersionNotSupport,
+            25=> Self::SessionToken,
+            26=> Self::SessionResume,
+            27=> Self::SessionAck,
             _=> Self::EnumMaxValue,
         }
     }
-}
\ No newline at end of file
+}
+
+/// SProtoId::Disconnect 的断开原因
+/// 放在 MsgData.buf 的前2个字节 小端
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub enum DisReason {
+    /// 客户端主动关闭连接
+    PeerClosed = 0,
+    /// 网络错误 如连接被重置
+    IoError = 1,
+    /// 客户端数据不符合协议
+    Protocol = 2,
+    /// 客户端消息超过上限
+    MsgTooLarge = 3,
+    /// 待发送消息队列已满
+    QueueFull = 4,
+    /// 待发送消息数在高水位以上超时 客户端网络太慢
+    HighMarkTimeout = 5,
+    /// 发送时连接已不存在
+    NotConnected = 6,
+    /// 服务要求断开 ExcUserData
+    Kick = 7,
+    /// proxy 退出
+    ProxyShutdown = 8,
+    /// 密钥交换或解密失败
+    Crypto = 9,
+    /// 包的 CRC32C 校验失败
+    Checksum = 10,
+    /// 没有带原因或未知的原因
+    Unknown = 255,
+}
+
+impl DisReason {
+    #[inline]
+    pub fn new(v: u16)-> Self{
+        match v {
+            0=> Self::PeerClosed,
+            1=> Self::IoError,
+            2=> Self::Protocol,
+            3=> Self::MsgTooLarge,
+            4=> Self::QueueFull,
+            5=> Self::HighMarkTimeout,
+            6=> Self::NotConnected,
+            7=> Self::Kick,
+            8=> Self::ProxyShutdown,
+            9=> Self::Crypto,
+            10=> Self::Checksum,
+            _=> Self::Unknown,
+        }
+    }
+}
+
+impl From<&Error> for DisReason {
+    fn from(err: &Error) -> Self {
+        match err {
+            Error::PeerClosed => DisReason::PeerClosed,
+            Error::Io(..) => DisReason::IoError,
+            Error::Protocol(_) => DisReason::Protocol,
+            Error::MsgTooLarge(_) => DisReason::MsgTooLarge,
+            Error::QueueFull => DisReason::QueueFull,
+            Error::HighMarkTimeout(_) => DisReason::HighMarkTimeout,
+            Error::NotConnected => DisReason::NotConnected,
+            Error::Crypto(_) => DisReason::Crypto,
+            Error::Checksum(..) => DisReason::Checksum,
+            Error::Config(_) => DisReason::Unknown,
+        }
+    }
+}
+
+#[test]
+fn test_msg_buf_shared() {
+    let msg = MsgData::new_pid(100);
+    let msg = MsgData { buf: vec![1u8, 2, 3].into(), ..msg }.into_shared();
+    let clone = msg.clone();
+    match (&msg.buf, &clone.buf) {
+        (MsgBuf::Shared(a), MsgBuf::Shared(b)) => assert!(Arc::ptr_eq(a, b)),
+        _ => panic!("MsgData.into_shared buf not shared"),
+    }
+    assert_eq!(&clone.buf[..], &[1u8, 2, 3]);
+    drop(msg);
+    assert_eq!(clone.buf.into_vec(), vec![1u8, 2, 3]);
+}
+
+#[test]
+fn test_multicast_data() {
+    let msg = MsgData::new_multicast(1000, &[1, 2, u64::MAX], &[7u8, 8, 9]);
+    assert_eq!(msg.pid, SProtoId::Multicast as u16);
+    let data = MulticastData::decode(&msg.buf).unwrap();
+    assert_eq!(data.pid, 1000);
+    assert_eq!(data.vec_id, vec![1, 2, u64::MAX]);
+    assert_eq!(data.payload, &[7u8, 8, 9]);
+    assert!(MulticastData::decode(&msg.buf[..10]).is_err());
+}
+
+#[test]
+fn test_publish_group_data() {
+    let msg = MsgData::new_publish_group(1000, "room_1", &[7u8, 8, 9]);
+    assert_eq!(msg.pid, SProtoId::PublishGroup as u16);
+    let data = PublishGroupData::decode(&msg.buf).unwrap();
+    assert_eq!((data.pid, data.name, data.payload), (1000, "room_1", &[7u8, 8, 9][..]));
+    assert!(PublishGroupData::decode(&msg.buf[..3]).is_err());
+    assert!(PublishGroupData::decode(&msg.buf[..6]).is_err());
+
+    // 空的组名和包体
+    let buf = PublishGroupData::encode(1, "", &[]);
+    let data = PublishGroupData::decode(&buf).unwrap();
+    assert_eq!((data.pid, data.name, data.payload.len()), (1, "", 0));
+
+    let mut buf = PublishGroupData::encode(1, "ab", &[]);
+    buf[4] = 0xff;
+    assert!(PublishGroupData::decode(&buf).is_err());
+}
+
+#[test]
+fn test_dis_reason() {
+    let msg = MsgData::new_disconnect(9, DisReason::from(&Error::HighMarkTimeout(100)));
+    assert_eq!(msg.pid, SProtoId::Disconnect as u16);
+    assert_eq!(msg.get_dis_reason(), DisReason::HighMarkTimeout);
+    assert_eq!(MsgData::new_uid_pid(9, SProtoId::Disconnect as u16).get_dis_reason(), DisReason::Unknown);
+    assert_eq!(DisReason::new(DisReason::ProxyShutdown as u16), DisReason::ProxyShutdown);
+}
+
+#[test]
+fn test_msg_version() {
+    let mut msg = MsgData::new_pid(1000);
+    msg.ext = u32::MAX;
+    msg.set_version(5);
+    assert_eq!(msg.get_version(), 5);
+    assert_eq!(msg.ext | EXT_VERSION_MASK, u32::MAX);
+    msg.set_version(MAX_VERSION + 1);
+    assert_eq!(msg.get_version(), 0);
+
+    msg.set_tid(MAX_TID);
+    assert_eq!((msg.get_tid(), msg.get_version()), (MAX_TID, 0));
+    assert!(msg.is_reply() && msg.is_rpc_error());
+    msg.set_tid(0);
+    assert_eq!(msg.ext, EXT_REPLY | EXT_RPC_ERROR | EXT_COMPRESS | EXT_ENCRYPT);
+
+    // 序号回绕和中间丢失
+    for (seq, recv_seq) in [(1, 0), (1024, 1023), (1025, 1023), (3000, 2100), (7, 7)] {
+        msg.set_session_seq(seq);
+        assert_eq!(msg.get_session_seq(recv_seq), seq);
+    }
+}
@@ -0,0 +1,137 @@
+use crate::error::Error;
+use crate::os_socket;
+use std::net::{SocketAddr, TcpStream};
+use std::os::unix::io::{AsRawFd, RawFd};
+
+/// TCP 保活探测 单位:秒
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeepAlive {
+    /// 空闲多久后开始探测 TCP_KEEPIDLE
+    pub idle: u32,
+    /// 探测间隔 TCP_KEEPINTVL
+    pub interval: u32,
+    /// 探测失败多少次断开 TCP_KEEPCNT
+    pub count: u32,
+}
+
+/// 每个连接上的 socket 选项 listen 和 connect 共用
+/// 默认值都不设置 使用系统的值
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SocketOption {
+    /// default:None 不启用 SO_KEEPALIVE
+    pub keepalive: Option<KeepAlive>,
+
+    /// default:0 不设置
+    /// 单位:毫秒 发出的数据超过这个时长没有确认 断开连接 TCP_USER_TIMEOUT
+    pub user_timeout: u32,
+
+    /// default:None 不设置
+    /// 单位:秒 close 时等待未发送数据发完的时长 SO_LINGER
+    /// 只支持 Some(0): close 时直接发 RST
+    /// 非0时 close 会阻塞 epoll 线程直到数据发完或超时 check 返回错误
+    pub linger: Option<u16>,
+
+    /// default:0 不设置
+    /// IPv4 的 IP_TOS IPv6 的 IPV6_TCLASS
+    /// 高6位是 DSCP 低2位是 ECN 要为0 例如 EF(46) << 2 = 0xb8
+    pub tos: u8,
+}
+
+impl SocketOption {
+    /// 检查配置 错误信息带上选项名
+    pub fn check(&self) -> Result<(), Error> {
+        if let Some(keepalive) = self.keepalive {
+            // 系统的上限 见 tcp(7)
+            if keepalive.idle == 0 || keepalive.idle > 32767 {
+                return Err(Error::Config(format!("TCP_KEEPIDLE:{} not in 1..=32767", keepalive.idle)));
+            }
+            if keepalive.interval == 0 || keepalive.interval > 32767 {
+                return Err(Error::Config(format!("TCP_KEEPINTVL:{} not in 1..=32767", keepalive.interval)));
+            }
+            if keepalive.count == 0 || keepalive.count > 127 {
+                return Err(Error::Config(format!("TCP_KEEPCNT:{} not in 1..=127", keepalive.count)));
+            }
+        }
+        if self.user_timeout > i32::MAX as u32 {
+            return Err(Error::Config(format!("TCP_USER_TIMEOUT:{} too large", self.user_timeout)));
+        }
+        if let Some(linger) = self.linger.filter(|linger| *linger > 0) {
+            return Err(Error::Config(format!("SO_LINGER:{} blocking close only 0 is supported", linger)));
+        }
+        if self.tos & 0x3 != 0 {
+            return Err(Error::Config(format!("IP_TOS:{:#04x} ECN bits must be 0", self.tos)));
+        }
+        Ok(())
+    }
+
+    /// 设置到连接上 第一个失败的选项返回错误
+    pub fn apply(&self, socket: &TcpStream) -> Result<(), Error> {
+        let raw_fd = socket.as_raw_fd();
+        if let Some(keepalive) = self.keepalive {
+            setsockopt(raw_fd, libc::SOL_SOCKET, libc::SO_KEEPALIVE, 1, "SO_KEEPALIVE")?;
+            setsockopt(raw_fd, libc::SOL_TCP, libc::TCP_KEEPIDLE, keepalive.idle as i32, "TCP_KEEPIDLE")?;
+            setsockopt(raw_fd, libc::SOL_TCP, libc::TCP_KEEPINTVL, keepalive.interval as i32, "TCP_KEEPINTVL")?;
+            setsockopt(raw_fd, libc::SOL_TCP, libc::TCP_KEEPCNT, keepalive.count as i32, "TCP_KEEPCNT")?;
+        }
+        if self.user_timeout > 0 {
+            setsockopt(raw_fd, libc::SOL_TCP, libc::TCP_USER_TIMEOUT, self.user_timeout as i32, "TCP_USER_TIMEOUT")?;
+        }
+        if let Some(linger) = self.linger {
+            let val = libc::linger { l_onoff: 1, l_linger: linger as i32 };
+            setsockopt(raw_fd, libc::SOL_SOCKET, libc::SO_LINGER, val, "SO_LINGER")?;
+        }
+        if self.tos > 0 {
+            let tos = self.tos as i32;
+            match socket.local_addr() {
+                Ok(SocketAddr::V6(addr)) => {
+                    setsockopt(raw_fd, libc::IPPROTO_IPV6, libc::IPV6_TCLASS, tos, "IPV6_TCLASS")?;
+                    // 双栈监听收到的 IPv4 连接
+                    if addr.ip().to_ipv4_mapped().is_some() {
+                        setsockopt(raw_fd, libc::IPPROTO_IP, libc::IP_TOS, tos, "IP_TOS")?;
+                    }
+                }
+                Ok(SocketAddr::V4(_)) => {
+                    setsockopt(raw_fd, libc::IPPROTO_IP, libc::IP_TOS, tos, "IP_TOS")?;
+                }
+                Err(err) => return Err(Error::from(err).context("IP_TOS local_addr")),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// setsockopt 出错时 错误信息带上选项名
+#[inline]
+pub(crate) fn setsockopt<T>(fd: RawFd, opt: libc::c_int, key: libc::c_int, val: T, name: &str) -> Result<(), Error> {
+    os_socket::setsockopt(fd, opt, key, val).map_err(|err| err.context(&format!("setsockopt {}", name)))
+}
+
+#[test]
+fn test_socket_option() {
+    use std::net::TcpListener;
+
+    let mut option = SocketOption::default();
+    assert!(option.check().is_ok());
+    option.keepalive = Some(KeepAlive { idle: 0, interval: 5, count: 3 });
+    assert_eq!(option.check(), Err(Error::Config("TCP_KEEPIDLE:0 not in 1..=32767".into())));
+    option.keepalive = Some(KeepAlive { idle: 60, interval: 5, count: 3 });
+    option.tos = 0xb9;
+    assert!(option.check().is_err());
+    option.tos = 0xb8;
+    option.user_timeout = 10000;
+    option.linger = Some(2);
+    assert!(option.check().is_err());
+    option.linger = Some(0);
+    assert!(option.check().is_ok());
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let socket = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    option.apply(&socket).unwrap();
+    let raw_fd = socket.as_raw_fd();
+    assert_eq!(os_socket::getsockopt::<i32>(raw_fd, libc::SOL_SOCKET, libc::SO_KEEPALIVE).unwrap(), 1);
+    assert_eq!(os_socket::getsockopt::<i32>(raw_fd, libc::SOL_TCP, libc::TCP_KEEPIDLE).unwrap(), 60);
+    assert_eq!(os_socket::getsockopt::<i32>(raw_fd, libc::SOL_TCP, libc::TCP_USER_TIMEOUT).unwrap(), 10000);
+    assert_eq!(os_socket::getsockopt::<i32>(raw_fd, libc::IPPROTO_IP, libc::IP_TOS).unwrap(), 0xb8);
+    let linger = os_socket::getsockopt::<libc::linger>(raw_fd, libc::SOL_SOCKET, libc::SO_LINGER).unwrap();
+    assert_eq!((linger.l_onoff, linger.l_linger), (1, 0));
+}
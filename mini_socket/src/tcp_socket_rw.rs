@@ -1,22 +1,150 @@
+use crate::error::Error;
 use std::net::TcpStream;
 
 #[derive(PartialEq)]
 pub enum WriteResult {
     Finish,
     BufferFull,
-    Error(String),
+    Error(Error),
 }
 
 pub enum ReadResult<MSG> {
     Data(Vec<MSG>),
-    Error(Vec<MSG>, String),
+    /// 出错前已读到的消息 出错原因
+    Error(Vec<MSG>, Error),
+}
+
+/// 创建连接后传给 TcpSocketRw 的配置
+#[derive(Debug, Clone, Default)]
+pub struct RwConfig {
+    /// 0:不启用分片
+    /// 超过单帧上限的消息拆成分片发送 接收方重组
+    /// 重组后消息的最大字节数
+    pub chunk_max_size: usize,
+
+    /// 0:不压缩
+    /// 包体字节数不小于这个值时压缩
+    pub compress_threshold: usize,
+
+    /// false: 收到对方压缩过的消息后才压缩 旧的客户端不会收到压缩的消息
+    /// true: 直接压缩 第一条消息总是压缩 通知对方支持压缩 主动连接方使用
+    pub compress_first: bool,
+
+    /// 服务端的 X25519 私钥 设置后客户端必须先发 KeyExchange 所有消息加密
+    pub encrypt_secret: Option<[u8; 32]>,
+
+    /// 客户端配置的服务端 X25519 公钥 设置后连接时发 KeyExchange 所有消息加密
+    pub encrypt_server_key: Option<[u8; 32]>,
+
+    /// 包头后加4个字节 包头和包体的 CRC32C 两端要一致
+    pub checksum: bool,
+}
+
+/// 压缩的统计 只统计压缩了的消息
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CompressStats {
+    /// 发送 压缩前字节数
+    pub write_raw_bytes: u64,
+    /// 发送 压缩后字节数
+    pub write_bytes: u64,
+    /// 接收 解压后字节数
+    pub read_raw_bytes: u64,
+    /// 接收 解压前字节数
+    pub read_bytes: u64,
+}
+
+impl CompressStats {
+    /// 发送的压缩率 压缩后/压缩前
+    pub fn write_ratio(&self) -> f64 {
+        if self.write_raw_bytes == 0 {
+            return 1.0;
+        }
+        self.write_bytes as f64 / self.write_raw_bytes as f64
+    }
+
+    /// 接收的压缩率 解压前/解压后
+    pub fn read_ratio(&self) -> f64 {
+        if self.read_raw_bytes == 0 {
+            return 1.0;
+        }
+        self.read_bytes as f64 / self.read_raw_bytes as f64
+    }
 }
 
 pub trait TcpSocketRw<MSG> {
+    /// 创建连接后设置配置
+    fn set_config(&mut self, _config: &RwConfig) {}
+
     /// 把数据写到tcp buffer中
     fn write(&mut self, socket: &mut TcpStream, data: &mut MSG) -> WriteResult;
 
     /// 从tcp buffer中读取数据
     /// share_buffer: 共享缓冲区
     fn read(&mut self, socket: &mut TcpStream, share_buffer: &mut Vec<u8>) -> ReadResult<MSG>;
+
+    /// 支持 read_data 并且支持批量写时 io_uring 直接提交 recv writev
+    /// 不支持时 io_uring 只等待可读可写 用 read write 收发
+    fn support_read_data(&self) -> bool {
+        false
+    }
+
+    /// 解析已经收到的数据 io_uring 的 recv 完成后调用
+    /// 不完整的包留到下次
+    fn read_data(&mut self, _data: &[u8]) -> ReadResult<MSG> {
+        ReadResult::Error(vec![], Error::Config("read_data not implemented".into()))
+    }
+
+    /// 包头字节数
+    /// 大于0: TcpSocket 把队列里的多条消息编码成 iovec 用一次 writev 写入
+    /// 0: 不支持批量写 用 write 逐条写
+    fn head_size(&self) -> usize {
+        0
+    }
+
+    /// 批量写时填充消息的包头
+    /// head.len() == head_size()
+    /// 每条消息只会调用一次 可以在这里生成包id
+    fn encode_head(&mut self, _msg: &MSG, _head: &mut [u8]) -> Result<(), Error> {
+        Err(Error::Config("encode_head not implemented".into()))
+    }
+
+    /// 批量写时消息的包体数据
+    /// io_uring 写入期间消息在队列中会移动 包体数据不能存放在消息内部
+    fn body<'b>(&self, _msg: &'b MSG) -> &'b [u8] {
+        &[]
+    }
+
+    /// 连接建立后最先发送的消息 如密钥交换
+    fn hello_msg(&mut self) -> Option<MSG> {
+        None
+    }
+
+    /// 放入发送队列前压缩消息 在拆分片之前
+    /// 广播的 MsgBuf::Shared 每个连接各处理一次
+    fn prepare_msg(&mut self, msg: MSG) -> MSG {
+        msg
+    }
+
+    /// 帧(消息或分片)放入发送队列时加密
+    /// 调用顺序就是发送顺序 分片之间插入的消息也一样
+    /// 返回错误时丢弃这一帧 连接要断开
+    fn seal_frame(&mut self, msg: MSG) -> Result<MSG, Error> {
+        Ok(msg)
+    }
+
+    /// 压缩的统计
+    fn get_compress_stats(&self) -> CompressStats {
+        CompressStats::default()
+    }
+
+    /// 消息是否要拆成分片发送
+    fn need_chunk(&self, _msg: &MSG) -> bool {
+        false
+    }
+
+    /// 把消息拆成分片
+    /// TcpSocket 每次只把一个分片放入发送队列 分片之间可以插入其它消息
+    fn chunk_msg(&mut self, msg: MSG) -> Vec<MSG> {
+        vec![msg]
+    }
 }
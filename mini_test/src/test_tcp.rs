@@ -1,13 +1,17 @@
 use log::{error, info, warn};
 
+use mini_socket::rpc::{self, Rpc, RpcError};
 use mini_socket::tcp_socket::TcpSocket;
 use mini_socket::tcp_socket_rw::ReadResult;
 use mini_socket::tcp_socket_rw::WriteResult;
 use mini_socket::tcp_socket_msg::MsgData;
 use mini_utils::bytes;
+use mini_utils::wtimer::WTimer;
 
+use std::cell::Cell;
 use std::net::Shutdown;
 use std::net::TcpStream;
+use std::rc::Rc;
 use std::thread;
 use std::time::Duration;
 
@@ -27,6 +31,7 @@ pub fn test() {
     for _ in 0..1{
         thread_pool.push(new_client().unwrap());
     }
+    let _rpc_thread = rpc_client();
 
     loop {
         thread::sleep(Duration::from_secs(60));
@@ -51,7 +56,10 @@ fn loop_write(socket: TcpStream) -> thread::JoinHandle<()> {
                 ext_data = 0;
             }
             let msg_data = encode(ext_data);
-            client.push_vec_queue(msg_data);
+            if let Err(err) = client.push_vec_queue(msg_data) {
+                error!("push_vec_queue err:{}", err);
+                break;
+            }
             msg_num += 1;
             if msg_num % 100 == 0 {
                 info!("write data:{} {:?}", msg_num, thread::current().id());
@@ -75,7 +83,7 @@ fn encode(ext: u32) -> MsgData {
         uid: 0,
         pid: 258,
         ext: ext,
-        buf: buf
+        buf: buf.into()
     }
 }
 
@@ -163,3 +171,75 @@ fn new_client() -> Result<(thread::JoinHandle<()>, thread::JoinHandle<()>), Stri
         }
     }
 }
+
+/// rpc 请求和回复
+/// 没有服务处理的协议 proxy 原样返回 收到自己的请求后回复 回复再经 proxy 返回
+fn rpc_client() -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let socket = match TcpStream::connect("0.0.0.0:9999") {
+            Ok(socket) => socket,
+            Err(err) => {
+                error!("rpc connect error:{}", err);
+                return;
+            }
+        };
+        let mut client = TcpSocket::new(socket, Box::new(WanTcpRw::default()));
+        let mut share_buffer = vec![0u8; 1024 * 1024];
+        let mut wtimer = WTimer::new(1);
+        let rpc = Rpc::new();
+        // (成功, 错误回复, 超时)
+        let count: Rc<Cell<(u64, u64, u64)>> = Rc::default();
+        let mut req_num: u64 = 0;
+        loop {
+            if rpc.pending_num() < 100 {
+                req_num += 1;
+                let count = count.clone();
+                let mut msg = MsgData::new_pid(259);
+                msg.buf = req_num.to_le_bytes().to_vec().into();
+                let req = rpc.request(&mut wtimer, msg, 3000, move |result| {
+                    let (ok, err, timeout) = count.get();
+                    match result {
+                        Ok(_) => count.set((ok + 1, err, timeout)),
+                        Err(RpcError::Remote(..)) => count.set((ok, err + 1, timeout)),
+                        Err(_) => count.set((ok, err, timeout + 1)),
+                    }
+                });
+                if let Err(err) = client.push_vec_queue(req) {
+                    error!("rpc push_vec_queue err:{}", err);
+                    break;
+                }
+            }
+            match client.read(&mut share_buffer) {
+                ReadResult::Data(vec_msg) => {
+                    for msg in vec_msg {
+                        if let Some(req) = rpc.on_msg(msg) {
+                            // 单号成功 双号失败 序号小端 第1个字节是低位
+                            let reply = if req.buf.first().is_some_and(|low| low % 2 == 1) {
+                                rpc::reply(&req, vec![])
+                            } else {
+                                rpc::reply_error(&req, 1, "even")
+                            };
+                            if let Err(err) = client.push_vec_queue(reply) {
+                                error!("rpc push_vec_queue err:{}", err);
+                                return;
+                            }
+                        }
+                    }
+                }
+                ReadResult::Error(_, err) => {
+                    error!("rpc read error:{}", err);
+                    break;
+                }
+            }
+            if !write(&mut client) {
+                break;
+            }
+            wtimer.scheduled(time::timestamp());
+            if req_num.is_multiple_of(10000) {
+                info!("rpc req:{} (ok, err, timeout):{:?}", req_num, count.get());
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        rpc.cancel_all();
+    })
+}
@@ -1,6 +1,6 @@
 use crate::time;
 use std::collections::VecDeque;
-use std::mem::{self, MaybeUninit};
+use std::mem;
 
 //第1个轮子占用8位
 const TVR_BITS: u64 = 8;
@@ -27,16 +27,20 @@ macro_rules! tv_idx {
 
 macro_rules! cascade {
     ($wheel:expr, $tv:ident, $idx:expr) => {{
-        let mut deque = mem::replace(&mut $wheel.$tv[$idx], VecDeque::new());
+        let idx: usize = $idx;
+        let mut deque = mem::replace(&mut $wheel.$tv[idx], VecDeque::new());
         while let Some(entity) = deque.pop_front() {
             push_entity($wheel, entity);
         }
-        $idx > 0
+        // 槽号不为0 上层轮子还没转完一圈
+        idx != 0
     }};
 }
 
 pub struct WTimer {
     wheel: Wheel,
+    /// 还没有完成的任务数
+    task_num: usize,
 }
 pub trait IWTask {
     /// return true:任务完成
@@ -70,28 +74,40 @@ impl WTimer {
         let tick_size: u64 = if tick_size < 1 { 1 } else { tick_size as u64 };
         let cur_tick = time::timestamp() / tick_size;
 
-        let mut wheel = Wheel {
+        let wheel = Wheel {
             cur_tick,
             tick_size,
-            tv1: unsafe { MaybeUninit::uninit().assume_init() },
-            tv2: unsafe { MaybeUninit::uninit().assume_init() },
-            tv3: unsafe { MaybeUninit::uninit().assume_init() },
-            tv4: unsafe { MaybeUninit::uninit().assume_init() },
-            tv5: unsafe { MaybeUninit::uninit().assume_init() },
+            tv1: std::array::from_fn(|_| VecDeque::new()),
+            tv2: std::array::from_fn(|_| VecDeque::new()),
+            tv3: std::array::from_fn(|_| VecDeque::new()),
+            tv4: std::array::from_fn(|_| VecDeque::new()),
+            tv5: std::array::from_fn(|_| VecDeque::new()),
         };
 
-        for i in 0..TVR_SIZE as usize {
-            wheel.tv1[i] = VecDeque::new();
-        }
+        WTimer { wheel, task_num: 0 }
+    }
 
-        for i in 0..TVN_SIZE as usize {
-            wheel.tv2[i] = VecDeque::new();
-            wheel.tv3[i] = VecDeque::new();
-            wheel.tv4[i] = VecDeque::new();
-            wheel.tv5[i] = VecDeque::new();
-        }
+    /// 还没有完成的任务数
+    #[inline]
+    pub fn task_num(&self) -> usize {
+        self.task_num
+    }
 
-        WTimer { wheel }
+    /// 下次要调用 scheduled 的时间戳
+    /// 最近的任务在第1个轮子外时 返回第1个轮子转完一圈的时间 用于把任务移到第1个轮子
+    /// None: 没有任务
+    pub fn next_expire(&self) -> Option<u64> {
+        if self.task_num == 0 {
+            return None;
+        }
+        let wheel = &self.wheel;
+        for tick in wheel.cur_tick..wheel.cur_tick + TVR_SIZE {
+            if !wheel.tv1[(tick & TVR_MSAK) as usize].is_empty() {
+                return Some((tick + 1) * wheel.tick_size);
+            }
+        }
+        let next_tick = (wheel.cur_tick | TVR_MSAK) + 1;
+        Some((next_tick + 1) * wheel.tick_size)
     }
     pub fn scheduled(&mut self, timestamp: u64) {
         let wheel = &mut self.wheel;
@@ -99,12 +115,14 @@ impl WTimer {
         let cur_tick = timestamp / wheel.tick_size;
         while w_tick < cur_tick {
             let idx = w_tick & TVR_MSAK;
-            if idx != 0
+            // 第1个轮子转完一圈 把上层轮子的任务移下来
+            if idx == 0
                 && !cascade!(wheel, tv2, tv_idx!(w_tick, 0))
                 && !cascade!(wheel, tv3, tv_idx!(w_tick, 1))
                 && !cascade!(wheel, tv4, tv_idx!(w_tick, 2))
             {
-                cascade!(wheel, tv5, tv_idx!(w_tick, 3));
+                // 最上层轮子 不用再看返回值
+                let _ = cascade!(wheel, tv5, tv_idx!(w_tick, 3));
             }
 
             w_tick += 1;
@@ -112,6 +130,7 @@ impl WTimer {
             let mut deque = mem::replace(&mut wheel.tv1[idx as usize], VecDeque::new());
             while let Some(mut entity) = deque.pop_front() {
                 if entity.task.execute() {
+                    self.task_num -= 1;
                     continue;
                 }
                 entity.expire = timestamp + entity.interval;
@@ -132,6 +151,7 @@ impl WTimer {
             }
         };
 
+        self.task_num += 1;
         push_entity(
             &mut self.wheel,
             TEntity {
@@ -144,8 +164,9 @@ impl WTimer {
 }
 
 fn push_entity(wheel: &mut Wheel, entity: TEntity) {
-    let mut ticks = entity.expire / wheel.tick_size;
-    let mut idx = (ticks - wheel.cur_tick) as u64;
+    // 已经过期的任务 放到下一个要执行的槽
+    let mut ticks = std::cmp::max(entity.expire / wheel.tick_size, wheel.cur_tick);
+    let mut idx = ticks - wheel.cur_tick;
 
     let entity_deque: &mut VecDeque<TEntity>;
     if idx < TVR_SIZE {
@@ -198,8 +219,50 @@ impl IWTask for TestIWTask {
 #[cfg(test)]
 mod test {
     use crate::time;
+    use crate::wtimer::IWTask;
     use crate::wtimer::TestIWTask;
     use crate::wtimer::WTimer;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    /// 执行一次就完成 记录执行时间
+    struct OnceTask(Rc<Cell<u64>>);
+
+    impl IWTask for OnceTask {
+        fn execute(&mut self) -> bool {
+            self.0.set(time::timestamp());
+            true
+        }
+    }
+
+    #[test]
+    fn test_next_expire() {
+        let mut wtimer = WTimer::new(1);
+        assert_eq!(wtimer.next_expire(), None);
+
+        let ts = time::timestamp();
+        let run_ts = Rc::new(Cell::new(0));
+        wtimer.push_task(5, 0, Box::new(OnceTask(run_ts.clone())));
+        let expire = wtimer.next_expire().unwrap();
+        assert!(expire > ts + 5 && expire <= time::timestamp() + 6);
+        wtimer.scheduled(expire - 1);
+        assert_eq!(run_ts.get(), 0);
+        wtimer.scheduled(expire);
+        assert!(run_ts.get() > 0);
+        assert_eq!(wtimer.next_expire(), None);
+
+        // 不在第1个轮子的任务 第1个轮子每转一圈唤醒一次
+        wtimer.push_task(1000, 0, Box::new(OnceTask(run_ts.clone())));
+        run_ts.set(0);
+        let mut num = 0;
+        while let Some(expire) = wtimer.next_expire() {
+            wtimer.scheduled(expire);
+            num += 1;
+        }
+        assert!(run_ts.get() > 0);
+        assert!(num <= 1000 / 256 + 2);
+        assert_eq!(wtimer.task_num(), 0);
+    }
 
     #[test]
     fn test_timer() {